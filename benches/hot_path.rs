@@ -0,0 +1,115 @@
+// Per-request cost of serving /api/ping and /style.css from public/, without sockets: the request
+// is parsed, run through WebsiteHandler and its default middleware, and serialized the way the
+// server sends it. Run with `cargo bench --bench hot_path`; allocation counts per request are
+// printed first, and tests/allocations.rs keeps them under a ceiling.
+// Static content types and header names plus a format!-free security block took serializing these
+// responses from 27 (/api/ping) and 29 (/style.css) allocations down to 15 and 17; rendering the
+// security block once per server and writing the head into a single pre-sized buffer brought
+// that to 5 and 6.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use rust_server::http::response::RenderedSecurityHeaders;
+use rust_server::http::{Request, SecurityHeaders, SendOptions};
+use rust_server::middleware::MiddlewareStack;
+use rust_server::security::SecurityConfig;
+use rust_server::server::Handler;
+use rust_server::website_handler::WebsiteHandler;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const PING_REQUEST: &[u8] = b"GET /api/ping HTTP/1.1\r\nHost: localhost:8080\r\nUser-Agent: bench\r\nAccept: */*\r\n\r\n";
+const STATIC_REQUEST: &[u8] = b"GET /style.css HTTP/1.1\r\nHost: localhost:8080\r\nUser-Agent: bench\r\nAccept: text/css\r\n\r\n";
+const CLIENT: &str = "127.0.0.1:50000";
+
+struct Bench {
+    runtime: tokio::runtime::Runtime,
+    handler: MiddlewareStack<WebsiteHandler>,
+    security_headers: Arc<RenderedSecurityHeaders>,
+    client: SocketAddr,
+    sink: Vec<u8>,
+}
+
+impl Bench {
+    fn new() -> Self {
+        let public_path = std::fs::canonicalize(concat!(env!("CARGO_MANIFEST_DIR"), "/public")).unwrap();
+        // The limiter still runs for every request; the short window keeps one client under it
+        let config = SecurityConfig {
+            rate_limit_requests: usize::MAX,
+            rate_limit_window: Duration::from_millis(1),
+            ..SecurityConfig::default()
+        };
+        Self {
+            runtime: tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap(),
+            handler: WebsiteHandler::new(public_path, config).with_default_middleware(),
+            security_headers: Arc::new(RenderedSecurityHeaders::new(&SecurityHeaders::default())),
+            client: CLIENT.parse().unwrap(),
+            sink: Vec::with_capacity(4096),
+        }
+    }
+
+    // What the connection loop does for one request, with a Vec standing in for the socket
+    fn serve(&mut self, raw: &[u8]) {
+        let request = Request::try_from(raw).unwrap();
+        let options = SendOptions::new(request.version(), request.keep_alive(), false);
+        self.sink.clear();
+        self.runtime.block_on(async {
+            let response = self.handler.handle_request(&request, self.client).await
+                .with_request_id("00000000-000000000000")
+                .with_default_security_headers(&self.security_headers);
+            response.send(&mut self.sink, options).await
+        }).unwrap();
+    }
+}
+
+fn allocations_per_call(iterations: usize, mut f: impl FnMut()) -> f64 {
+    f();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..iterations {
+        f();
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / iterations as f64
+}
+
+fn hot_path(c: &mut Criterion) {
+    let mut bench = Bench::new();
+
+    let ping = allocations_per_call(1000, || bench.serve(PING_REQUEST));
+    let css = allocations_per_call(1000, || bench.serve(STATIC_REQUEST));
+    println!("allocations per request: /api/ping {:.1}, /style.css {:.1}", ping, css);
+
+    let mut group = c.benchmark_group("hot_path");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("api_ping", |b| b.iter(|| bench.serve(PING_REQUEST)));
+    group.bench_function("static_css", |b| b.iter(|| bench.serve(STATIC_REQUEST)));
+    group.finish();
+}
+
+criterion_group!(benches, hot_path);
+criterion_main!(benches);
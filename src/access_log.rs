@@ -0,0 +1,183 @@
+use crate::http::{Request, StatusCode};
+use chrono::{SecondsFormat, Utc};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// Text keeps the human-readable lines; Json writes one object per request for log shippers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown log format: {} (expected text or json)", s)),
+        }
+    }
+}
+
+// Which successful requests get an access record. 4xx and 5xx responses (rate-limit denials
+// included) are always logged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogSampling {
+    #[default]
+    All,
+    // Every Nth successful request
+    OneIn(u64),
+    ErrorsOnly,
+}
+
+impl FromStr for LogSampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "all" | "1" => Ok(Self::All),
+            "errors" => Ok(Self::ErrorsOnly),
+            n => match n.parse::<u64>() {
+                Ok(n) if n > 0 => Ok(Self::OneIn(n)),
+                _ => Err(format!("Unknown log sampling: {} (expected all, errors or a positive N for 1 in N)", s)),
+            },
+        }
+    }
+}
+
+pub struct LogSampler {
+    sampling: LogSampling,
+    successes: AtomicU64,
+}
+
+impl LogSampler {
+    pub fn new(sampling: LogSampling) -> Self {
+        Self { sampling, successes: AtomicU64::new(0) }
+    }
+
+    pub fn should_log(&self, status: StatusCode) -> bool {
+        if status as u16 >= 400 {
+            return true;
+        }
+        match self.sampling {
+            LogSampling::All => true,
+            LogSampling::ErrorsOnly => false,
+            LogSampling::OneIn(n) => self.successes.fetch_add(1, Ordering::Relaxed).is_multiple_of(n),
+        }
+    }
+}
+
+// The access record for a finished request, or None when sampling drops it. `detail` closes the
+// text line: the bytes consumed on HTTP/1, the protocol on HTTP/2.
+pub fn access_line(
+    format: LogFormat,
+    sampler: &LogSampler,
+    request: &Request,
+    status: StatusCode,
+    latency: Duration,
+    client_ip: SocketAddr,
+    detail: &str,
+) -> Option<String> {
+    if !sampler.should_log(status) {
+        return None;
+    }
+    Some(match format {
+        LogFormat::Text => format!(" [{}] {} {} {} {} ({})", request.request_id(), client_ip, request.method_str(), request.path(), status, detail),
+        LogFormat::Json => json_line(request, status, latency, client_ip),
+    })
+}
+
+// serde_json does the escaping, so control characters and quotes in paths or user agents
+// cannot break the line apart
+pub fn json_line(request: &Request, status: StatusCode, latency: Duration, client_ip: SocketAddr) -> String {
+    let status_code = status as u16;
+    let level = match status_code {
+        500.. => "error",
+        400..=499 => "warn",
+        _ => "info",
+    };
+    json!({
+        "ts": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        "level": level,
+        "request_id": request.request_id(),
+        "method": request.method_str(),
+        "path": request.path(),
+        "status": status_code,
+        "latency_ms": latency.as_micros() as f64 / 1000.0,
+        "client_ip": client_ip.ip().to_canonical().to_string(),
+        "user_agent": request.header("User-Agent"),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Method;
+
+    #[test]
+    fn json_lines_parse_with_escaped_fields() {
+        let client: SocketAddr = "[::ffff:192.0.2.7]:5000".parse().unwrap();
+        let headers = [("Host", "localhost"), ("User-Agent", "probe \"quoted\"\t\u{1}")];
+        let request = Request::for_test(Method::GET, "/a\"b\\c", &headers, b"", client);
+        let line = json_line(&request, StatusCode::NotFound, Duration::from_micros(1500), client);
+
+        assert!(!line.contains('\n'));
+        let entry: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(entry["level"], "warn");
+        assert_eq!(entry["method"], "GET");
+        assert_eq!(entry["path"], "/a\"b\\c");
+        assert_eq!(entry["status"], 404);
+        assert_eq!(entry["latency_ms"], 1.5);
+        assert_eq!(entry["client_ip"], "192.0.2.7");
+        assert_eq!(entry["user_agent"], "probe \"quoted\"\t\u{1}");
+        assert!(entry["ts"].as_str().is_some_and(|ts| ts.ends_with('Z')));
+    }
+
+    #[test]
+    fn ipv6_clients_are_logged_by_address() {
+        let client: SocketAddr = "[2001:db8::7]:5000".parse().unwrap();
+        let request = Request::for_test(Method::GET, "/", &[("Host", "[2001:db8::1]:8080")], b"", client);
+        let entry: serde_json::Value = serde_json::from_str(&json_line(&request, StatusCode::Ok, Duration::ZERO, client)).unwrap();
+        assert_eq!(entry["client_ip"], "2001:db8::7");
+        assert_eq!(entry["level"], "info");
+    }
+
+    #[test]
+    fn one_in_ten_samples_successes_but_keeps_every_error() {
+        let sampler = LogSampler::new("10".parse().unwrap());
+        let logged = (0..100).filter(|_| sampler.should_log(StatusCode::Ok)).count();
+        assert_eq!(logged, 10);
+        for status in [StatusCode::NotFound, StatusCode::TooManyRequests, StatusCode::InternalServerError] {
+            assert!((0..20).all(|_| sampler.should_log(status)), "{:?}", status);
+        }
+
+        let errors_only = LogSampler::new("errors".parse().unwrap());
+        assert!(!errors_only.should_log(StatusCode::Ok));
+        assert!(errors_only.should_log(StatusCode::BadRequest));
+        assert!("0".parse::<LogSampling>().is_err());
+    }
+
+    #[test]
+    fn sampled_out_successes_write_nothing() {
+        let client: SocketAddr = "192.0.2.7:5000".parse().unwrap();
+        let mut request = Request::for_test(Method::GET, "/page", &[("Host", "localhost")], b"", client);
+        request.set_request_id("abc".to_string());
+        let line = |format, sampler: &LogSampler, status| access_line(format, sampler, &request, status, Duration::ZERO, client, "42");
+
+        let errors_only = LogSampler::new(LogSampling::ErrorsOnly);
+        for format in [LogFormat::Text, LogFormat::Json] {
+            assert_eq!(line(format, &errors_only, StatusCode::Ok), None);
+            assert!(line(format, &errors_only, StatusCode::TooManyRequests).is_some());
+        }
+        let all = LogSampler::new(LogSampling::All);
+        assert_eq!(line(LogFormat::Text, &all, StatusCode::Ok).as_deref(), Some(" [abc] 192.0.2.7:5000 GET /page 200 (42)"));
+    }
+}
@@ -0,0 +1,39 @@
+// public/ compiled into the binary for single-file deployments (the `embedded` feature). Lookups
+// take paths relative to the public root without a leading slash.
+use include_dir::{include_dir, Dir};
+
+static ASSETS: Dir<'static> = include_dir!("$CARGO_MANIFEST_DIR/public");
+
+pub fn file(relative_path: &str) -> Option<&'static [u8]> {
+    ASSETS.get_file(relative_path).map(|file| file.contents())
+}
+
+// The root counts as a directory, so `/` reaches the default documents
+pub fn is_dir(relative_path: &str) -> bool {
+    relative_path.is_empty() || ASSETS.get_dir(relative_path).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::{Method, Request, StatusCode};
+    use crate::security::SecurityConfig;
+    use crate::server::Handler;
+    use crate::website_handler::WebsiteHandler;
+
+    #[tokio::test]
+    async fn default_documents_are_served_without_a_public_directory() {
+        // Nothing exists at this path, so every byte served has to come from the binary
+        let handler = WebsiteHandler::new("/nonexistent/public".into(), SecurityConfig::default());
+        let client = "127.0.0.1:40000".parse().unwrap();
+
+        for (path, name) in [("/", "index.html"), ("/style.css", "style.css")] {
+            let request = Request::for_test(Method::GET, path, &[("Host", "localhost:8080")], b"", client);
+            let response = handler.handle_request(&request, client).await;
+            assert_eq!(response.status_code(), StatusCode::Ok, "{}", path);
+            assert_eq!(response.body(), super::file(name).unwrap(), "{}", path);
+        }
+
+        let request = Request::for_test(Method::GET, "/missing.html", &[("Host", "localhost:8080")], b"", client);
+        assert_eq!(handler.handle_request(&request, client).await.status_code(), StatusCode::NotFound);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::http::{ParseError, Response, StatusCode};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io;
+
+// Everything that can stop a request from being served normally. Each maps to one status and a
+// message that is safe to show clients; the underlying detail only goes to the logs.
+#[derive(Debug)]
+pub enum ServerError {
+    Parse(ParseError),
+    Io(io::Error),
+    NotFound,
+    Forbidden,
+    TooLarge,
+    UriTooLong,
+    // The client stopped sending partway through a request
+    Timeout,
+    RateLimited,
+    Overloaded,
+    SecurityViolation,
+    Internal,
+}
+
+impl ServerError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Parse(e) => e.status_code(),
+            Self::Io(_) | Self::Internal => StatusCode::InternalServerError,
+            Self::NotFound => StatusCode::NotFound,
+            Self::Forbidden => StatusCode::Forbidden,
+            Self::TooLarge => StatusCode::PayloadTooLarge,
+            Self::UriTooLong => StatusCode::UriTooLong,
+            Self::Timeout => StatusCode::RequestTimeout,
+            Self::RateLimited => StatusCode::TooManyRequests,
+            Self::Overloaded => StatusCode::ServiceUnavailable,
+            Self::SecurityViolation => StatusCode::BadRequest,
+        }
+    }
+
+    pub fn safe_message(&self) -> &'static str {
+        match self {
+            Self::Parse(e) => match e.status_code() {
+                StatusCode::BadRequest => "Invalid request format",
+                status => status.reason_phrase(),
+            },
+            Self::Io(_) | Self::Internal => "Internal server error",
+            Self::NotFound => "The requested resource was not found.",
+            Self::Forbidden => "Access to this resource is forbidden.",
+            Self::TooLarge => "Request body too large",
+            Self::UriTooLong => "Request URI too long",
+            Self::Timeout => "Request timeout",
+            Self::RateLimited => "Rate limit exceeded. Please try again later.",
+            Self::Overloaded => "The server is busy. Please try again shortly.",
+            Self::SecurityViolation => "Security violation: Request blocked for security reasons",
+        }
+    }
+
+    // API clients get the same envelope as successful API responses; everything else gets plain text.
+    // Server faults are never described beyond the generic message; the body gains the request id
+    // when the response is sent.
+    pub fn to_response(&self, path: Option<&str>) -> Response {
+        let status = self.status_code();
+        let response = if path.is_some_and(|path| path.starts_with("/api/")) {
+            let body = format!(r#"{{"success": false, "data": null, "message": "{}"}}"#, self.safe_message());
+            Response::with_content_type(status, Some(body), "application/json; charset=utf-8")
+        } else {
+            Response::new(status, Some(self.safe_message().to_string()))
+        };
+        match self {
+            Self::Io(_) | Self::Internal => response.with_error_reference(self.safe_message()),
+            _ => response,
+        }
+    }
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Parse(e) => write!(f, "{}", e),
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            _ => write!(f, "{}", self.safe_message()),
+        }
+    }
+}
+
+impl From<ParseError> for ServerError {
+    fn from(e: ParseError) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_paths_get_the_json_envelope() {
+        let response = ServerError::NotFound.to_response(Some("/api/missing"));
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["success"], false);
+        assert_eq!(body["message"], "The requested resource was not found.");
+
+        let response = ServerError::NotFound.to_response(Some("/missing.html"));
+        assert_eq!(response.body(), b"The requested resource was not found.");
+    }
+
+    #[test]
+    fn timeouts_are_408_with_a_generic_message() {
+        assert_eq!(ServerError::Timeout.status_code(), StatusCode::RequestTimeout);
+        assert_eq!(ServerError::Timeout.to_response(None).body(), b"Request timeout");
+    }
+
+    #[test]
+    fn io_errors_do_not_leak_their_detail() {
+        let error = ServerError::Io(io::Error::other("disk on fire at /srv/secret"));
+        let response = error.to_response(Some("/page.html"));
+        assert_eq!(response.status_code(), StatusCode::InternalServerError);
+        assert_eq!(response.body(), b"Internal server error");
+    }
+
+    #[test]
+    fn io_errors_quote_the_request_id_but_not_the_cause() {
+        let error = ServerError::Io(io::Error::other("disk on fire at /srv/secret"));
+        let response = error.to_response(Some("/page.html")).with_request_id("req-7");
+        assert_eq!(response.body(), b"Internal server error (request id req-7)");
+        assert_eq!(response.header("X-Request-Id"), Some("req-7"));
+
+        // Client errors have nothing to correlate, so their bodies are left alone
+        let response = ServerError::NotFound.to_response(Some("/missing.html")).with_request_id("req-8");
+        assert_eq!(response.body(), b"The requested resource was not found.");
+    }
+}
@@ -0,0 +1,131 @@
+use super::ParseError;
+
+pub enum ChunkedStatus {
+    // The terminating chunk and trailers were seen; `consumed` is the encoded length
+    Complete { consumed: usize },
+    // More bytes are needed; `decoded_len` counts the data bytes received so far
+    Incomplete { decoded_len: usize },
+}
+
+// Walks a chunked body, appending the decoded data to `sink` when given. Passing `None` only
+// scans, which lets the reader check completeness and size without copying. A chunk that would take
+// the decoded body past `max_len` is rejected from its size line alone, before any of it is read.
+pub fn decode(buf: &[u8], mut sink: Option<&mut Vec<u8>>, max_len: usize) -> Result<ChunkedStatus, ParseError> {
+    let mut pos = 0;
+    let mut decoded_len = 0;
+
+    loop {
+        let line_end = match find_crlf(&buf[pos..]) {
+            Some(i) => pos + i,
+            None => return Ok(ChunkedStatus::Incomplete { decoded_len }),
+        };
+        let size = parse_chunk_size(&buf[pos..line_end])?;
+        if size > max_len - decoded_len {
+            return Err(ParseError::BodyTooLarge);
+        }
+        pos = line_end + 2;
+
+        if size == 0 {
+            // Trailer fields follow the last chunk and end with an empty line
+            loop {
+                let trailer_end = match find_crlf(&buf[pos..]) {
+                    Some(i) => pos + i,
+                    None => return Ok(ChunkedStatus::Incomplete { decoded_len }),
+                };
+                let is_last = trailer_end == pos;
+                pos = trailer_end + 2;
+                if is_last {
+                    return Ok(ChunkedStatus::Complete { consumed: pos });
+                }
+            }
+        }
+
+        // Sizes are bounded by max_len, but a caller may pass usize::MAX
+        let chunk_end = pos.checked_add(size).ok_or(ParseError::InvalidChunkedBody)?;
+        let available = buf.len() - pos;
+        if available < size.checked_add(2).ok_or(ParseError::InvalidChunkedBody)? {
+            decoded_len += available.min(size);
+            return Ok(ChunkedStatus::Incomplete { decoded_len });
+        }
+
+        if let Some(sink) = sink.as_deref_mut() {
+            sink.extend_from_slice(&buf[pos..chunk_end]);
+        }
+        decoded_len += size;
+        pos = chunk_end;
+
+        if &buf[pos..pos + 2] != b"\r\n" {
+            return Err(ParseError::InvalidChunkedBody);
+        }
+        pos += 2;
+    }
+}
+
+fn parse_chunk_size(line: &[u8]) -> Result<usize, ParseError> {
+    // Chunk extensions after ';' carry nothing we use
+    let digits = match line.iter().position(|&b| b == b';') {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    let digits = std::str::from_utf8(digits).map_err(|_| ParseError::InvalidChunkedBody)?.trim_end();
+
+    if digits.is_empty() || digits.len() > 16 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ParseError::InvalidChunkedBody);
+    }
+    usize::from_str_radix(digits, 16).map_err(|_| ParseError::InvalidChunkedBody)
+}
+
+fn find_crlf(buf: &[u8]) -> Option<usize> {
+    buf.windows(2).position(|window| window == b"\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(encoded: &[u8], max_len: usize) -> Result<Vec<u8>, ParseError> {
+        let mut body = Vec::new();
+        match decode(encoded, Some(&mut body), max_len)? {
+            ChunkedStatus::Complete { consumed } => {
+                assert_eq!(consumed, encoded.len());
+                Ok(body)
+            },
+            ChunkedStatus::Incomplete { .. } => panic!("body reported incomplete"),
+        }
+    }
+
+    #[test]
+    fn decodes_chunks_extensions_and_trailers() {
+        let body = decode_all(b"4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\nExpires: never\r\n\r\n", usize::MAX).unwrap();
+        assert_eq!(body, b"Wikipedia");
+    }
+
+    #[test]
+    fn reports_partial_bodies_as_incomplete() {
+        match decode(b"4\r\nWi", None, usize::MAX).unwrap() {
+            ChunkedStatus::Incomplete { decoded_len } => assert_eq!(decoded_len, 2),
+            ChunkedStatus::Complete { .. } => panic!("partial body reported complete"),
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_size_lines_and_missing_crlf() {
+        for encoded in [&b"x\r\nabc\r\n0\r\n\r\n"[..], b"\r\n0\r\n\r\n", b"3\r\nabcX\r\n0\r\n\r\n", b"11111111111111111\r\n"] {
+            assert!(matches!(decode(encoded, None, usize::MAX), Err(ParseError::InvalidChunkedBody)));
+        }
+    }
+
+    #[test]
+    fn maximal_chunk_size_does_not_overflow() {
+        let encoded = b"FFFFFFFFFFFFFFFF\r\nabc\r\n0\r\n\r\n";
+        assert!(matches!(decode(encoded, None, usize::MAX), Err(ParseError::InvalidChunkedBody)));
+        assert!(matches!(decode(encoded, None, 1024), Err(ParseError::BodyTooLarge)));
+    }
+
+    #[test]
+    fn limit_covers_the_whole_decoded_body() {
+        assert_eq!(decode_all(b"4\r\nWiki\r\n0\r\n\r\n", 4).unwrap(), b"Wiki");
+        let encoded = b"4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n";
+        assert!(matches!(decode(encoded, None, 8), Err(ParseError::BodyTooLarge)));
+    }
+}
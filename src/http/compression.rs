@@ -0,0 +1,153 @@
+use std::io::{Result as IoResult, Write};
+use super::negotiation::negotiate_encoding;
+
+// Below this size the encoding overhead outweighs the savings
+const MIN_COMPRESS_BYTES: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Identity,
+    Gzip,
+    #[cfg(feature = "brotli")]
+    Brotli,
+}
+
+// On gzip's 1-9 scale: 1 is fastest, 9 compresses best. Out-of-range levels are clamped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompressionLevel(u32);
+
+impl CompressionLevel {
+    pub const MIN: u32 = 1;
+    pub const MAX: u32 = 9;
+
+    pub fn new(level: u32) -> Self {
+        Self(level.clamp(Self::MIN, Self::MAX))
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0
+    }
+
+    // Brotli's quality scale runs 0-11; one below the gzip level keeps the balanced default at 5
+    #[cfg(feature = "brotli")]
+    fn brotli_quality(&self) -> u32 {
+        self.0 - 1
+    }
+}
+
+// A balanced trade of CPU for ratio
+impl Default for CompressionLevel {
+    fn default() -> Self {
+        Self(6)
+    }
+}
+
+// Server preference order, used to break ties between equal q-values
+const SUPPORTED: &[ContentEncoding] = &[
+    #[cfg(feature = "brotli")]
+    ContentEncoding::Brotli,
+    ContentEncoding::Gzip,
+];
+
+impl ContentEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Identity => "identity",
+            Self::Gzip => "gzip",
+            #[cfg(feature = "brotli")]
+            Self::Brotli => "br",
+        }
+    }
+
+    pub fn negotiate(accept_encoding: Option<&str>) -> Self {
+        let available: Vec<&str> = SUPPORTED.iter().map(ContentEncoding::as_str).collect();
+        negotiate_encoding(accept_encoding, &available)
+            .and_then(|coding| SUPPORTED.iter().copied().find(|encoding| encoding.as_str() == coding))
+            .unwrap_or(Self::Identity)
+    }
+}
+
+// Already-compressed formats like images and archives gain nothing from another pass
+pub fn is_compressible(content_type: &str, len: usize) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    len >= MIN_COMPRESS_BYTES
+        && (media_type.starts_with("text/")
+            || matches!(media_type, "application/json" | "application/javascript" | "application/xml" | "image/svg+xml"))
+}
+
+pub fn compress(body: &[u8], encoding: ContentEncoding, level: CompressionLevel) -> IoResult<Vec<u8>> {
+    match encoding {
+        ContentEncoding::Identity => Ok(body.to_vec()),
+        ContentEncoding::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level.get()));
+            encoder.write_all(body)?;
+            encoder.finish()
+        },
+        #[cfg(feature = "brotli")]
+        ContentEncoding::Brotli => {
+            let mut output = Vec::new();
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut output, 4096, level.brotli_quality(), 22);
+                encoder.write_all(body)?;
+            }
+            Ok(output)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn q_values_decide_the_encoding() {
+        assert_eq!(ContentEncoding::negotiate(None), ContentEncoding::Identity);
+        assert_eq!(ContentEncoding::negotiate(Some("gzip;q=0.5, identity")), ContentEncoding::Gzip);
+        assert_eq!(ContentEncoding::negotiate(Some("gzip;q=0, deflate")), ContentEncoding::Identity);
+        assert_eq!(ContentEncoding::negotiate(Some("*")), ContentEncoding::negotiate(Some("br, gzip")));
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn brotli_wins_ties_and_higher_q_values() {
+        assert_eq!(ContentEncoding::negotiate(Some("gzip, deflate, br")), ContentEncoding::Brotli);
+        assert_eq!(ContentEncoding::negotiate(Some("gzip;q=0.5, br;q=1.0")), ContentEncoding::Brotli);
+        assert_eq!(ContentEncoding::negotiate(Some("gzip;q=1.0, br;q=0.5")), ContentEncoding::Gzip);
+    }
+
+    #[cfg(not(feature = "brotli"))]
+    #[test]
+    fn brotli_is_not_offered_without_the_feature() {
+        assert_eq!(ContentEncoding::negotiate(Some("br;q=1.0, gzip;q=0.5")), ContentEncoding::Gzip);
+        assert_eq!(ContentEncoding::negotiate(Some("br")), ContentEncoding::Identity);
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn brotli_output_decompresses_to_the_input() {
+        let body = "compressible text ".repeat(100);
+        // The clamped ends of the scale map to brotli qualities 0 and 8
+        for level in [0, 6, 42] {
+            let compressed = compress(body.as_bytes(), ContentEncoding::Brotli, CompressionLevel::new(level)).unwrap();
+            let mut decompressed = Vec::new();
+            std::io::Read::read_to_end(&mut brotli::Decompressor::new(&compressed[..], 4096), &mut decompressed).unwrap();
+            assert_eq!(decompressed, body.as_bytes(), "level {}", level);
+        }
+    }
+
+    #[test]
+    fn every_gzip_level_round_trips_and_out_of_range_levels_are_clamped() {
+        assert_eq!(CompressionLevel::new(0).get(), CompressionLevel::MIN);
+        assert_eq!(CompressionLevel::new(42).get(), CompressionLevel::MAX);
+        assert_eq!(CompressionLevel::default().get(), 6);
+
+        let body = "compressible text ".repeat(100);
+        for level in [0, 1, 6, 9, 42] {
+            let compressed = compress(body.as_bytes(), ContentEncoding::Gzip, CompressionLevel::new(level)).unwrap();
+            assert!(compressed.len() < body.len(), "level {}", level);
+            let mut decompressed = Vec::new();
+            std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(&compressed[..]), &mut decompressed).unwrap();
+            assert_eq!(decompressed, body.as_bytes(), "level {}", level);
+        }
+    }
+}
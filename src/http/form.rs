@@ -0,0 +1,57 @@
+use super::percent::form_decode;
+use serde::Serialize;
+use std::collections::HashMap;
+
+// Fields of an application/x-www-form-urlencoded body. Unlike QueryString the keys and values are
+// decoded, so they are owned.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct Form {
+    fields: HashMap<String, Vec<String>>,
+}
+
+impl Form {
+    // None when the body is not UTF-8 or contains a malformed escape
+    pub fn parse(body: &[u8]) -> Option<Self> {
+        let body = std::str::from_utf8(body).ok()?;
+        let mut fields: HashMap<String, Vec<String>> = HashMap::new();
+        for pair in body.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            fields.entry(form_decode(key)?).or_default().push(form_decode(value)?);
+        }
+        Some(Self { fields })
+    }
+
+    // The first value for `key`, for fields that are expected once
+    pub fn first(&self, key: &str) -> Option<&str> {
+        self.fields.get(key)?.first().map(String::as_str)
+    }
+
+    // Every value for `key` in the order sent, as checkboxes and multi-selects repeat a field
+    pub fn get_all(&self, key: &str) -> &[String] {
+        self.fields.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_are_decoded_with_plus_as_space() {
+        let form = Form::parse(b"a=1&b=hello+world&c=50%25+off&a=2&flag").unwrap();
+        assert_eq!(form.first("a"), Some("1"));
+        assert_eq!(form.get_all("a"), ["1", "2"]);
+        assert_eq!(form.first("b"), Some("hello world"));
+        assert_eq!(form.first("c"), Some("50% off"));
+        assert_eq!(form.first("flag"), Some(""));
+        assert_eq!(form.first("missing"), None);
+
+        assert!(Form::parse(b"").unwrap().is_empty());
+        assert!(Form::parse(b"a=%zz").is_none());
+    }
+}
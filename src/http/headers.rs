@@ -0,0 +1,41 @@
+#[derive(Clone, Debug, Default)]
+pub struct Headers<'buf> {
+    entries: Vec<(&'buf str, &'buf str)>,
+}
+
+impl<'buf> Headers<'buf> {
+    pub fn get(&self, name: &str) -> Option<&'buf str> {
+        self.entries.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|&(_, value)| value)
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'buf str> + 'a {
+        self.entries.iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|&(_, value)| value)
+    }
+
+    // True if any value of a comma-separated list header contains `token`, e.g. `Connection: keep-alive, Upgrade`
+    pub fn contains_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'buf str, &'buf str)> + '_ {
+        self.entries.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, name: &'buf str, value: &'buf str) {
+        self.entries.push((name, value));
+    }
+}
@@ -1,6 +1,7 @@
 use std::str::FromStr;
 
-#[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Method {
     GET,
     DELETE,
@@ -13,6 +14,25 @@ pub enum Method {
     PATCH,
 }
 
+// Parsing rejects anything outside this set (see MethodError), so every Method is one of the RFC 9110
+// methods that parse. Parsing is not support: the request parser refuses CONNECT, and TRACE is
+// answered with a 405. as_str is the exact inverse of from_str.
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::GET => "GET",
+            Self::DELETE => "DELETE",
+            Self::POST => "POST",
+            Self::PUT => "PUT",
+            Self::HEAD => "HEAD",
+            Self::CONNECT => "CONNECT",
+            Self::OPTIONS => "OPTIONS",
+            Self::TRACE => "TRACE",
+            Self::PATCH => "PATCH",
+        }
+    }
+}
+
 impl FromStr for Method {
     type Err = MethodError;
 
@@ -27,9 +47,44 @@ impl FromStr for Method {
             "OPTIONS" => Ok(Self::OPTIONS),
             "TRACE" => Ok(Self::TRACE),
             "PATCH" => Ok(Self::PATCH),
-            _ => Err(MethodError),
+            _ if is_token(s) => Err(MethodError::Unsupported),
+            _ => Err(MethodError::Invalid),
         }
     }
 }
 
-pub struct MethodError;
+// Invalid is a malformed token (400); Unsupported is a well-formed method this server doesn't implement (501)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MethodError {
+    Invalid,
+    Unsupported,
+}
+
+// RFC 9110 token characters; a well-formed method we just don't implement is a 501, not a 400
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_tokens_are_unsupported_and_garbage_is_invalid() {
+        assert_eq!("PATCH".parse::<Method>().map(|method| method.as_str()), Ok("PATCH"));
+        assert_eq!("PROPFIND".parse::<Method>().err(), Some(MethodError::Unsupported));
+        // Methods are case-sensitive, so this is well-formed but not ours
+        assert_eq!("get".parse::<Method>().err(), Some(MethodError::Unsupported));
+        for garbage in ["G@T", "GET/", "", "GE T"] {
+            assert_eq!(garbage.parse::<Method>().err(), Some(MethodError::Invalid), "{:?}", garbage);
+        }
+    }
+
+    #[test]
+    fn every_method_round_trips_through_its_name() {
+        use Method::*;
+        for method in [GET, DELETE, POST, PUT, HEAD, CONNECT, OPTIONS, TRACE, PATCH] {
+            assert_eq!(method.as_str().parse::<Method>(), Ok(method));
+        }
+    }
+}
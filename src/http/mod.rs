@@ -1,12 +1,25 @@
+pub use form::Form;
+pub use headers::Headers;
 pub use method::Method;
+pub use negotiation::negotiate;
 pub use query_string::QueryString; 
 pub use request::ParseError;
-pub use request::Request;
-pub use response::Response;
+pub use request::{HeaderLimits, Request};
+pub use response::{CachePolicy, Response, SecurityHeaders, SendOptions};
 pub use status_code::StatusCode;
+pub use version::Version;
 
+pub mod chunked;
+pub mod compression;
+pub mod form;
+pub mod headers;
 pub mod method;
+pub mod mime;
+pub mod multipart;
+pub mod negotiation;
+pub mod percent;
 pub mod query_string;
 pub mod request;
 pub mod response;
-pub mod status_code;
\ No newline at end of file
+pub mod status_code;
+pub mod version;
@@ -0,0 +1,115 @@
+// multipart/form-data bodies (RFC 7578), split into parts that borrow from the buffered body
+use super::ParseError;
+
+#[derive(Debug)]
+pub struct Part<'a> {
+    pub name: &'a str,
+    // Set for file fields; plain form fields have none
+    pub filename: Option<&'a str>,
+    pub content_type: Option<&'a str>,
+    pub data: &'a [u8],
+}
+
+// The boundary parameter of a `multipart/form-data` Content-Type, or None for any other type
+pub fn boundary(content_type: &str) -> Option<&str> {
+    let mut params = content_type.split(';');
+    if !params.next()?.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    let boundary = params
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"'))?;
+    // RFC 2046 §5.1.1 caps boundaries at 70 characters
+    (!boundary.is_empty() && boundary.len() <= 70).then_some(boundary)
+}
+
+pub fn parse<'a>(body: &'a [u8], boundary: &str) -> Result<Vec<Part<'a>>, ParseError> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+    // Every delimiter after the first one starts on a new line
+    let mut separator = b"\r\n".to_vec();
+    separator.extend_from_slice(delimiter);
+
+    // Anything before the first delimiter is preamble and ignored
+    let mut pos = if body.starts_with(delimiter) {
+        delimiter.len()
+    } else {
+        find(body, &separator).ok_or(ParseError::InvalidMultipartBody)? + separator.len()
+    };
+
+    let mut parts = Vec::new();
+    loop {
+        let rest = &body[pos..];
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        if !rest.starts_with(b"\r\n") {
+            return Err(ParseError::InvalidMultipartBody);
+        }
+        let part = &rest[2..];
+        let head_len = find(part, b"\r\n\r\n").ok_or(ParseError::InvalidMultipartBody)?;
+        let data_len = find(&part[head_len + 4..], &separator).ok_or(ParseError::InvalidMultipartBody)?;
+        parts.push(parse_part(&part[..head_len], &part[head_len + 4..head_len + 4 + data_len])?);
+        pos += 2 + head_len + 4 + data_len + separator.len();
+    }
+}
+
+fn parse_part<'a>(head: &'a [u8], data: &'a [u8]) -> Result<Part<'a>, ParseError> {
+    let head = std::str::from_utf8(head)?;
+    let (mut name, mut filename, mut content_type) = (None, None, None);
+    for line in head.split("\r\n") {
+        let (field, value) = line.split_once(':').ok_or(ParseError::InvalidMultipartBody)?;
+        if field.trim().eq_ignore_ascii_case("Content-Disposition") {
+            let mut params = value.split(';');
+            if !params.next().is_some_and(|kind| kind.trim().eq_ignore_ascii_case("form-data")) {
+                return Err(ParseError::InvalidMultipartBody);
+            }
+            for (param, value) in params.filter_map(|param| param.split_once('=')) {
+                let value = value.trim().trim_matches('"');
+                match param.trim().to_ascii_lowercase().as_str() {
+                    "name" => name = Some(value),
+                    "filename" => filename = Some(value),
+                    _ => {},
+                }
+            }
+        } else if field.trim().eq_ignore_ascii_case("Content-Type") {
+            content_type = Some(value.trim());
+        }
+    }
+
+    Ok(Part {
+        name: name.ok_or(ParseError::InvalidMultipartBody)?,
+        filename,
+        content_type,
+        data,
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parts_carry_their_name_filename_type_and_bytes() {
+        let content_type = "multipart/form-data; boundary=\"XyZ\"";
+        assert_eq!(boundary(content_type), Some("XyZ"));
+        assert_eq!(boundary("application/json"), None);
+
+        let body = b"preamble\r\n--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nNotes\r\n\
+            --XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\nContent-Type: text/plain\r\n\r\nline one\r\nline two\r\n\
+            --XyZ--\r\n";
+        let parts = parse(body, "XyZ").unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!((parts[0].name, parts[0].filename, parts[0].data), ("title", None, &b"Notes"[..]));
+        assert_eq!((parts[1].name, parts[1].filename, parts[1].content_type), ("file", Some("a.txt"), Some("text/plain")));
+        assert_eq!(parts[1].data, b"line one\r\nline two");
+
+        // A body cut off before its closing delimiter is rejected rather than half-parsed
+        assert!(parse(b"--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nunterminated", "XyZ").is_err());
+    }
+}
@@ -0,0 +1,136 @@
+// Picks the best media type from `available` (in server preference order) for an Accept header.
+// A missing Accept header means the client takes anything, so the first representation wins.
+pub fn negotiate<'a>(accept: Option<&str>, available: &[&'a str]) -> Option<&'a str> {
+    let accept = match accept {
+        Some(accept) if !accept.trim().is_empty() => accept,
+        _ => return available.first().copied(),
+    };
+
+    let ranges: Vec<(&str, f32)> = accept.split(',').filter_map(parse_weighted).collect();
+
+    let mut best: Option<(&'a str, f32)> = None;
+    for &candidate in available {
+        let quality = ranges.iter()
+            .filter(|(range, _)| media_range_matches(range, candidate))
+            .max_by_key(|(range, _)| specificity(range))
+            .map(|&(_, q)| q)
+            .unwrap_or(0.0);
+
+        if quality > 0.0 && best.is_none_or(|(_, best_q)| quality > best_q) {
+            best = Some((candidate, quality));
+        }
+    }
+
+    best.map(|(media_type, _)| media_type)
+}
+
+// Picks the best content coding from `available` (in server preference order) for an
+// Accept-Encoding header. None means the body should be sent unencoded.
+pub fn negotiate_encoding<'a>(accept_encoding: Option<&str>, available: &[&'a str]) -> Option<&'a str> {
+    let codings = parse_accept_encoding(accept_encoding?);
+
+    let mut best: Option<(&'a str, f32)> = None;
+    for &candidate in available {
+        // An explicit entry overrides the `*` wildcard, even when it is q=0
+        let quality = codings.iter()
+            .find(|(coding, _)| coding.eq_ignore_ascii_case(candidate))
+            .or_else(|| codings.iter().find(|(coding, _)| coding == "*"))
+            .map(|&(_, q)| q)
+            .unwrap_or(0.0);
+
+        if quality > 0.0 && best.is_none_or(|(_, best_q)| quality > best_q) {
+            best = Some((candidate, quality));
+        }
+    }
+
+    best.map(|(coding, _)| coding)
+}
+
+// Accept-Encoding as (lowercased coding, q) pairs, most preferred first; ties keep header order.
+// q=0 entries are kept, at the end, because they forbid a coding (`identity;q=0`) or override `*`.
+// Only the first entry for a coding counts. Nothing implicit is added: a missing `identity` is
+// still acceptable per RFC 9110 §12.5.3 unless `*;q=0` excludes it.
+pub fn parse_accept_encoding(accept_encoding: &str) -> Vec<(String, f32)> {
+    let mut codings: Vec<(String, f32)> = Vec::new();
+    for (coding, quality) in accept_encoding.split(',').filter_map(parse_weighted) {
+        let coding = coding.to_ascii_lowercase();
+        if !codings.iter().any(|(seen, _)| *seen == coding) {
+            codings.push((coding, quality));
+        }
+    }
+    codings.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    codings
+}
+
+// Parses `value;q=0.5` style entries shared by Accept and Accept-Encoding
+fn parse_weighted(entry: &str) -> Option<(&str, f32)> {
+    let mut parts = entry.split(';');
+    let range = parts.next()?.trim();
+    if range.is_empty() {
+        return None;
+    }
+
+    let quality = parts
+        .filter_map(|param| param.trim().strip_prefix("q="))
+        .find_map(|q| q.trim().parse::<f32>().ok())
+        .unwrap_or(1.0);
+
+    Some((range, quality.clamp(0.0, 1.0)))
+}
+
+fn media_range_matches(range: &str, media_type: &str) -> bool {
+    if range == "*/*" || range.eq_ignore_ascii_case(media_type) {
+        return true;
+    }
+    match (range.split_once('/'), media_type.split_once('/')) {
+        (Some((range_type, "*")), Some((candidate_type, _))) => range_type.eq_ignore_ascii_case(candidate_type),
+        _ => false,
+    }
+}
+
+// More specific ranges take precedence: `text/plain` over `text/*` over `*/*`
+fn specificity(range: &str) -> u8 {
+    if range == "*/*" {
+        0
+    } else if range.ends_with("/*") {
+        1
+    } else {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: [&str; 3] = ["application/json", "text/plain", "application/xml"];
+
+    #[test]
+    fn first_representation_is_the_default() {
+        assert_eq!(negotiate(None, &INFO), Some("application/json"));
+        assert_eq!(negotiate(Some("*/*"), &INFO), Some("application/json"));
+    }
+
+    #[test]
+    fn explicit_types_and_weights_pick_the_representation() {
+        assert_eq!(negotiate(Some("application/xml"), &INFO), Some("application/xml"));
+        assert_eq!(negotiate(Some("text/*"), &INFO), Some("text/plain"));
+        assert_eq!(negotiate(Some("application/json;q=0.5, text/plain"), &INFO), Some("text/plain"));
+        // A specific q=0 beats the wildcard that would otherwise match it
+        assert_eq!(negotiate(Some("application/json;q=0, */*;q=0.1"), &INFO), Some("text/plain"));
+    }
+
+    #[test]
+    fn nothing_acceptable_is_none() {
+        assert_eq!(negotiate(Some("image/png"), &INFO), None);
+    }
+
+    #[test]
+    fn accept_encoding_is_ordered_with_refusals_last() {
+        let owned = |pairs: &[(&str, f32)]| pairs.iter().map(|&(coding, q)| (coding.to_string(), q)).collect::<Vec<_>>();
+        assert_eq!(parse_accept_encoding("gzip;q=0.8, br, identity;q=0"), owned(&[("br", 1.0), ("gzip", 0.8), ("identity", 0.0)]));
+        // Case folds, the first mention of a coding wins and q is clamped
+        assert_eq!(parse_accept_encoding("GZIP;q=0.5, gzip, *;q=2, , deflate;q=junk"), owned(&[("*", 1.0), ("deflate", 1.0), ("gzip", 0.5)]));
+        assert!(parse_accept_encoding("").is_empty());
+    }
+}
@@ -0,0 +1,51 @@
+// Decodes %XX escapes in a path. Returns None for truncated or non-hex escapes and for
+// sequences that don't decode to UTF-8, since neither can name a file we serve.
+pub fn percent_decode(input: &str) -> Option<String> {
+    if !input.contains('%') {
+        return Some(input.to_string());
+    }
+
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = input.get(i + 1..i + 3)?;
+            // from_str_radix alone would accept a sign, so `%+5` would decode to 0x05
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+// application/x-www-form-urlencoded values also encode spaces as '+', which a literal plus can't be
+pub fn form_decode(input: &str) -> Option<String> {
+    if input.contains('+') {
+        percent_decode(&input.replace('+', " "))
+    } else {
+        percent_decode(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_must_be_two_hex_digits() {
+        assert_eq!(percent_decode("/my%20file.txt").as_deref(), Some("/my file.txt"));
+        assert_eq!(percent_decode("%2e%2E").as_deref(), Some(".."));
+        for input in ["%+5", "%-1", "%zz", "%4", "%", "%c3"] {
+            assert_eq!(percent_decode(input), None, "{}", input);
+        }
+    }
+}
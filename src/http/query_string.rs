@@ -1,11 +1,11 @@
 use std::collections::HashMap;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct QueryString<'buf> {
     data: HashMap<&'buf str, Value<'buf>>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Value<'buf> {
     Single(&'buf str),
     Multiple(Vec<&'buf str>),
@@ -15,6 +15,14 @@ impl<'buf> QueryString<'buf> {
     pub fn get(&self, key: &str) -> Option<&Value<'_>> {
         self.data.get(key)
     }
+
+    // The first value for `key`, for parameters that are expected once
+    pub fn first(&self, key: &str) -> Option<&'buf str> {
+        match self.data.get(key)? {
+            Value::Single(value) => Some(value),
+            Value::Multiple(values) => values.first().copied(),
+        }
+    }
 }
 
 
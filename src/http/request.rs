@@ -1,21 +1,66 @@
+use super::chunked::{self, ChunkedStatus};
 use super::method::{Method, MethodError};
-use super::QueryString;
+use super::negotiation::parse_accept_encoding;
+use super::version::{Version, VersionError};
+use super::{Form, Headers, QueryString, StatusCode};
+use std::borrow::Cow;
 use std::convert::TryFrom;
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::net::SocketAddr;
 use std::str;
 use std::str::Utf8Error;
 
-#[derive(Debug)]
+// Bounds on the request head; exceeding any of them is a 431
+#[derive(Clone, Copy, Debug)]
+pub struct HeaderLimits {
+    // Request line plus all header fields
+    pub max_head_bytes: usize,
+    pub max_headers: usize,
+    // A single `Name: value` line
+    pub max_header_bytes: usize,
+}
+
+impl Default for HeaderLimits {
+    fn default() -> Self {
+        Self { max_head_bytes: 8192, max_headers: 100, max_header_bytes: 4096 }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Request<'buf> {
     path: &'buf str,
     query_string: Option<QueryString<'buf>>,
+    raw_query: Option<&'buf str>,
     method: Method,
+    version: Version,
+    headers: Headers<'buf>,
+    body: Cow<'buf, [u8]>,
+    consumed: usize,
+    request_id: String,
+    authority: Option<&'buf str>,
+    content_length: Option<usize>,
+    remote_addr: Option<SocketAddr>,
+    secure: bool,
+    raw: Option<&'buf [u8]>,
 }
 
 impl<'buf> Request<'buf> {
+    // The target up to any '?', so `/?page=2` routes as `/`; the rest is in query_string()
     pub fn path(&self) -> &str {
-        &self.path
+        self.path
+    }
+
+    // Non-empty `/`-separated pieces of the path, so `/api/users/7/` gives ["api", "users", "7"].
+    // Segments stay percent-encoded: decoding first would let `%2F` split a segment in two
+    pub fn path_segments(&self) -> Vec<&'buf str> {
+        self.path.split('/').filter(|segment| !segment.is_empty()).collect()
+    }
+
+    // `/hello/` -> `/hello`; the root path stays `/`
+    pub(crate) fn trim_trailing_slash(&mut self) {
+        let trimmed = self.path.trim_end_matches('/');
+        self.path = if trimmed.is_empty() { "/" } else { trimmed };
     }
 
     pub fn method(&self) -> &Method {
@@ -23,64 +68,354 @@ impl<'buf> Request<'buf> {
     }
 
     pub fn method_str(&self) -> &str {
-        match self.method {
-            Method::GET => "GET",
-            Method::POST => "POST",
-            Method::PUT => "PUT",
-            Method::DELETE => "DELETE",
-            Method::HEAD => "HEAD",
-            Method::OPTIONS => "OPTIONS",
-            Method::PATCH => "PATCH",
-            Method::CONNECT => "CONNECT",
-            Method::TRACE => "TRACE",
-        }
+        self.method.as_str()
     }
 
     pub fn query_string(&self) -> Option<&QueryString<'buf>> {
         self.query_string.as_ref()
     }
-}
 
-impl<'buf> TryFrom<&'buf [u8]> for Request<'buf> {
-    type Error = ParseError;
+    // The body's fields for a urlencoded form POST; None for other content types or a malformed body
+    pub fn form(&self) -> Option<Form> {
+        let content_type = self.header("Content-Type")?;
+        let media_type = content_type.split(';').next().unwrap_or("").trim();
+        if !media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            return None;
+        }
+        Form::parse(self.body())
+    }
 
-    fn try_from(buf: &'buf [u8]) -> Result<Request<'buf>, Self::Error> {
-        let request = str::from_utf8(buf)?;
+    // The query exactly as sent, without the leading '?', for rebuilding URLs
+    pub fn raw_query(&self) -> Option<&'buf str> {
+        self.raw_query
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn headers(&self) -> &Headers<'buf> {
+        &self.headers
+    }
+
+    pub fn header(&self, name: &str) -> Option<&'buf str> {
+        self.headers.get(name)
+    }
+
+    // For absolute-form targets the authority in the request line replaces the Host header (RFC 7230 §5.4)
+    pub fn host(&self) -> Option<&'buf str> {
+        self.authority.or_else(|| self.headers.get("Host"))
+    }
+
+    // Parsed Accept-Encoding, most preferred first; see negotiation::parse_accept_encoding
+    pub fn accept_encodings(&self) -> Vec<(String, f32)> {
+        self.header("Accept-Encoding").map(parse_accept_encoding).unwrap_or_default()
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    // Hands the body over to a streaming handler, leaving the request with an empty one
+    pub(crate) fn take_body(&mut self) -> Cow<'buf, [u8]> {
+        std::mem::replace(&mut self.body, Cow::Borrowed(&[]))
+    }
+
+    pub fn content_length(&self) -> Option<usize> {
+        self.content_length
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        self.headers.contains_token("Transfer-Encoding", "chunked")
+    }
+
+    // Bytes of the input buffer that belong to this request; anything after is a pipelined request
+    pub fn consumed_len(&self) -> usize {
+        self.consumed
+    }
+
+    // Assigned by the server once the request is parsed; empty until then
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    pub(crate) fn set_request_id(&mut self, request_id: String) {
+        self.request_id = request_id;
+    }
+
+    // The peer the server accepted this request from; None for requests built outside a connection
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    pub(crate) fn set_remote_addr(&mut self, remote_addr: SocketAddr) {
+        self.remote_addr = Some(remote_addr);
+    }
+
+    // Whether the connection this arrived on is HTTPS, as reported by the server's transport
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        if self.secure { "https" } else { "http" }
+    }
+
+    pub(crate) fn set_secure(&mut self, secure: bool) {
+        self.secure = secure;
+    }
+
+    // The bytes this request arrived as, head and body exactly as sent (chunk framing included); a
+    // streamed body only contributes what came in with the head. Only kept when the server opts in
+    // with Server::with_raw_requests; None otherwise and for HTTP/2.
+    pub fn raw(&self) -> Option<&'buf [u8]> {
+        self.raw
+    }
+
+    // `buf` is the buffer this request was parsed from
+    pub(crate) fn retain_raw(&mut self, buf: &'buf [u8]) {
+        self.raw = Some(&buf[..self.consumed]);
+    }
+
+    // Builds an HTTP/1.1 request without going through the wire format, so handlers can be
+    // exercised without a socket. `target` may carry a query string.
+    pub fn for_test(method: Method, target: &'buf str, headers: &[(&'buf str, &'buf str)], body: &'buf [u8], remote_addr: SocketAddr) -> Self {
+        let mut request = Self::from_parts(method, Version::Http11, target, None, headers, body);
+        request.remote_addr = Some(remote_addr);
+        request
+    }
+
+    // For protocol layers that decode requests themselves (HTTP/2), rather than from HTTP/1 bytes
+    pub(crate) fn from_parts(
+        method: Method,
+        version: Version,
+        target: &'buf str,
+        authority: Option<&'buf str>,
+        headers: &[(&'buf str, &'buf str)],
+        body: &'buf [u8],
+    ) -> Self {
+        let (path, raw_query) = match target.split_once('?') {
+            Some(("", query)) => ("/", Some(query)),
+            Some((path, query)) => (path, Some(query)),
+            None => (target, None),
+        };
+
+        let mut parsed_headers = Headers::default();
+        for &(name, value) in headers {
+            parsed_headers.push(name, value);
+        }
+        let content_length = parse_content_length(&parsed_headers).ok().flatten();
+
+        Self {
+            path,
+            query_string: raw_query.map(QueryString::from),
+            raw_query,
+            method,
+            version,
+            headers: parsed_headers,
+            body: Cow::Borrowed(body),
+            consumed: 0,
+            request_id: String::new(),
+            authority,
+            content_length,
+            remote_addr: None,
+            secure: false,
+            raw: None,
+        }
+    }
+
+    // An explicit Connection header wins over the version default
+    pub fn keep_alive(&self) -> bool {
+        if self.headers.contains_token("Connection", "close") {
+            false
+        } else if self.headers.contains_token("Connection", "keep-alive") {
+            true
+        } else {
+            self.version.keep_alive_by_default()
+        }
+    }
+
+    // RFC 6455 opening handshake: GET over HTTP/1.1 with Upgrade, Connection, key and version 13
+    pub fn is_websocket_upgrade(&self) -> bool {
+        matches!(self.method, Method::GET)
+            && self.version == Version::Http11
+            && self.headers.contains_token("Upgrade", "websocket")
+            && self.headers.contains_token("Connection", "Upgrade")
+            && self.headers.get("Sec-WebSocket-Key").is_some_and(|key| !key.is_empty())
+            && self.headers.get("Sec-WebSocket-Version") == Some("13")
+    }
+
+    pub fn wants_event_stream(&self) -> bool {
+        self.headers.get_all("Accept")
+            .flat_map(|value| value.split(','))
+            .any(|range| range.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case("text/event-stream"))
+    }
+}
+
+impl<'buf> Request<'buf> {
+    // Parses the request line and header fields only, leaving the body empty. The reader uses this
+    // to learn how the body is framed before all of it has arrived.
+    pub(crate) fn parse_head(buf: &'buf [u8], limits: &HeaderLimits) -> Result<Request<'buf>, ParseError> {
+        // Everything up to the blank line is the request line plus header fields
+        let (head, body_start) = match find_head_end(buf) {
+            Some((end, body_start)) => (&buf[..end], body_start),
+            None => (buf, buf.len()),
+        };
 
-        if request.len() > 8192 {
-            return Err(ParseError::RequestTooLarge);
+        if head.len() > limits.max_head_bytes {
+            return Err(ParseError::HeadersTooLarge);
         }
 
-        let (method, request) = get_next_word(request).ok_or(ParseError::InvalidRequest)?;
-        let (mut path, request) = get_next_word(request).ok_or(ParseError::InvalidRequest)?;
-        let (protocol, _) = get_next_word(request).ok_or(ParseError::InvalidRequest)?;
+        let head = str::from_utf8(head)?;
+        // The blank line decides the terminator. A CR or LF left inside a line means the head mixes
+        // the two, which a proxy could split differently than we do.
+        let terminator = if body_start - head.len() == 2 { "\n" } else { "\r\n" };
+        if head.split(terminator).any(|line| line.contains(['\r', '\n'])) {
+            return Err(ParseError::MixedLineEndings);
+        }
+        let mut lines = head.split(terminator);
+        let request_line = lines.next().ok_or(ParseError::InvalidRequest)?;
+        if request_line.trim().is_empty() {
+            return Err(ParseError::EmptyRequestLine);
+        }
 
-        if !protocol.starts_with("HTTP/1.") {
-            return Err(ParseError::InvalidProtocol);
+        let (method, request_line) = get_next_word(request_line).ok_or(ParseError::IncompleteRequestLine)?;
+        let (target, protocol) = get_next_word(request_line).ok_or(ParseError::IncompleteRequestLine)?;
+        // Leading or doubled spaces leave an empty word where the method or target should be
+        if method.is_empty() || target.is_empty() {
+            return Err(ParseError::IncompleteRequestLine);
         }
 
+        let version: Version = protocol.parse()?;
         let method: Method = method.parse()?;
+        let (authority, mut path) = parse_request_target(&method, target)?;
 
-        let mut query_string = None;
+        let mut raw_query = None;
         if let Some(i) = path.find('?') {
-            query_string = Some(QueryString::from(&path[i + 1..]));
+            raw_query = Some(&path[i + 1..]);
             path = &path[..i];
         }
+        if path.is_empty() {
+            path = "/";
+        }
 
         if path.contains("..") || path.contains('\0') {
             return Err(ParseError::InvalidPath);
         }
 
+        let headers = parse_headers(lines, limits)?;
+        let content_length = parse_content_length(&headers)?;
+
+        // A message framed both ways is the classic request smuggling setup
+        if headers.get("Transfer-Encoding").is_some() {
+            if content_length.is_some() {
+                return Err(ParseError::ConflictingFraming);
+            }
+            validate_transfer_encoding(&headers)?;
+        }
+
         Ok(Self {
             path,
-            query_string,
+            query_string: raw_query.map(QueryString::from),
+            raw_query,
             method,
+            version,
+            headers,
+            body: Cow::Borrowed(&[]),
+            consumed: body_start,
+            request_id: String::new(),
+            authority,
+            content_length,
+            remote_addr: None,
+            secure: false,
+            raw: None,
         })
     }
 }
 
+impl<'buf> TryFrom<&'buf [u8]> for Request<'buf> {
+    type Error = ParseError;
+
+    fn try_from(buf: &'buf [u8]) -> Result<Request<'buf>, Self::Error> {
+        Self::parse(buf, &HeaderLimits::default(), usize::MAX)
+    }
+}
+
+impl<'buf> Request<'buf> {
+    // Full parse, head and body, with caller-chosen limits on the header section and decoded body
+    pub(crate) fn parse(buf: &'buf [u8], limits: &HeaderLimits, max_body_bytes: usize) -> Result<Request<'buf>, ParseError> {
+        let mut request = Self::parse_head(buf, limits)?;
+        let rest = &buf[request.consumed..];
+
+        if request.is_chunked() {
+            let mut body = Vec::new();
+            match chunked::decode(rest, Some(&mut body), max_body_bytes)? {
+                ChunkedStatus::Complete { consumed } => {
+                    request.body = Cow::Owned(body);
+                    request.consumed += consumed;
+                },
+                ChunkedStatus::Incomplete { .. } => return Err(ParseError::InvalidChunkedBody),
+            }
+        } else {
+            // Only the declared Content-Length belongs to this request; anything after it is not ours
+            let content_length = request.content_length.unwrap_or(0);
+            let body = &rest[..content_length.min(rest.len())];
+            request.body = Cow::Borrowed(body);
+            request.consumed += body.len();
+        }
+
+        Ok(request)
+    }
+}
+
+// Splits a request-target into an optional authority and an origin-form path (with any query attached).
+// Absolute-form is normalized; CONNECT's authority-form is not something a file server can act on.
+fn parse_request_target<'buf>(method: &Method, target: &'buf str) -> Result<(Option<&'buf str>, &'buf str), ParseError> {
+    if matches!(method, Method::CONNECT) {
+        return Err(ParseError::UnsupportedTarget);
+    }
+
+    if target.starts_with('/') {
+        return Ok((None, target));
+    }
+
+    if target == "*" {
+        return match method {
+            Method::OPTIONS => Ok((None, target)),
+            _ => Err(ParseError::InvalidTarget),
+        };
+    }
+
+    let scheme_end = target.find("://").ok_or(ParseError::InvalidTarget)?;
+    let scheme = &target[..scheme_end];
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return Err(ParseError::UnsupportedTarget);
+    }
+
+    let rest = &target[scheme_end + 3..];
+    let authority_end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let authority = &rest[..authority_end];
+    if authority.is_empty() || authority.contains('@') {
+        return Err(ParseError::InvalidTarget);
+    }
+
+    // `http://host` and `http://host?q` leave an empty path, which the caller treats as "/"
+    Ok((Some(authority), &rest[authority_end..]))
+}
+
+// Returns where the head ends and the body starts. Bare-LF heads are accepted (RFC 9112 §2.2);
+// whichever blank line comes first wins, and parse_head rejects a head that mixes the two.
+pub(crate) fn find_head_end(buf: &[u8]) -> Option<(usize, usize)> {
+    let crlf = buf.windows(4).position(|window| window == b"\r\n\r\n").map(|i| (i, i + 4));
+    let lf = buf.windows(2).position(|window| window == b"\n\n").map(|i| (i, i + 2));
+    match (crlf, lf) {
+        (Some(crlf), Some(lf)) => Some(if lf.0 < crlf.0 { lf } else { crlf }),
+        (crlf, lf) => crlf.or(lf),
+    }
+}
+
 fn get_next_word(request: &str) -> Option<(&str, &str)> {
-    for (i, c) in request.chars().enumerate() {
+    for (i, c) in request.char_indices() {
         if c == ' ' || c == '\r' {
             return Some((&request[..i], &request[i + 1..]));
         }
@@ -88,31 +423,153 @@ fn get_next_word(request: &str) -> Option<(&str, &str)> {
     None
 }
 
+fn parse_headers<'buf>(lines: impl Iterator<Item = &'buf str>, limits: &HeaderLimits) -> Result<Headers<'buf>, ParseError> {
+    let mut headers = Headers::default();
+
+    for (count, line) in lines.enumerate() {
+        if line.is_empty() {
+            break;
+        }
+        if count >= limits.max_headers || line.len() > limits.max_header_bytes {
+            return Err(ParseError::HeadersTooLarge);
+        }
+        let (name, value) = line.split_once(':').ok_or(ParseError::InvalidHeader)?;
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(ParseError::InvalidHeader);
+        }
+        headers.push(name, value.trim());
+    }
+
+    // More than one Host field is ambiguous and a known request smuggling vector, even when the
+    // values agree; RFC 9112 §3.2 requires a 400 either way
+    if headers.get_all("Host").nth(1).is_some() {
+        return Err(ParseError::DuplicateHost);
+    }
+
+    Ok(headers)
+}
+
+// Every field is checked, since is_chunked() frames the body from all of them. `chunked` is the only
+// coding we decode; anything else is a 501, and a repeated `chunked` (as a second field or in a list)
+// is a 400 because intermediaries disagree on what it means.
+fn validate_transfer_encoding(headers: &Headers) -> Result<(), ParseError> {
+    let codings: Vec<&str> = headers.get_all("Transfer-Encoding")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+
+    if codings.iter().any(|coding| !coding.eq_ignore_ascii_case("chunked")) {
+        return Err(ParseError::UnsupportedTransferEncoding);
+    }
+    if codings.len() > 1 {
+        return Err(ParseError::RepeatedTransferEncoding);
+    }
+    Ok(())
+}
+
+// Repeated Content-Length fields (or `5, 5` lists) are tolerated only when every value agrees;
+// differing values would let a proxy and this server disagree about where the body ends.
+fn parse_content_length(headers: &Headers) -> Result<Option<usize>, ParseError> {
+    let mut values = headers.get_all("Content-Length")
+        .flat_map(|value| value.split(','))
+        .map(str::trim);
+
+    let first = match values.next() {
+        Some(first) => first,
+        None => return Ok(None),
+    };
+    if values.any(|value| value != first) {
+        return Err(ParseError::ConflictingContentLength);
+    }
+
+    // Only plain digits: `parse` would also take a leading `+`, which other parsers may read differently
+    if first.is_empty() || !first.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ParseError::InvalidContentLength);
+    }
+    first.parse::<usize>().map(Some).map_err(|_| ParseError::InvalidContentLength)
+}
+
 pub enum ParseError {
     InvalidRequest,
+    EmptyRequestLine,
+    IncompleteRequestLine,
     InvalidEncoding,
     InvalidProtocol,
     InvalidMethod,
+    UnsupportedMethod,
     InvalidPath,
-    RequestTooLarge,
+    HeadersTooLarge,
+    UnsupportedVersion,
+    InvalidHeader,
+    MixedLineEndings,
+    DuplicateHost,
+    InvalidTarget,
+    UnsupportedTarget,
+    ConflictingFraming,
+    ConflictingContentLength,
+    InvalidContentLength,
+    UnsupportedTransferEncoding,
+    RepeatedTransferEncoding,
+    InvalidChunkedBody,
+    BodyTooLarge,
+    InvalidMultipartBody,
 }
 
 impl ParseError {
     fn message(&self) -> &str {
         match self {
             Self::InvalidRequest => "Invalid Request",
+            Self::EmptyRequestLine => "Empty Request Line",
+            Self::IncompleteRequestLine => "Incomplete Request Line",
             Self::InvalidEncoding => "Invalid Encoding",
             Self::InvalidProtocol => "Invalid Protocol",
             Self::InvalidMethod => "Invalid Method",
+            Self::UnsupportedMethod => "Unsupported Method",
             Self::InvalidPath => "Invalid Path",
-            Self::RequestTooLarge => "Request Too Large",
+            Self::HeadersTooLarge => "Request Header Fields Too Large",
+            Self::UnsupportedVersion => "Unsupported HTTP Version",
+            Self::InvalidHeader => "Invalid Header",
+            Self::MixedLineEndings => "Mixed Line Endings",
+            Self::DuplicateHost => "Duplicate Host Headers",
+            Self::InvalidTarget => "Invalid Request Target",
+            Self::UnsupportedTarget => "Unsupported Request Target",
+            Self::ConflictingFraming => "Both Content-Length and Transfer-Encoding Present",
+            Self::ConflictingContentLength => "Conflicting Content-Length Headers",
+            Self::InvalidContentLength => "Invalid Content-Length",
+            Self::UnsupportedTransferEncoding => "Unsupported Transfer-Encoding",
+            Self::RepeatedTransferEncoding => "Repeated Transfer-Encoding",
+            Self::InvalidChunkedBody => "Invalid Chunked Body",
+            Self::BodyTooLarge => "Payload Too Large",
+            Self::InvalidMultipartBody => "Invalid Multipart Body",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::UnsupportedVersion => StatusCode::HttpVersionNotSupported,
+            Self::HeadersTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+            Self::BodyTooLarge => StatusCode::PayloadTooLarge,
+            Self::UnsupportedMethod | Self::UnsupportedTransferEncoding => StatusCode::NotImplemented,
+            _ => StatusCode::BadRequest,
         }
     }
 }
 
 impl From<MethodError> for ParseError {
-    fn from(_: MethodError) -> Self {
-        Self::InvalidMethod
+    fn from(e: MethodError) -> Self {
+        match e {
+            MethodError::Invalid => Self::InvalidMethod,
+            MethodError::Unsupported => Self::UnsupportedMethod,
+        }
+    }
+}
+
+impl From<VersionError> for ParseError {
+    fn from(e: VersionError) -> Self {
+        match e {
+            VersionError::Invalid => Self::InvalidProtocol,
+            VersionError::Unsupported => Self::UnsupportedVersion,
+        }
     }
 }
 
@@ -135,3 +592,239 @@ impl Debug for ParseError {
 }
 
 impl Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &[u8]) -> Result<Request<'_>, ParseError> {
+        Request::parse(raw, &HeaderLimits::default(), usize::MAX)
+    }
+
+    #[test]
+    fn repeated_host_is_a_bad_request() {
+        for second in ["evil.example", "localhost"] {
+            let raw = format!("GET / HTTP/1.1\r\nHost: localhost\r\nHost: {}\r\n\r\n", second);
+            let error = parse(raw.as_bytes()).err().unwrap();
+            assert!(matches!(error, ParseError::DuplicateHost), "{}", second);
+            assert_eq!(error.status_code(), StatusCode::BadRequest);
+        }
+        assert_eq!(parse(b"GET / HTTP/1.1\r\nHost: localhost:8080\r\n\r\n").unwrap().host(), Some("localhost:8080"));
+    }
+
+    #[test]
+    fn websocket_upgrades_and_event_streams_are_detected() {
+        let upgrade = parse(b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n").unwrap();
+        assert!(upgrade.is_websocket_upgrade());
+        assert!(!upgrade.wants_event_stream());
+
+        let events = parse(b"GET /events HTTP/1.1\r\nHost: localhost\r\nAccept: text/html, text/event-stream;q=0.9\r\n\r\n").unwrap();
+        assert!(events.wants_event_stream());
+        assert!(!events.is_websocket_upgrade());
+
+        let plain = parse(b"GET / HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n").unwrap();
+        assert!(!plain.is_websocket_upgrade());
+        assert!(!plain.wants_event_stream());
+        // The handshake headers are all required
+        let keyless = parse(b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\r\n").unwrap();
+        assert!(!keyless.is_websocket_upgrade());
+    }
+
+    #[test]
+    fn absolute_form_is_normalized_and_connect_rejected() {
+        let request = parse(b"GET http://localhost:8080/docs?page=2 HTTP/1.1\r\nHost: ignored.example\r\n\r\n").unwrap();
+        assert_eq!(request.path(), "/docs");
+        assert_eq!(request.raw_query(), Some("page=2"));
+        assert_eq!(request.host(), Some("localhost:8080"));
+        assert_eq!(parse(b"GET http://localhost HTTP/1.1\r\n\r\n").unwrap().path(), "/");
+
+        let error = parse(b"CONNECT localhost:443 HTTP/1.1\r\nHost: localhost:443\r\n\r\n").err().unwrap();
+        assert!(matches!(error, ParseError::UnsupportedTarget));
+        assert_eq!(error.status_code(), StatusCode::BadRequest);
+        for target in ["ftp://localhost/file", "http://user@localhost/", "docs"] {
+            let raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target);
+            assert_eq!(parse(raw.as_bytes()).err().unwrap().status_code(), StatusCode::BadRequest, "{}", target);
+        }
+    }
+
+    #[test]
+    fn chunked_body_is_decoded() {
+        let raw = b"POST /api/echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\nGET / HTTP/1.1\r\n";
+        let request = parse(raw).unwrap();
+        assert_eq!(request.body(), b"Wikipedia");
+        assert!(request.is_chunked());
+        assert_eq!(&raw[request.consumed_len()..], b"GET / HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn content_length_with_transfer_encoding_is_rejected() {
+        let raw = b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+        let error = parse(raw).err().unwrap();
+        assert!(matches!(error, ParseError::ConflictingFraming));
+        assert_eq!(error.status_code(), StatusCode::BadRequest);
+    }
+
+    #[test]
+    fn unsupported_transfer_codings_are_not_implemented() {
+        for encoding in ["gzip", "gzip, chunked", "chunked, gzip", "chunked\r\nTransfer-Encoding: gzip", "identity"] {
+            let raw = format!("POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: {}\r\n\r\n0\r\n\r\n", encoding);
+            let error = parse(raw.as_bytes()).err().unwrap();
+            assert!(matches!(error, ParseError::UnsupportedTransferEncoding), "{}", encoding);
+            assert_eq!(error.status_code(), StatusCode::NotImplemented);
+        }
+    }
+
+    #[test]
+    fn repeated_chunked_is_rejected() {
+        for encoding in ["chunked, chunked", "chunked\r\nTransfer-Encoding: chunked"] {
+            let raw = format!("POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: {}\r\n\r\n0\r\n\r\n", encoding);
+            let error = parse(raw.as_bytes()).err().unwrap();
+            assert!(matches!(error, ParseError::RepeatedTransferEncoding), "{}", encoding);
+            assert_eq!(error.status_code(), StatusCode::BadRequest);
+        }
+        assert!(parse(b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: Chunked\r\n\r\n0\r\n\r\n").is_ok());
+    }
+
+    #[test]
+    fn unknown_methods_are_501_and_malformed_ones_400() {
+        let error = parse(b"PROPFIND / HTTP/1.1\r\nHost: localhost\r\n\r\n").err().unwrap();
+        assert_eq!(error.status_code(), StatusCode::NotImplemented);
+        let error = parse(b"G@T / HTTP/1.1\r\nHost: localhost\r\n\r\n").err().unwrap();
+        assert_eq!(error.status_code(), StatusCode::BadRequest);
+    }
+
+    #[test]
+    fn oversized_chunk_is_payload_too_large() {
+        let raw = b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\nFFFFFFFFFFFFFFFF\r\nabc\r\n0\r\n\r\n";
+        let error = Request::parse(raw, &HeaderLimits::default(), 1024).err().unwrap();
+        assert_eq!(error.status_code(), StatusCode::PayloadTooLarge);
+    }
+
+    #[test]
+    fn repeated_content_lengths_must_agree() {
+        for lengths in ["Content-Length: 5\r\nContent-Length: 6", "Content-Length: 5, 6"] {
+            let raw = format!("POST / HTTP/1.1\r\nHost: localhost\r\n{}\r\n\r\nhello!", lengths);
+            let error = parse(raw.as_bytes()).err().unwrap();
+            assert!(matches!(error, ParseError::ConflictingContentLength), "{}", lengths);
+            assert_eq!(error.status_code(), StatusCode::BadRequest);
+        }
+        for lengths in ["Content-Length: 5\r\nContent-Length: 5", "Content-Length: 5, 5"] {
+            let raw = format!("POST / HTTP/1.1\r\nHost: localhost\r\n{}\r\n\r\nhello", lengths);
+            let request = parse(raw.as_bytes()).unwrap();
+            assert_eq!((request.content_length(), request.body()), (Some(5), &b"hello"[..]), "{}", lengths);
+        }
+        let error = parse(b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n0\r\n\r\n").err().unwrap();
+        assert!(matches!(error, ParseError::ConflictingFraming));
+        assert!(matches!(parse(b"POST / HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello").err().unwrap(), ParseError::InvalidContentLength));
+    }
+
+    #[test]
+    fn too_many_or_too_long_header_fields_are_431() {
+        let limits = HeaderLimits { max_headers: 2, max_header_bytes: 64, ..HeaderLimits::default() };
+        let many = b"GET / HTTP/1.1\r\nHost: localhost\r\nA: 1\r\nB: 2\r\n\r\n";
+        let long = format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", "a".repeat(64));
+        for raw in [&many[..], long.as_bytes()] {
+            let error = Request::parse(raw, &limits, usize::MAX).err().unwrap();
+            assert!(matches!(error, ParseError::HeadersTooLarge));
+            assert_eq!(error.status_code(), StatusCode::RequestHeaderFieldsTooLarge);
+        }
+        assert!(Request::parse(b"GET / HTTP/1.1\r\nHost: localhost\r\nA: 1\r\n\r\n", &limits, usize::MAX).is_ok());
+    }
+
+    #[test]
+    fn content_length_is_parsed_or_rejected() {
+        let request = parse(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+        assert_eq!(request.content_length(), Some(5));
+        assert_eq!(request.body(), b"hello");
+        assert_eq!(parse(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap().content_length(), None);
+
+        for value in ["-1", "five", "", "99999999999999999999999999"] {
+            let raw = format!("POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n", value);
+            let error = parse(raw.as_bytes()).err().unwrap();
+            assert!(matches!(error, ParseError::InvalidContentLength), "{:?}", value);
+            assert_eq!(error.status_code(), StatusCode::BadRequest);
+        }
+    }
+
+    #[test]
+    fn bare_lf_heads_parse_but_mixed_line_endings_are_rejected() {
+        let request = parse(b"POST /form HTTP/1.1\nHost: localhost\nContent-Length: 5\n\nhello").unwrap();
+        assert_eq!(request.path(), "/form");
+        assert_eq!(request.host(), Some("localhost"));
+        assert_eq!(request.body(), b"hello");
+
+        let mixed: [&[u8]; 4] = [
+            b"GET / HTTP/1.1\r\nHost: localhost\nX-Smuggled: 1\r\n\r\n",
+            b"GET / HTTP/1.1\nHost: localhost\r\nX-Smuggled: 1\n\n",
+            b"GET / HTTP/1.1\nHost: localhost\r\n\n",
+            b"GET / HTTP/1.1\r\nHost: local\rhost\r\n\r\n",
+        ];
+        for raw in mixed {
+            let error = parse(raw).err().unwrap();
+            assert!(matches!(error, ParseError::MixedLineEndings), "{:?}", String::from_utf8_lossy(raw));
+            assert_eq!(error.status_code(), StatusCode::BadRequest);
+        }
+    }
+
+    #[test]
+    fn path_segments_drop_empty_pieces() {
+        let segments = |target| parse(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target).as_bytes()).unwrap().path_segments().join("|");
+        assert_eq!(segments("/api/users/7"), "api|users|7");
+        assert_eq!(segments("/api/users/7/"), "api|users|7");
+        assert_eq!(segments("//api//users?id=7"), "api|users");
+        assert_eq!(segments("/a%2Fb"), "a%2Fb");
+        assert_eq!(segments("/"), "");
+    }
+
+    #[test]
+    fn method_str_is_the_method_as_sent() {
+        for method in ["GET", "PATCH", "OPTIONS"] {
+            let raw = format!("{} / HTTP/1.1\r\nHost: localhost\r\n\r\n", method);
+            assert_eq!(parse(raw.as_bytes()).unwrap().method_str(), method);
+        }
+        assert!(matches!(parse(b"PROPFIND / HTTP/1.1\r\nHost: localhost\r\n\r\n").err().unwrap(), ParseError::UnsupportedMethod));
+    }
+
+    #[test]
+    fn the_query_is_split_off_the_path() {
+        let request = parse(b"GET /?x=1 HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert_eq!(request.path(), "/");
+        assert_eq!(request.raw_query(), Some("x=1"));
+        assert_eq!(request.query_string().and_then(|query| query.first("x")), Some("1"));
+    }
+
+    #[test]
+    fn accept_encodings_come_from_the_header() {
+        let request = parse(b"GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip;q=0.8, br, identity;q=0\r\n\r\n").unwrap();
+        let codings: Vec<_> = request.accept_encodings().into_iter().map(|(coding, q)| format!("{}={}", coding, q)).collect();
+        assert_eq!(codings, ["br=1", "gzip=0.8", "identity=0"]);
+        assert!(parse(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap().accept_encodings().is_empty());
+    }
+
+    #[test]
+    fn empty_and_incomplete_request_lines_are_rejected() {
+        for raw in [&b"\r\n\r\n"[..], b" \r\n\r\n", b"   \r\nHost: localhost\r\n\r\n"] {
+            let error = parse(raw).err().unwrap();
+            assert!(matches!(error, ParseError::EmptyRequestLine), "{:?}", String::from_utf8_lossy(raw));
+            assert_eq!(error.status_code(), StatusCode::BadRequest);
+        }
+        for raw in [&b"GET\r\nHost: localhost\r\n\r\n"[..], b"GET /\r\n\r\n", b" GET / HTTP/1.1\r\n\r\n", b"GET  / HTTP/1.1\r\n\r\n"] {
+            let error = parse(raw).err().unwrap();
+            assert!(matches!(error, ParseError::IncompleteRequestLine), "{:?}", String::from_utf8_lossy(raw));
+            assert_eq!(error.status_code(), StatusCode::BadRequest);
+        }
+    }
+
+    #[test]
+    fn raw_is_exactly_the_bytes_the_request_arrived_as() {
+        let first = b"POST /submit?x=1 HTTP/1.1\r\nHost: localhost\r\nX-Odd:  spaced \r\nContent-Length: 5\r\n\r\nhello";
+        let mut buffer = first.to_vec();
+        buffer.extend_from_slice(b"GET /next HTTP/1.1\r\nHost: localhost\r\n\r\n");
+
+        let mut request = parse(&buffer).unwrap();
+        assert_eq!(request.raw(), None);
+        request.retain_raw(&buffer);
+        // A pipelined request after it is not part of it
+        assert_eq!(request.raw(), Some(&first[..]));
+    }
+}
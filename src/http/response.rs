@@ -1,93 +1,703 @@
-use tokio::io::{Result as IoResult, AsyncWriteExt};
-use super::StatusCode;
+use tokio::io::{Error as IoError, ErrorKind, Result as IoResult, AsyncWriteExt};
+use std::borrow::Cow;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use super::compression::{self, CompressionLevel, ContentEncoding};
+use super::mime;
+use super::{StatusCode, Version};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+// Upper bound on how long a stalled client can hold a connection while we write
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Status line, framing headers, Server and the security block; a typical head fits without regrowing
+const HEAD_CAPACITY_ESTIMATE: usize = 1024;
+
+pub const DEFAULT_SERVER_HEADER: &str = "SecureRustServer/1.0";
+
+// IMF-fixdate (RFC 9110 §5.6.7)
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
 
 #[derive(Debug)]
 pub struct Response {
     status_code: StatusCode,
-    body: Option<String>,
-    content_type: String,
+    body: Option<Vec<u8>>,
+    content_type: Cow<'static, str>,
+    headers: Vec<(Cow<'static, str>, String)>,
+    server_header: Option<Cow<'static, str>>,
+    security_headers: Option<Arc<RenderedSecurityHeaders>>,
+    cache_control: Option<String>,
+    // Request headers the handler negotiated on; Accept-Encoding is added when compression applies
+    vary: Vec<&'static str>,
+    // Set on generic 500 bodies, whose message is rebuilt with the request id once it is known
+    error_reference: Option<&'static str>,
+    // Generated bodies are cut off at SendOptions::max_body_bytes; file contents are sent whole
+    capped: bool,
+}
+
+// Cache-Control for static files: `fixed` for everything when set, otherwise `public, max-age` for
+// the first matching Content-Type prefix. No match keeps the security header default.
+#[derive(Clone, Debug, Default)]
+pub struct CachePolicy {
+    pub fixed: Option<String>,
+    pub max_age: Vec<(&'static str, u64)>,
+}
+
+impl CachePolicy {
+    pub fn cache_control(&self, content_type: &str) -> Option<String> {
+        self.fixed.clone().or_else(|| {
+            self.max_age.iter()
+                .find(|(prefix, _)| content_type.starts_with(prefix))
+                .map(|(_, max_age)| format!("public, max-age={}", max_age))
+        })
+    }
+}
+
+// Configurable part of the security header block; the remaining headers are always sent
+#[derive(Clone, Debug)]
+pub struct SecurityHeaders {
+    pub content_security_policy: Option<String>,
+    pub frame_options: Option<String>,
+    pub hsts: HstsPolicy,
+    pub cache_control: Option<String>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            content_security_policy: Some(
+                "default-src 'self'; script-src 'self' 'unsafe-inline'; style-src 'self' 'unsafe-inline'; img-src 'self' data:; object-src 'none'; base-uri 'self'".to_string()
+            ),
+            frame_options: Some("DENY".to_string()),
+            hsts: HstsPolicy::TlsOnly,
+            cache_control: Some("no-cache, no-store, must-revalidate".to_string()),
+        }
+    }
+}
+
+// Browsers ignore HSTS received over plaintext, so by default it is only sent on TLS connections
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HstsPolicy {
+    Never,
+    TlsOnly,
+    Always,
+}
+
+// SecurityHeaders rendered to wire format once, so each response only copies the block. Everything
+// but Cache-Control is fixed; that one can be overridden per response.
+#[derive(Debug)]
+pub struct RenderedSecurityHeaders {
+    plain: String,
+    secure: String,
+    cache_control: Option<String>,
 }
 
+impl RenderedSecurityHeaders {
+    pub fn new(config: &SecurityHeaders) -> Self {
+        Self {
+            plain: Self::render(config, false),
+            secure: Self::render(config, true),
+            cache_control: config.cache_control.clone(),
+        }
+    }
+
+    fn render(config: &SecurityHeaders, secure: bool) -> String {
+        let mut headers = String::with_capacity(512);
+        headers.push_str("X-Content-Type-Options: nosniff\r\n");
+        if let Some(frame_options) = &config.frame_options {
+            push_header(&mut headers, "X-Frame-Options", frame_options);
+        }
+        headers.push_str(
+            "X-XSS-Protection: 1; mode=block\r\n\
+            Referrer-Policy: strict-origin-when-cross-origin\r\n"
+        );
+        if let Some(csp) = &config.content_security_policy {
+            push_header(&mut headers, "Content-Security-Policy", csp);
+        }
+        if config.hsts == HstsPolicy::Always || (config.hsts == HstsPolicy::TlsOnly && secure) {
+            headers.push_str("Strict-Transport-Security: max-age=31536000; includeSubDomains\r\n");
+        }
+        headers.push_str("Permissions-Policy: geolocation=(), microphone=(), camera=()\r\n");
+        headers
+    }
+
+    fn block(&self, secure: bool) -> &str {
+        if secure { &self.secure } else { &self.plain }
+    }
+}
+
+// Connection-level facts the response needs when it is written out
+#[derive(Clone, Copy, Debug)]
+pub struct SendOptions {
+    pub version: Version,
+    pub keep_alive: bool,
+    pub secure: bool,
+    pub encoding: ContentEncoding,
+    pub compression_level: CompressionLevel,
+    // Answering a HEAD: the head describes the body a GET would get, down to Content-Length and
+    // Content-Encoding, but the body itself is not sent
+    pub head_only: bool,
+    // Generated bodies over this are truncated to it, with a warning; see Response::uncapped
+    pub max_body_bytes: usize,
+}
+
+impl SendOptions {
+    pub fn new(version: Version, keep_alive: bool, secure: bool) -> Self {
+        Self {
+            version,
+            keep_alive,
+            secure,
+            encoding: ContentEncoding::Identity,
+            compression_level: CompressionLevel::default(),
+            head_only: false,
+            max_body_bytes: usize::MAX,
+        }
+    }
+
+    pub fn with_encoding(mut self, encoding: ContentEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn with_compression_level(mut self, compression_level: CompressionLevel) -> Self {
+        self.compression_level = compression_level;
+        self
+    }
+
+    pub fn with_head_only(mut self, head_only: bool) -> Self {
+        self.head_only = head_only;
+        self
+    }
+
+    pub fn with_max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.max_body_bytes = max_body_bytes.max(1);
+        self
+    }
+
+    // For responses sent before a request could be parsed
+    pub fn closing() -> Self {
+        Self::new(Version::Http11, false, false)
+    }
+}
+
+static DEFAULT_SECURITY_HEADERS: LazyLock<RenderedSecurityHeaders> = LazyLock::new(|| RenderedSecurityHeaders::new(&SecurityHeaders::default()));
+
 impl Response {
     pub fn new(status_code: StatusCode, body: Option<String>) -> Self {
-        Response { 
-            status_code, 
-            body,
-            content_type: "text/plain; charset=utf-8".to_string(),
-        }
+        Self::build(status_code, body.map(String::into_bytes), Cow::Borrowed("text/plain; charset=utf-8"))
     }
 
     pub fn html(status_code: StatusCode, body: Option<String>) -> Self {
-        Response {
-            status_code,
-            body,
-            content_type: "text/html; charset=utf-8".to_string(),
+        Self::build(status_code, body.map(String::into_bytes), Cow::Borrowed("text/html; charset=utf-8"))
+    }
+
+    // Static strings are stored as-is, so common content types cost no allocation
+    pub fn with_content_type(status_code: StatusCode, body: Option<String>, content_type: impl Into<Cow<'static, str>>) -> Self {
+        Self::build(status_code, body.map(String::into_bytes), content_type.into())
+    }
+
+    pub fn with_bytes(status_code: StatusCode, body: Vec<u8>, content_type: impl Into<Cow<'static, str>>) -> Self {
+        Self::build(status_code, Some(body), content_type.into())
+    }
+
+    // The whole file as the body, typed by its extension, with Last-Modified from its mtime and
+    // Cache-Control from `cache`. Content-Length is filled in when the response is sent.
+    pub async fn from_file(status_code: StatusCode, path: impl AsRef<Path>, cache: &CachePolicy) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let body = tokio::fs::read(path).await?;
+        let modified = tokio::fs::metadata(path).await?.modified()?;
+        let content_type = mime::content_type_for(&path.to_string_lossy());
+        let last_modified = DateTime::<Utc>::from(modified).format(HTTP_DATE).to_string();
+        Ok(Self::with_bytes(status_code, body, content_type)
+            .with_cache_policy(cache)
+            .with_header("Last-Modified", &last_modified)
+            .uncapped())
+    }
+
+    // Exempts the body from the response cap, for static content whose size is known up front
+    pub fn uncapped(mut self) -> Self {
+        self.capped = false;
+        self
+    }
+
+    // Serialized with serde, so strings taken from the request are always escaped correctly
+    pub fn json<T: Serialize + ?Sized>(status_code: StatusCode, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Self::with_bytes(status_code, body, "application/json; charset=utf-8"),
+            Err(e) => {
+                eprintln!("Failed to serialize JSON response: {}", e);
+                Self::with_bytes(
+                    StatusCode::InternalServerError,
+                    br#"{"success": false, "data": null, "message": "Internal server error"}"#.to_vec(),
+                    "application/json; charset=utf-8",
+                )
+            },
         }
     }
 
-    pub fn with_content_type(status_code: StatusCode, body: Option<String>, content_type: String) -> Self {
+    fn build(status_code: StatusCode, body: Option<Vec<u8>>, content_type: Cow<'static, str>) -> Self {
         Response {
             status_code,
             body,
             content_type,
+            headers: Vec::new(),
+            server_header: Some(Cow::Borrowed(DEFAULT_SERVER_HEADER)),
+            security_headers: None,
+            cache_control: None,
+            vary: Vec::new(),
+            error_reference: None,
+            capped: true,
         }
     }
 
-    pub fn security_error(message: &str) -> Self {
-        Response::new(
-            StatusCode::BadRequest,
-            Some(format!("Security violation: {}", message))
-        )
+    // Declares that the response depends on a request header, so caches key on it
+    pub fn with_vary(mut self, header: &'static str) -> Self {
+        if !self.vary.iter().any(|existing| existing.eq_ignore_ascii_case(header)) {
+            self.vary.push(header);
+        }
+        self
+    }
+
+    pub(crate) fn with_error_reference(mut self, message: &'static str) -> Self {
+        self.error_reference = Some(message);
+        self
+    }
+
+    // Tags the response with its request id. A generic 500 also quotes the id in its body, so a
+    // user reporting the error can hand support something to search the logs for.
+    pub fn with_request_id(mut self, request_id: &str) -> Self {
+        if let Some(message) = self.error_reference.take() {
+            self.body = Some(if self.content_type.starts_with("application/json") {
+                serde_json::json!({ "success": false, "data": null, "message": message, "request_id": request_id }).to_string()
+            } else {
+                format!("{} (request id {})", message, request_id)
+            }.into_bytes());
+        }
+        self.with_header("X-Request-Id", request_id)
+    }
+
+    pub fn with_header(mut self, name: impl Into<Cow<'static, str>>, value: &str) -> Self {
+        self.headers.push((name.into(), value.to_string()));
+        self
+    }
+
+    // None suppresses the Server banner entirely
+    pub fn with_server_header(mut self, server_header: Option<&str>) -> Self {
+        self.server_header = server_header.map(|value| Cow::Owned(value.to_string()));
+        self
+    }
+
+    // Applies a header set unless this response already carries its own
+    pub fn with_default_security_headers(mut self, security_headers: &Arc<RenderedSecurityHeaders>) -> Self {
+        if self.security_headers.is_none() {
+            self.security_headers = Some(Arc::clone(security_headers));
+        }
+        self
+    }
+
+    pub fn with_security_headers(mut self, security_headers: SecurityHeaders) -> Self {
+        self.security_headers = Some(Arc::new(RenderedSecurityHeaders::new(&security_headers)));
+        self
+    }
+
+    // Per-response cache policy, e.g. to let static assets be cached while API responses stay no-store
+    pub fn with_cache_control(mut self, cache_control: &str) -> Self {
+        self.cache_control = Some(cache_control.to_string());
+        self
+    }
+
+    // Replaces any Cache-Control set so far with what `cache` prescribes for this content type
+    pub fn with_cache_policy(mut self, cache: &CachePolicy) -> Self {
+        self.cache_control = cache.cache_control(&self.content_type);
+        self
+    }
+
+    // Changes the type chosen at construction (e.g. once the body has been sniffed); the cache
+    // policy is applied again because it depends on the type
+    pub fn retyped(mut self, content_type: impl Into<Cow<'static, str>>, cache: &CachePolicy) -> Self {
+        self.content_type = content_type.into();
+        self.with_cache_policy(cache)
+    }
+
+    pub fn map_body(mut self, f: impl FnOnce(Vec<u8>) -> Vec<u8>) -> Self {
+        self.body = self.body.map(f);
+        self
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        self.status_code
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    pub fn body(&self) -> &[u8] {
+        self.body.as_deref().unwrap_or_default()
     }
 
-    pub fn rate_limited() -> Self {
+    pub fn service_unavailable(retry_after_secs: u64) -> Self {
         Response::new(
-            StatusCode::TooManyRequests,
-            Some("Rate limit exceeded. Please try again later.".to_string())
-        )
+            StatusCode::ServiceUnavailable,
+            Some("Service is starting up. Please try again shortly.".to_string())
+        ).with_header("Retry-After", &retry_after_secs.to_string())
     }
 
-    fn get_security_headers(&self) -> String {
-        // Comprehensive security headers
-        format!(
-            "X-Content-Type-Options: nosniff\r\n\
-            X-Frame-Options: DENY\r\n\
-            X-XSS-Protection: 1; mode=block\r\n\
-            Referrer-Policy: strict-origin-when-cross-origin\r\n\
-            Content-Security-Policy: default-src 'self'; script-src 'self' 'unsafe-inline'; style-src 'self' 'unsafe-inline'; img-src 'self' data:; object-src 'none'; base-uri 'self'\r\n\
-            Strict-Transport-Security: max-age=31536000; includeSubDomains\r\n\
-            Permissions-Policy: geolocation=(), microphone=(), camera=()\r\n\
-            Cache-Control: no-cache, no-store, must-revalidate\r\n\
-            Pragma: no-cache\r\n\
-            Expires: 0\r\n"
-        )
+    fn write_security_headers(&self, out: &mut Vec<u8>, secure: bool) {
+        let rendered = self.security_headers.as_deref().unwrap_or(&DEFAULT_SECURITY_HEADERS);
+        out.extend_from_slice(rendered.block(secure).as_bytes());
+
+        if let Some(cache_control) = self.cache_control.as_ref().or(rendered.cache_control.as_ref()) {
+            write_header(out, "Cache-Control", cache_control);
+            // HTTP/1.0 caches only understand these
+            if cache_control.contains("no-store") || cache_control.contains("no-cache") {
+                out.extend_from_slice(b"Pragma: no-cache\r\nExpires: 0\r\n");
+            }
+        }
+    }
+
+    pub async fn send(&self, stream: &mut (impl AsyncWriteExt + Unpin), options: SendOptions) -> IoResult<()> {
+        let (body, encoding, compressible) = self.prepare_body(&options);
+
+        // write_all retries partial writes; the timeout covers clients that stop reading entirely
+        let write = async {
+            if options.head_only {
+                stream.write_all(&self.encode_head(&options, body.len(), encoding, compressible, 0)?).await?;
+            } else {
+                // One pre-sized buffer for the whole response, so it goes out in a single write
+                let mut response = self.encode_head(&options, body.len(), encoding, compressible, body.len())?;
+                response.extend_from_slice(&body);
+                stream.write_all(&response).await?;
+            }
+            stream.flush().await
+        };
+
+        match tokio::time::timeout(WRITE_TIMEOUT, write).await {
+            Ok(result) => result,
+            Err(_) => Err(IoError::new(ErrorKind::TimedOut, "write timed out")),
+        }
+    }
+
+    // The complete HTTP/1 message and the length of its head; the HTTP/2 layer reuses the head's fields
+    pub(crate) fn encode(&self, options: &SendOptions) -> IoResult<(Vec<u8>, usize)> {
+        let (body, encoding, compressible) = self.prepare_body(options);
+        let mut response = self.encode_head(options, body.len(), encoding, compressible, body.len())?;
+        let head_len = response.len();
+        if !options.head_only {
+            response.extend_from_slice(&body);
+        }
+        Ok((response, head_len))
     }
 
-    pub async fn send(&self, stream: &mut (impl AsyncWriteExt + Unpin)) -> IoResult<()> {
-        let body = match &self.body {
+    // The body as it goes on the wire, the encoding applied to it, and whether it was a candidate
+    fn prepare_body(&self, options: &SendOptions) -> (Cow<'_, [u8]>, Option<ContentEncoding>, bool) {
+        let body: &[u8] = match &self.body {
             Some(b) => b,
-            None => "",
+            None => &[],
         };
+        // A safety valve for generated bodies: the excess is dropped rather than built into the response
+        let body = if self.capped && body.len() > options.max_body_bytes {
+            eprintln!("Response body of {} bytes exceeds the {} byte cap; truncating it", body.len(), options.max_body_bytes);
+            &body[..options.max_body_bytes]
+        } else {
+            body
+        };
+
+        // Handlers that set their own Content-Encoding have already encoded the body
+        let compressible = compression::is_compressible(&self.content_type, body.len())
+            && self.header("Content-Encoding").is_none();
+        let compressed = match options.encoding {
+            ContentEncoding::Identity => None,
+            encoding if compressible => compression::compress(body, encoding, options.compression_level).ok().map(|bytes| (bytes, encoding)),
+            _ => None,
+        };
+        match compressed {
+            Some((bytes, encoding)) => (Cow::Owned(bytes), Some(encoding), compressible),
+            None => (Cow::Borrowed(body), None, compressible),
+        }
+    }
 
-        let security_headers = self.get_security_headers();
+    fn encode_head(
+        &self,
+        options: &SendOptions,
+        body_len: usize,
+        encoding: Option<ContentEncoding>,
+        compressible: bool,
+        extra_capacity: usize,
+    ) -> IoResult<Vec<u8>> {
+        let extra_len: usize = self.headers.iter().map(|(name, value)| name.len() + value.len() + 4).sum();
+        let mut response = Vec::with_capacity(HEAD_CAPACITY_ESTIMATE + extra_len + extra_capacity);
 
-        let response = format!(
-            "HTTP/1.1 {} {}\r\n\
+        write!(response, "{} {} {}\r\nContent-Type: {}\r\n", options.version, self.status_code, self.status_code.reason_phrase(), self.content_type)?;
+        write!(response, "Content-Length: {}\r\n", body_len)?;
+        write_header(&mut response, "Connection", if options.keep_alive { "keep-alive" } else { "close" });
+        if let Some(encoding) = encoding {
+            write_header(&mut response, "Content-Encoding", encoding.as_str());
+        }
+        // Compressible bodies vary by Accept-Encoding whether or not this client got them compressed
+        let vary_encoding = compressible && !self.vary.iter().any(|header| header.eq_ignore_ascii_case("Accept-Encoding"));
+        if vary_encoding || !self.vary.is_empty() {
+            response.extend_from_slice(b"Vary: ");
+            for (i, header) in self.vary.iter().chain(vary_encoding.then_some(&"Accept-Encoding")).enumerate() {
+                if i > 0 {
+                    response.extend_from_slice(b", ");
+                }
+                response.extend_from_slice(header.as_bytes());
+            }
+            response.extend_from_slice(b"\r\n");
+        }
+        if let Some(server) = &self.server_header {
+            write_header(&mut response, "Server", server);
+        }
+        self.write_security_headers(&mut response, options.secure);
+        for (name, value) in &self.headers {
+            write_header(&mut response, name, value);
+        }
+        response.extend_from_slice(b"\r\n");
+        Ok(response)
+    }
+}
+
+fn write_header(out: &mut Vec<u8>, name: &str, value: &str) {
+    out.extend_from_slice(name.as_bytes());
+    out.extend_from_slice(b": ");
+    out.extend_from_slice(value.as_bytes());
+    out.extend_from_slice(b"\r\n");
+}
+
+fn push_header(headers: &mut String, name: &str, value: &str) {
+    headers.push_str(name);
+    headers.push_str(": ");
+    headers.push_str(value);
+    headers.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded_head(response: &Response, options: SendOptions) -> String {
+        let (encoded, head_len) = response.encode(&options).unwrap();
+        String::from_utf8(encoded[..head_len].to_vec()).unwrap()
+    }
+
+    fn header_values<'a>(head: &'a str, name: &str) -> Vec<&'a str> {
+        head.lines()
+            .filter_map(|line| line.split_once(": "))
+            .filter(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    fn text(len: usize) -> Response {
+        Response::new(StatusCode::Ok, Some("a".repeat(len)))
+    }
+
+    // The head as the original per-request `format!` assembled it
+    #[test]
+    fn head_matches_the_format_built_layout() {
+        let security_headers = SecurityHeaders {
+            content_security_policy: Some("default-src 'none'".to_string()),
+            frame_options: Some("SAMEORIGIN".to_string()),
+            hsts: HstsPolicy::TlsOnly,
+            cache_control: Some("no-store".to_string()),
+        };
+        let response = Response::new(StatusCode::Created, Some("hello".to_string()))
+            .with_server_header(Some("Test/1.0"))
+            .with_security_headers(security_headers)
+            .with_header("X-Custom", "one")
+            .with_header("Location", "/items/1");
+
+        let security_lines = "X-Content-Type-Options: nosniff\r\n\
+            X-Frame-Options: SAMEORIGIN\r\n\
+            X-XSS-Protection: 1; mode=block\r\n\
+            Referrer-Policy: strict-origin-when-cross-origin\r\n\
+            Content-Security-Policy: default-src 'none'\r\n\
+            Strict-Transport-Security: max-age=31536000; includeSubDomains\r\n\
+            Permissions-Policy: geolocation=(), microphone=(), camera=()\r\n\
+            Cache-Control: no-store\r\n\
+            Pragma: no-cache\r\nExpires: 0\r\n";
+        let expected = format!(
+            "{} {} {}\r\n\
             Content-Type: {}\r\n\
             Content-Length: {}\r\n\
-            Connection: close\r\n\
-            Server: SecureRustServer/1.0\r\n\
+            Connection: {}\r\n\
             {}\
-            \r\n{}",
-            self.status_code,
-            self.status_code.reason_phrase(),
-            self.content_type,
-            body.len(),
-            security_headers,
-            body
+            {}\
+            {}\
+            {}\
+            \r\n",
+            "HTTP/1.1", 201, "Created", "text/plain; charset=utf-8", 5, "keep-alive",
+            "",
+            "Server: Test/1.0\r\n",
+            security_lines,
+            "X-Custom: one\r\nLocation: /items/1\r\n",
         );
+        assert_eq!(encoded_head(&response, SendOptions::new(Version::Http11, true, true)), expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_stalled_reader_times_the_write_out() {
+        let (mut writer, _reader) = tokio::io::duplex(64);
+        let error = text(64 * 1024).send(&mut writer, SendOptions::closing()).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn custom_csp_is_sent_and_cacheable_responses_skip_no_store() {
+        let headers = SecurityHeaders { content_security_policy: Some("default-src https:".to_string()), ..SecurityHeaders::default() };
+        let head = encoded_head(&text(10).with_security_headers(headers), SendOptions::closing());
+        assert_eq!(header_values(&head, "Content-Security-Policy"), ["default-src https:"]);
+        assert_eq!(header_values(&head, "Cache-Control"), ["no-cache, no-store, must-revalidate"]);
+
+        let head = encoded_head(&text(10).with_cache_control("public, max-age=60"), SendOptions::closing());
+        assert_eq!(header_values(&head, "Cache-Control"), ["public, max-age=60"]);
+        assert!(header_values(&head, "Pragma").is_empty() && header_values(&head, "Expires").is_empty(), "{}", head);
+    }
+
+    #[test]
+    fn hsts_follows_the_transport_unless_forced() {
+        let hsts = |policy, secure| {
+            let headers = SecurityHeaders { hsts: policy, ..SecurityHeaders::default() };
+            let head = encoded_head(&text(10).with_security_headers(headers), SendOptions::new(Version::Http11, false, secure));
+            !header_values(&head, "Strict-Transport-Security").is_empty()
+        };
+        assert!(!hsts(HstsPolicy::TlsOnly, false));
+        assert!(hsts(HstsPolicy::TlsOnly, true));
+        assert!(hsts(HstsPolicy::Always, false));
+        assert!(!hsts(HstsPolicy::Never, true));
+        assert!(!encoded_head(&text(10), SendOptions::closing()).contains("Strict-Transport-Security"));
+    }
+
+    #[test]
+    fn static_content_types_and_header_names_are_not_copied() {
+        let response = Response::with_bytes(StatusCode::Ok, Vec::new(), mime::content_type_for("assets/Style.CSS"))
+            .with_header("Accept-Ranges", "bytes");
+        assert!(matches!(response.content_type, Cow::Borrowed("text/css; charset=utf-8")));
+        assert!(matches!(response.headers[..], [(Cow::Borrowed("Accept-Ranges"), _)]));
+        // Only the last path segment's extension counts
+        assert_eq!(mime::content_type_for("assets.v2/readme"), "application/octet-stream");
+    }
+
+    // Records the size of every write, to show how a response was split up
+    #[derive(Default)]
+    struct Writes {
+        data: Vec<u8>,
+        sizes: Vec<usize>,
+    }
+
+    impl tokio::io::AsyncWrite for Writes {
+        fn poll_write(mut self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>, buf: &[u8]) -> std::task::Poll<IoResult<usize>> {
+            self.data.extend_from_slice(buf);
+            self.sizes.push(buf.len());
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<IoResult<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<IoResult<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    async fn send(response: &Response, options: SendOptions) -> Writes {
+        let mut writes = Writes::default();
+        response.send(&mut writes, options).await.unwrap();
+        writes
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_truncated_to_the_cap() {
+        let body: String = (0..10_000).map(|i| char::from(b'a' + (i % 26) as u8)).collect();
+        let response = Response::with_content_type(StatusCode::Ok, Some(body.clone()), "application/octet-stream");
+        for version in [Version::Http11, Version::Http10] {
+            let writes = send(&response, SendOptions::new(version, false, false).with_max_body_bytes(4096)).await;
+
+            let wire = String::from_utf8(writes.data).unwrap();
+            let (head, sent) = wire.split_once("\r\n\r\n").unwrap();
+            assert_eq!(header_values(head, "Content-Length"), ["4096"]);
+            assert!(!head.contains("Transfer-Encoding"));
+            assert_eq!(sent, &body[..4096]);
+            assert_eq!(writes.sizes.len(), 1);
+        }
+        // HTTP/2 goes through encode, which applies the same cap
+        let (encoded, head_len) = response.encode(&SendOptions::closing().with_max_body_bytes(4096)).unwrap();
+        assert_eq!(encoded.len() - head_len, 4096);
+    }
+
+    #[tokio::test]
+    async fn file_bodies_are_not_capped() {
+        let response = Response::with_content_type(StatusCode::Ok, Some("x".repeat(10_000)), "text/plain").uncapped();
+        let writes = send(&response, SendOptions::closing().with_max_body_bytes(4096)).await;
+        let wire = String::from_utf8(writes.data).unwrap();
+        assert!(wire.contains("Content-Length: 10000\r\n") && wire.ends_with(&"x".repeat(10_000)));
+    }
+
+    #[tokio::test]
+    async fn bodies_within_the_cap_go_out_in_one_write() {
+        let response = Response::new(StatusCode::Ok, Some("small".to_string()));
+        let writes = send(&response, SendOptions::closing().with_max_body_bytes(4096)).await;
+        assert_eq!(writes.sizes.len(), 1);
+        let wire = String::from_utf8(writes.data).unwrap();
+        assert!(wire.contains("Content-Length: 5\r\n") && wire.ends_with("\r\n\r\nsmall"));
+    }
 
-        stream.write_all(response.as_bytes()).await?;
-        stream.flush().await
+    #[test]
+    fn compressed_responses_vary_on_accept_encoding() {
+        let gzip = SendOptions::closing().with_encoding(ContentEncoding::Gzip);
+        let head = encoded_head(&text(1024), gzip);
+        assert_eq!(header_values(&head, "Content-Encoding"), ["gzip"]);
+        assert_eq!(header_values(&head, "Vary"), ["Accept-Encoding"]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn negotiated_responses_vary_on_what_they_negotiated() {
+        let head = encoded_head(&text(10).with_vary("Accept"), SendOptions::closing());
+        assert_eq!(header_values(&head, "Vary"), ["Accept"]);
+
+        let head = encoded_head(&text(1024).with_vary("Accept"), SendOptions::closing());
+        assert_eq!(header_values(&head, "Vary"), ["Accept, Accept-Encoding"]);
+        assert!(header_values(&encoded_head(&text(10), SendOptions::closing()), "Vary").is_empty());
+    }
+
+    #[test]
+    fn accept_encoding_is_listed_once() {
+        let response = text(1024).with_vary("accept-encoding").with_vary("Accept-Encoding");
+        let head = encoded_head(&response, SendOptions::closing().with_encoding(ContentEncoding::Gzip));
+        assert_eq!(header_values(&head, "Vary"), ["accept-encoding"]);
+    }
+
+    #[tokio::test]
+    async fn files_are_typed_sized_and_cached_by_extension() {
+        let path = std::env::temp_dir().join(format!("rust-server-from-file-{}.css", std::process::id()));
+        std::fs::write(&path, "body { color: red; }").unwrap();
+        let cache = CachePolicy { fixed: None, max_age: vec![("text/css", 3600)] };
+        let response = Response::from_file(StatusCode::Ok, &path, &cache).await;
+        std::fs::remove_file(&path).unwrap();
+
+        let head = encoded_head(&response.unwrap(), SendOptions::closing());
+        assert_eq!(header_values(&head, "Content-Type"), ["text/css; charset=utf-8"]);
+        assert_eq!(header_values(&head, "Content-Length"), ["20"]);
+        assert_eq!(header_values(&head, "Cache-Control"), ["public, max-age=3600"]);
+        let last_modified = header_values(&head, "Last-Modified");
+        assert!(DateTime::parse_from_rfc2822(last_modified[0]).is_ok(), "{:?}", last_modified);
+    }
+
+    #[tokio::test]
+    async fn missing_files_are_io_errors() {
+        let path = std::env::temp_dir().join("rust-server-from-file-missing.css");
+        let error = Response::from_file(StatusCode::Ok, path, &CachePolicy::default()).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+    // Only produced by the HTTP/2 connection layer, never parsed from a request line
+    Http2,
+}
+
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Http10 => "HTTP/1.0",
+            Self::Http11 => "HTTP/1.1",
+            Self::Http2 => "HTTP/2",
+        }
+    }
+
+    // HTTP/1.0 connections close after each response unless the client asks otherwise
+    pub fn keep_alive_by_default(&self) -> bool {
+        match self {
+            Self::Http10 => false,
+            Self::Http11 | Self::Http2 => true,
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = VersionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HTTP/1.0" => Ok(Self::Http10),
+            "HTTP/1.1" => Ok(Self::Http11),
+            _ if s.starts_with("HTTP/") => Err(VersionError::Unsupported),
+            _ => Err(VersionError::Invalid),
+        }
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+pub enum VersionError {
+    Invalid,
+    Unsupported,
+}
@@ -0,0 +1,16 @@
+#![allow(dead_code)]
+
+// Everything but the startup code in main.rs, so benches can drive the real handler
+pub mod access_log;
+#[cfg(feature = "embedded")]
+pub mod embedded;
+pub mod error;
+pub mod http;
+pub mod middleware;
+pub mod redirect;
+pub mod server;
+pub mod website_handler;
+pub mod security;
+pub mod template;
+pub mod validation;
+pub mod watcher;
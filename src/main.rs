@@ -1,28 +1,184 @@
-#![allow(dead_code)]
-
-use server::Server;
+use rust_server::access_log::{LogFormat, LogSampling};
+use rust_server::redirect::HttpsRedirect;
+use rust_server::server::Server;
 use std::env;
-use website_handler::WebsiteHandler;
-use security::SecurityConfig;
+use std::path::PathBuf;
+use rust_server::website_handler::WebsiteHandler;
+use rust_server::security::{CorsConfig, Role, SecurityConfig, TrailingSlash};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use rust_server::watcher::{DirectoryWatcher, FileChange};
 
-mod http;
-mod server;
-mod website_handler;
-mod security;
+// The runtime is built after configuration so WORKER_THREADS can size it
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let (server, canonical_path, security_config) = configure()?;
+    let runtime = server.runtime()?;
+    runtime.block_on(run(server, canonical_path, security_config))
+}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+// Everything read from the environment, before the runtime exists
+fn configure() -> Result<(Server, PathBuf, SecurityConfig), Box<dyn std::error::Error>> {
     let default_path = format!("{}/public", env!("CARGO_MANIFEST_DIR"));
     let public_path = env::var("PUBLIC_PATH").unwrap_or(default_path);
     
+    #[cfg(not(feature = "embedded"))]
     let canonical_path = std::fs::canonicalize(&public_path)
         .map_err(|_| format!("Invalid public path: {}", public_path))?;
+    // Assets come from the binary, so the directory only matters to the dev-mode watcher
+    #[cfg(feature = "embedded")]
+    let canonical_path = std::fs::canonicalize(&public_path).unwrap_or_else(|_| public_path.clone().into());
     
-    println!("Server starting on 127.0.0.1:8080");
+    // IPv6 addresses take brackets, e.g. "[::1]:8080"
+    let bind_addr = env::var("BIND_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
+    println!("Server starting on {}", bind_addr);
+    #[cfg(not(feature = "embedded"))]
     println!("Serving files from: {}", canonical_path.display());
+    #[cfg(feature = "embedded")]
+    println!("Serving embedded assets compiled from public/");
     println!("Security features enabled: Rate limiting, Security headers, File type validation");
     
-    let security_config = SecurityConfig::default();
-    let server = Server::new("127.0.0.1:8080".to_string());
-    server.run(WebsiteHandler::new(canonical_path, security_config)).await
+    // Tokens come from the environment so they never live in the source tree
+    let api_tokens = [("API_ADMIN_TOKEN", Role::Admin), ("API_USER_TOKEN", Role::User)]
+        .into_iter()
+        .filter_map(|(var, role)| env::var(var).ok().filter(|token| !token.is_empty()).map(|token| (token, role)))
+        .collect();
+
+    // Optional JSON list of {"from", "to", "prefix"} URL rewrites
+    let rewrites = match env::var("REWRITES_FILE") {
+        Ok(file) => {
+            let json = std::fs::read_to_string(&file).map_err(|e| format!("Cannot read {}: {}", file, e))?;
+            serde_json::from_str(&json).map_err(|e| format!("Invalid rewrites in {}: {}", file, e))?
+        },
+        Err(_) => Vec::new(),
+    };
+
+    let log_format = match env::var("LOG_FORMAT") {
+        Ok(format) => format.parse::<LogFormat>()?,
+        Err(_) => LogFormat::default(),
+    };
+
+    // "all", "errors", or N to log one in N successful requests
+    let log_sampling = match env::var("LOG_SAMPLING") {
+        Ok(sampling) => sampling.parse::<LogSampling>()?,
+        Err(_) => LogSampling::default(),
+    };
+
+    let trailing_slash = match env::var("TRAILING_SLASH") {
+        Ok(policy) => policy.parse::<TrailingSlash>()?,
+        Err(_) => TrailingSlash::default(),
+    };
+
+    // Comma-separated CIDRs, e.g. "10.0.0.0/8,127.0.0.1"
+    let rate_limit_exempt = match env::var("RATE_LIMIT_EXEMPT") {
+        Ok(list) => list.split(',').filter(|entry| !entry.trim().is_empty()).map(str::parse).collect::<Result<_, _>>()?,
+        Err(_) => Vec::new(),
+    };
+
+    // Comma-separated file names, e.g. "index.html,default.html"
+    let default_documents = env::var("DEFAULT_DOCUMENTS").ok().map(|list| {
+        list.split(',').map(str::trim).filter(|name| !name.is_empty()).map(str::to_string).collect()
+    });
+
+    // Comma-separated origins (or "*") allowed cross-origin access; CORS_MAX_AGE is in seconds
+    let cors = match env::var("CORS_ORIGINS") {
+        Ok(origins) => {
+            let mut cors = CorsConfig::new(origins.split(',').map(str::trim).filter(|origin| !origin.is_empty()).map(str::to_string).collect());
+            if let Ok(max_age) = env::var("CORS_MAX_AGE") {
+                let secs = max_age.parse().map_err(|_| format!("Invalid CORS_MAX_AGE: {}", max_age))?;
+                cors.max_age = Duration::from_secs(secs);
+            }
+            Some(cors)
+        },
+        Err(_) => None,
+    };
+
+    // 1 (fastest) to 9 (smallest); anything outside that range is clamped
+    let compression_level = match env::var("COMPRESSION_LEVEL") {
+        Ok(level) => level.parse::<u32>().map_err(|_| format!("Invalid COMPRESSION_LEVEL: {}", level))?,
+        Err(_) => SecurityConfig::default().compression_level,
+    };
+
+    // Created if missing; uploads are disabled unless this is set
+    let upload_dir = match env::var("UPLOAD_DIR") {
+        Ok(dir) => {
+            std::fs::create_dir_all(&dir).map_err(|e| format!("Cannot create upload directory {}: {}", dir, e))?;
+            Some(std::fs::canonicalize(&dir).map_err(|e| format!("Invalid upload directory {}: {}", dir, e))?)
+        },
+        Err(_) => None,
+    };
+
+    let security_config = SecurityConfig {
+        dev_mode: env::var("DEV_MODE").is_ok_and(|value| value == "1" || value == "true"),
+        api_tokens,
+        rewrites,
+        log_format,
+        log_sampling,
+        rate_limit_exempt,
+        cors,
+        enable_api_routes: !env::var("DISABLE_API_ROUTES").is_ok_and(|value| value == "1" || value == "true"),
+        upload_dir,
+        clean_urls: env::var("CLEAN_URLS").is_ok_and(|value| value == "1" || value == "true"),
+        compression_level,
+        follow_symlinks: env::var("FOLLOW_SYMLINKS").is_ok_and(|value| value == "1" || value == "true"),
+        trailing_slash,
+        directory_slash_redirect: env::var("DIRECTORY_SLASH_REDIRECT").is_ok_and(|value| value == "1" || value == "true"),
+        default_documents: default_documents.unwrap_or_else(|| SecurityConfig::default().default_documents),
+        tls_terminated: env::var("TLS_TERMINATED").is_ok_and(|value| value == "1" || value == "true"),
+        ..SecurityConfig::default()
+    };
+
+    let mut builder = Server::builder()
+        .bind(bind_addr)
+        .security_config(&security_config);
+    // Unset keeps tokio's default of one worker per core; 1 runs everything on a single worker
+    // for small deployments
+    if let Ok(workers) = env::var("WORKER_THREADS") {
+        let workers = workers.parse::<usize>().ok().filter(|&n| n > 0)
+            .ok_or_else(|| format!("Invalid WORKER_THREADS: {}", workers))?;
+        println!("Using {} worker thread(s)", workers);
+        builder = builder.worker_threads(workers);
+    }
+
+    Ok((builder.build(), canonical_path, security_config))
+}
+
+async fn run(server: Server, canonical_path: PathBuf, security_config: SecurityConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let dev_mode = security_config.dev_mode;
+    let (max_connections_per_ip, max_body_bytes) = (security_config.max_connections_per_ip, security_config.max_body_bytes);
+    let handler = WebsiteHandler::new(canonical_path.clone(), security_config);
+
+    if dev_mode {
+        println!("Dev mode: watching {} for changes", canonical_path.display());
+        let reload = handler.reload_trigger();
+        DirectoryWatcher::new(canonical_path, Duration::from_millis(500)).spawn(move |change| {
+            match change {
+                FileChange::Added(path) => println!(" File added: {}", path.display()),
+                FileChange::Modified(path) => println!(" File modified: {}", path.display()),
+                FileChange::Removed(path) => println!(" File removed: {}", path.display()),
+            }
+            reload.fetch_add(1, Ordering::Release);
+        });
+    }
+
+    // Optional plaintext listener (e.g. 0.0.0.0:80) that only redirects to HTTPS_PORT
+    if let Ok(redirect_addr) = env::var("HTTPS_REDIRECT_ADDR") {
+        let https_port = match env::var("HTTPS_PORT") {
+            Ok(port) => port.parse().map_err(|_| format!("Invalid HTTPS_PORT: {}", port))?,
+            Err(_) => 443,
+        };
+        // Bound here so an inherited LISTEN_FDS socket stays with the main server
+        let listener = std::net::TcpListener::bind(&redirect_addr)
+            .map_err(|e| format!("Cannot bind redirect listener {}: {}", redirect_addr, e))?;
+        println!("Redirecting plaintext requests on {} to HTTPS port {}", redirect_addr, https_port);
+        let redirect_server = Server::new(redirect_addr)
+            .with_max_connections_per_ip(max_connections_per_ip)
+            .with_max_body_bytes(max_body_bytes);
+        tokio::spawn(async move {
+            if let Err(e) = redirect_server.run_with_listener(listener, HttpsRedirect::new(https_port)).await {
+                eprintln!("Redirect listener failed: {}", e);
+            }
+        });
+    }
+
+    server.run(handler.with_default_middleware()).await
 }
\ No newline at end of file
@@ -0,0 +1,346 @@
+use crate::error::ServerError;
+use crate::http::{Method, Request, Response, StatusCode};
+use crate::security::{CorsConfig, RateLimiter, SecurityConfig, SecurityValidator};
+use crate::server::{BodyStream, Handler};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+// A cross-cutting step around a handler. `before` may answer the request itself or reject it,
+// skipping everything inside it; `after` sees every response that passes back out through it.
+pub trait Middleware: Send + Sync + 'static {
+    fn before(&self, _request: &Request, _client_ip: SocketAddr) -> Result<Option<Response>, Rejection> {
+        Ok(None)
+    }
+
+    fn after(&self, _request: &Request, response: Response) -> Response {
+        response
+    }
+}
+
+// Why `before` refused a request. The stack answers it through the wrapped handler, so overrides of
+// handle_error and handle_security_violation apply to middleware rejections as well.
+#[derive(Debug)]
+pub enum Rejection {
+    Error(ServerError),
+    SecurityViolation(&'static str),
+}
+
+impl From<ServerError> for Rejection {
+    fn from(error: ServerError) -> Self {
+        Self::Error(error)
+    }
+}
+
+// Middlewares run outermost first in the order they were added; on the way out `after` runs in
+// reverse, and only for middlewares whose `before` ran (including one that short-circuited)
+pub struct MiddlewareStack<H> {
+    middlewares: Vec<Box<dyn Middleware>>,
+    handler: H,
+}
+
+impl<H: Handler> MiddlewareStack<H> {
+    pub fn new(handler: H) -> Self {
+        Self { middlewares: Vec::new(), handler }
+    }
+
+    pub fn with(mut self, middleware: impl Middleware) -> Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    // How many middlewares were entered, and the response of the one that short-circuited, if any
+    fn run_before(&self, request: &Request, client_ip: SocketAddr) -> (usize, Option<Response>) {
+        for (index, middleware) in self.middlewares.iter().enumerate() {
+            let response = match middleware.before(request, client_ip) {
+                Ok(None) => continue,
+                Ok(Some(response)) => response,
+                Err(Rejection::Error(error)) => self.handler.handle_error(&error, Some(request.path())),
+                Err(Rejection::SecurityViolation(reason)) => {
+                    self.handler.handle_security_violation(reason, request.path(), client_ip)
+                },
+            };
+            return (index + 1, Some(response));
+        }
+        (self.middlewares.len(), None)
+    }
+
+    fn run_after(&self, entered: usize, request: &Request, response: Response) -> Response {
+        self.middlewares[..entered].iter().rev()
+            .fold(response, |response, middleware| middleware.after(request, response))
+    }
+}
+
+impl<H: Handler> Handler for MiddlewareStack<H> {
+    async fn handle_request(&self, request: &Request<'_>, client_ip: SocketAddr) -> Response {
+        let (entered, short_circuit) = self.run_before(request, client_ip);
+        let response = match short_circuit {
+            Some(response) => response,
+            None => self.handler.handle_request(request, client_ip).await,
+        };
+        self.run_after(entered, request, response)
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.handler.streams_body(request)
+    }
+
+    // A middleware that answers early leaves the body unread
+    async fn handle_stream(&self, request: &Request<'_>, body: &mut BodyStream<'_>, client_ip: SocketAddr) -> Response {
+        let (entered, short_circuit) = self.run_before(request, client_ip);
+        let response = match short_circuit {
+            Some(response) => response,
+            None => self.handler.handle_stream(request, body, client_ip).await,
+        };
+        self.run_after(entered, request, response)
+    }
+
+    fn handle_error(&self, error: &ServerError, path: Option<&str>) -> Response {
+        self.handler.handle_error(error, path)
+    }
+
+    fn handle_security_violation(&self, reason: &str, path: &str, client_ip: SocketAddr) -> Response {
+        self.handler.handle_security_violation(reason, path, client_ip)
+    }
+
+    fn on_response(&self, request: &Request, response: &Response, latency: Duration, client_ip: SocketAddr) {
+        self.handler.on_response(request, response, latency, client_ip)
+    }
+
+    fn is_ready(&self) -> bool {
+        self.handler.is_ready()
+    }
+
+    fn is_health_check(&self, request: &Request) -> bool {
+        self.handler.is_health_check(request)
+    }
+}
+
+// Per-client request budget from SecurityConfig
+pub struct RateLimit {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimit {
+    // Shares the limiter so its owner can report on it
+    pub fn new(limiter: Arc<RateLimiter>) -> Self {
+        Self { limiter }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn before(&self, _request: &Request, client_ip: SocketAddr) -> Result<Option<Response>, Rejection> {
+        if self.limiter.is_allowed(client_ip.ip()) {
+            Ok(None)
+        } else {
+            Err(ServerError::RateLimited.into())
+        }
+    }
+}
+
+// Path and Host checks that apply before any routing; length gets its own status so clients can
+// tell it apart
+pub struct RequestValidation {
+    validator: SecurityValidator,
+}
+
+impl RequestValidation {
+    pub fn new(config: SecurityConfig) -> Self {
+        Self { validator: SecurityValidator::new(config) }
+    }
+}
+
+impl Middleware for RequestValidation {
+    fn before(&self, request: &Request, _client_ip: SocketAddr) -> Result<Option<Response>, Rejection> {
+        let path = request.path();
+        if !self.validator.validate_path_length(path) {
+            return Err(ServerError::UriTooLong.into());
+        }
+        match self.validator.validate_path(path) {
+            Err(reason) => Err(Rejection::SecurityViolation(reason)),
+            Ok(()) if !self.validator.validate_host(request.host()) => Err(Rejection::SecurityViolation("Host not allowed")),
+            Ok(()) => Ok(None),
+        }
+    }
+}
+
+// Cross-origin access for browsers. Preflights from allowed origins are answered here, with
+// Access-Control-Max-Age so the browser can reuse the answer; other responses to an allowed
+// origin get Access-Control-Allow-Origin on the way out.
+pub struct Cors {
+    config: CorsConfig,
+    allowed_methods: String,
+    allowed_headers: String,
+}
+
+impl Cors {
+    pub fn new(config: CorsConfig) -> Self {
+        let allowed_methods = config.allowed_methods.iter().map(Method::as_str).collect::<Vec<_>>().join(", ");
+        let allowed_headers = config.allowed_headers.join(", ");
+        Self { config, allowed_methods, allowed_headers }
+    }
+
+    // The Access-Control-Allow-Origin value for this request, if its origin is allowed
+    fn allow_origin<'a>(&self, request: &Request<'a>) -> Option<&'a str> {
+        let origin = request.header("Origin")?;
+        if self.config.allowed_origins.iter().any(|allowed| allowed == "*") {
+            Some("*")
+        } else if self.config.allowed_origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin)) {
+            Some(origin)
+        } else {
+            None
+        }
+    }
+}
+
+impl Middleware for Cors {
+    fn before(&self, request: &Request, _client_ip: SocketAddr) -> Result<Option<Response>, Rejection> {
+        let is_preflight = *request.method() == Method::OPTIONS && request.header("Access-Control-Request-Method").is_some();
+        // A disallowed origin falls through to a plain OPTIONS answer without CORS headers,
+        // which the browser treats as a refusal
+        if !is_preflight || self.allow_origin(request).is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Response::new(StatusCode::Ok, None)
+            .with_header("Access-Control-Allow-Methods", &self.allowed_methods)
+            .with_header("Access-Control-Allow-Headers", &self.allowed_headers)
+            .with_header("Access-Control-Max-Age", &self.config.max_age.as_secs().to_string())))
+    }
+
+    fn after(&self, request: &Request, response: Response) -> Response {
+        match self.allow_origin(request) {
+            Some(origin) => response.with_header("Access-Control-Allow-Origin", origin).with_vary("Origin"),
+            None if request.header("Origin").is_some() => response.with_vary("Origin"),
+            None => response,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    const CLIENT: &str = "127.0.0.1:40000";
+
+    // Answers 200 and records which error hooks ran; errors get a status none of the defaults use
+    #[derive(Default)]
+    struct Recording {
+        errors: Mutex<Vec<String>>,
+    }
+
+    impl Handler for Recording {
+        async fn handle_request(&self, _request: &Request<'_>, _client_ip: SocketAddr) -> Response {
+            Response::new(StatusCode::Ok, Some("handler".to_string()))
+        }
+
+        fn handle_error(&self, error: &ServerError, _path: Option<&str>) -> Response {
+            self.errors.lock().unwrap().push(format!("error: {}", error));
+            Response::new(StatusCode::NotAcceptable, None)
+        }
+
+        fn handle_security_violation(&self, reason: &str, _path: &str, _client_ip: SocketAddr) -> Response {
+            self.errors.lock().unwrap().push(format!("violation: {}", reason));
+            Response::new(StatusCode::NotAcceptable, None)
+        }
+    }
+
+    // Logs each pass through it; answers requests for `/<name>` itself
+    struct Tag(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl Middleware for Tag {
+        fn before(&self, request: &Request, _client_ip: SocketAddr) -> Result<Option<Response>, Rejection> {
+            self.1.lock().unwrap().push(format!("before {}", self.0));
+            let answers = request.path().strip_prefix('/') == Some(self.0);
+            Ok(answers.then(|| Response::new(StatusCode::Ok, Some(self.0.to_string()))))
+        }
+
+        fn after(&self, _request: &Request, response: Response) -> Response {
+            self.1.lock().unwrap().push(format!("after {}", self.0));
+            response
+        }
+    }
+
+    fn get(path: &str) -> Request<'_> {
+        Request::for_test(Method::GET, path, &[("Host", "localhost:8080")], b"", CLIENT.parse().unwrap())
+    }
+
+    async fn handle<H: Handler>(stack: &MiddlewareStack<H>, path: &str) -> Response {
+        stack.handle_request(&get(path), CLIENT.parse().unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn short_circuit_skips_inner_layers_and_unwinds_outer_ones() {
+        let trail = Arc::new(Mutex::new(Vec::new()));
+        let stack = MiddlewareStack::new(Recording::default())
+            .with(Tag("outer", Arc::clone(&trail)))
+            .with(Tag("inner", Arc::clone(&trail)));
+
+        assert_eq!(handle(&stack, "/page").await.body(), b"handler");
+        assert_eq!(std::mem::take(&mut *trail.lock().unwrap()), ["before outer", "before inner", "after inner", "after outer"]);
+
+        assert_eq!(handle(&stack, "/outer").await.body(), b"outer");
+        assert_eq!(*trail.lock().unwrap(), ["before outer", "after outer"]);
+    }
+
+    #[tokio::test]
+    async fn rejections_go_through_the_handler_overrides() {
+        let config = SecurityConfig { rate_limit_requests: 1, max_path_length: 16, ..SecurityConfig::default() };
+        let stack = MiddlewareStack::new(Recording::default())
+            .with(RequestValidation::new(config.clone()))
+            .with(RateLimit::new(Arc::new(RateLimiter::new(config))));
+
+        let expected = [
+            ("/a-very-long-path-indeed", StatusCode::NotAcceptable),
+            ("/.git/config", StatusCode::NotAcceptable),
+            ("/page", StatusCode::Ok),
+            ("/page", StatusCode::NotAcceptable),
+        ];
+        for (path, status) in expected {
+            assert_eq!(handle(&stack, path).await.status_code(), status, "{}", path);
+        }
+        // Validation runs first, so only the /page requests counted against the budget of one
+        assert_eq!(*stack.handler().errors.lock().unwrap(), [
+            "error: Request URI too long",
+            "violation: Forbidden path",
+            "error: Rate limit exceeded. Please try again later.",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn a_single_host_is_checked_against_the_allowlist() {
+        let stack = MiddlewareStack::new(Recording::default()).with(RequestValidation::new(SecurityConfig::default()));
+        let request = |host| Request::for_test(Method::GET, "/", &[("Host", host)], b"", CLIENT.parse().unwrap());
+
+        let response = stack.handle_request(&request("localhost:8080"), CLIENT.parse().unwrap()).await;
+        assert_eq!(response.status_code(), StatusCode::Ok);
+        let response = stack.handle_request(&request("evil.example"), CLIENT.parse().unwrap()).await;
+        assert_eq!(response.status_code(), StatusCode::NotAcceptable);
+        assert_eq!(*stack.handler().errors.lock().unwrap(), ["violation: Host not allowed"]);
+    }
+
+    #[tokio::test]
+    async fn preflights_from_allowed_origins_carry_a_max_age() {
+        let config = CorsConfig { max_age: Duration::from_secs(900), ..CorsConfig::new(vec!["https://app.example.com".to_string()]) };
+        let stack = MiddlewareStack::new(Recording::default()).with(Cors::new(config));
+        let preflight = |origin| {
+            let headers = [("Host", "localhost:8080"), ("Origin", origin), ("Access-Control-Request-Method", "POST")];
+            Request::for_test(Method::OPTIONS, "/api/users", &headers, b"", CLIENT.parse().unwrap())
+        };
+
+        let response = stack.handle_request(&preflight("https://app.example.com"), CLIENT.parse().unwrap()).await;
+        let max_age = response.header("Access-Control-Max-Age").expect("no Access-Control-Max-Age");
+        assert_eq!(max_age.parse::<u64>(), Ok(900));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("https://app.example.com"));
+        assert_eq!(response.header("Access-Control-Allow-Methods"), Some("GET, HEAD, POST, OPTIONS"));
+
+        // Other origins reach the handler and get no CORS headers
+        let response = stack.handle_request(&preflight("https://evil.example"), CLIENT.parse().unwrap()).await;
+        assert_eq!(response.body(), b"handler");
+        assert_eq!(response.header("Access-Control-Max-Age"), None);
+    }
+}
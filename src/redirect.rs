@@ -0,0 +1,83 @@
+use crate::http::{Request, Response, StatusCode};
+use crate::server::Handler;
+use std::net::SocketAddr;
+
+// Handler for the plaintext listener in TLS deployments: every request is sent to the same host
+// and path over HTTPS
+pub struct HttpsRedirect {
+    https_port: u16,
+}
+
+impl HttpsRedirect {
+    pub fn new(https_port: u16) -> Self {
+        Self { https_port }
+    }
+
+    fn location(&self, host: &str, request: &Request) -> String {
+        // Drop the plaintext port; the default HTTPS port is left implicit
+        let hostname = match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host.split(':').next().unwrap_or(host),
+        };
+        let mut location = format!("https://{}", hostname);
+        if self.https_port != 443 {
+            location.push_str(&format!(":{}", self.https_port));
+        }
+        location.push_str(request.path());
+        if let Some(query) = request.raw_query() {
+            location.push('?');
+            location.push_str(query);
+        }
+        location
+    }
+}
+
+impl Handler for HttpsRedirect {
+    async fn handle_request(&self, request: &Request<'_>, _client_ip: SocketAddr) -> Response {
+        // Without a Host there is nothing safe to redirect to
+        let host = match request.host().filter(|host| is_valid_host(host)) {
+            Some(host) => host,
+            None => return Response::new(StatusCode::BadRequest, Some("Missing or invalid Host header".to_string())),
+        };
+
+        Response::new(StatusCode::MovedPermanently, Some("Moved Permanently".to_string()))
+            .with_header("Location", &self.location(host, request))
+    }
+
+    fn is_health_check(&self, _request: &Request) -> bool {
+        false
+    }
+}
+
+// reg-name, IPv4 or bracketed IPv6, with an optional port
+fn is_valid_host(host: &str) -> bool {
+    !host.is_empty() && host.bytes().all(|b| b.is_ascii_alphanumeric() || b"-.:[]_".contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Method;
+    use crate::server::{testing, Server};
+
+    #[tokio::test]
+    async fn plaintext_requests_are_moved_to_https() {
+        let addr = testing::spawn(Server::new(String::new()), HttpsRedirect::new(8443)).await;
+        let cases = [
+            ("example.com:8080", "/docs/page?lang=en", "https://example.com:8443/docs/page?lang=en"),
+            ("[::1]:80", "/", "https://[::1]:8443/"),
+        ];
+        for (host, target, location) in cases {
+            let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", target, host);
+            let response = testing::exchange(addr, request.as_bytes()).await;
+            assert_eq!(testing::status(&response), 301);
+            assert_eq!(testing::header(&response, "Location"), Some(location));
+        }
+
+        let response = testing::exchange(addr, b"GET / HTTP/1.0\r\n\r\n").await;
+        assert_eq!(testing::status(&response), 400);
+        let request = Request::for_test(Method::GET, "/", &[("Host", "example.com")], b"", addr);
+        let response = HttpsRedirect::new(443).handle_request(&request, addr).await;
+        assert_eq!(response.header("Location"), Some("https://example.com/"));
+    }
+}
@@ -1,7 +1,15 @@
+use crate::access_log::{LogFormat, LogSampling};
+use crate::http::response::DEFAULT_SERVER_HEADER;
+use crate::http::{Method, SecurityHeaders};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::IpAddr;
-use std::sync::RwLock;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 
 #[derive(Clone)]
 pub struct SecurityConfig {
@@ -10,6 +18,80 @@ pub struct SecurityConfig {
     pub allowed_file_extensions: Vec<&'static str>,
     pub allowed_hosts: Vec<&'static str>,
     pub max_path_length: usize,
+    pub max_connections_per_ip: usize,
+    // New connections per second across all clients, to blunt connection floods; 0 is unlimited
+    pub max_accept_rate: u32,
+    pub max_body_bytes: usize,
+    // Largest single file `POST /api/upload` accepts; the whole request is still bound by max_body_bytes
+    pub max_upload_bytes: usize,
+    // Where uploaded files are saved; uploads are refused while this is unset
+    pub upload_dir: Option<PathBuf>,
+    // Response compression on gzip's 1-9 scale; higher trades CPU for smaller bodies
+    pub compression_level: u32,
+    // Request line plus header fields; anything larger is answered with 431
+    pub max_head_bytes: usize,
+    // Header count and single-header length caps, also answered with 431
+    pub max_headers: usize,
+    pub max_header_bytes: usize,
+    // Slow-loris guard: clients sending headers slower than this (bytes/sec) are disconnected
+    pub min_header_rate: u64,
+    // Keep-alive connections are closed after this many requests
+    pub max_requests_per_connection: usize,
+    // Generated response bodies are truncated to this many bytes, with a warning, as a safety valve
+    // for endpoints that build their output. Static files are sent whole.
+    pub max_response_bytes: usize,
+    // How long a shutdown waits for in-flight requests before giving up on them
+    pub shutdown_drain_timeout: Duration,
+    // How long a keep-alive connection may sit idle between requests
+    pub keep_alive_timeout: Duration,
+    pub enable_templates: bool,
+    // The built-in /api/* demo endpoints; off turns the server into a plain static file server
+    pub enable_api_routes: bool,
+    // Cross-origin browser access; None sends no CORS headers at all
+    pub cors: Option<CorsConfig>,
+    pub spa_fallback: bool,
+    // Extensionless paths that match no file are retried as `<path>.html`, so /about serves about.html
+    pub clean_urls: bool,
+    pub content_sniffing: bool,
+    pub server_header: Option<String>,
+    pub security_headers: SecurityHeaders,
+    // Cache-Control for successful static file responses; None keeps the security header default
+    pub static_cache_control: Option<String>,
+    // `public, max-age` per Content-Type prefix for static files, first match wins; ignored when
+    // static_cache_control is set or in dev mode
+    pub static_max_age: Vec<(&'static str, u64)>,
+    // Extension -> Content-Type, consulted before the built-in table (keys match case-insensitively).
+    // Overrides do not bypass allowed_file_extensions.
+    pub mime_overrides: HashMap<String, String>,
+    // Bearer tokens accepted on the Authorization header and the role each one grants
+    pub api_tokens: HashMap<String, Role>,
+    pub access_rules: Vec<AccessRule>,
+    // URL rewrites applied before static file lookup; exact matches win over prefixes
+    pub rewrites: Vec<Rewrite>,
+    // Development conveniences such as watching the public directory for changes
+    pub dev_mode: bool,
+    // Json suppresses the informational text lines so stdout carries only access records
+    pub log_format: LogFormat,
+    // Share of successful requests that get an access record; errors are always logged
+    pub log_sampling: LogSampling,
+    // Connections arrive through a TLS-terminating proxy, so requests count as HTTPS
+    pub tls_terminated: bool,
+    // Whether static files may be served through symlinks under the public directory. Even when
+    // enabled, the resolved target must stay inside it.
+    pub follow_symlinks: bool,
+    // Tried in order for `/` and for requests naming a directory
+    pub default_documents: Vec<String>,
+    // Clients in these networks (monitoring, internal services) bypass rate limiting entirely
+    pub rate_limit_exempt: Vec<Cidr>,
+    // Caps on simultaneous requests to expensive routes, independent of rate limiting
+    pub route_concurrency: Vec<ConcurrencyLimit>,
+    // How long a request over a route's cap waits for a slot before it gets a 503
+    pub route_queue_timeout: Duration,
+    // How `/hello/` relates to `/hello` (the root path is never affected)
+    pub trailing_slash: TrailingSlash,
+    // `/docs` naming a directory is 301-redirected to `/docs/`, so relative links in its default
+    // document resolve inside it. Directories then keep their slash under every trailing_slash policy.
+    pub directory_slash_redirect: bool,
 }
 
 impl Default for SecurityConfig {
@@ -21,27 +103,165 @@ impl Default for SecurityConfig {
                 "html", "css", "js", "json", "txt", "xml",
                 "png", "jpg", "jpeg", "gif", "svg", "ico", "webp"
             ],
-            allowed_hosts: vec!["127.0.0.1:8080", "localhost:8080"],
+            allowed_hosts: vec!["127.0.0.1:8080", "localhost:8080", "[::1]:8080"],
             max_path_length: 255,
+            max_connections_per_ip: 64,
+            max_accept_rate: 1000,
+            max_body_bytes: 1024 * 1024, // 1 MiB
+            max_upload_bytes: 512 * 1024,
+            compression_level: 6,
+            upload_dir: None,
+            max_head_bytes: 8192,
+            max_headers: 100,
+            max_header_bytes: 4096,
+            min_header_rate: 100,
+            max_requests_per_connection: 1000,
+            max_response_bytes: 8 * 1024 * 1024,
+            shutdown_drain_timeout: Duration::from_secs(30),
+            keep_alive_timeout: Duration::from_secs(30),
+            enable_templates: false,
+            enable_api_routes: true,
+            cors: None,
+            spa_fallback: false,
+            clean_urls: false,
+            content_sniffing: false,
+            server_header: Some(DEFAULT_SERVER_HEADER.to_string()),
+            security_headers: SecurityHeaders::default(),
+            static_cache_control: None,
+            static_max_age: vec![
+                ("image/", 30 * 24 * 60 * 60),
+                ("text/css", 30 * 24 * 60 * 60),
+                ("application/javascript", 30 * 24 * 60 * 60),
+                ("text/html", 5 * 60),
+            ],
+            mime_overrides: HashMap::new(),
+            api_tokens: HashMap::new(),
+            access_rules: vec![
+                AccessRule {
+                    path_prefix: "/api/users",
+                    methods: vec![Method::POST, Method::PUT, Method::PATCH, Method::DELETE],
+                    role: Role::Admin,
+                },
+                AccessRule {
+                    path_prefix: "/api/stats",
+                    methods: vec![Method::GET, Method::HEAD],
+                    role: Role::Admin,
+                },
+                AccessRule {
+                    path_prefix: "/api/upload",
+                    methods: vec![Method::POST],
+                    role: Role::User,
+                },
+                AccessRule {
+                    path_prefix: "/admin",
+                    methods: vec![Method::GET, Method::HEAD, Method::POST, Method::PUT, Method::PATCH, Method::DELETE],
+                    role: Role::Admin,
+                },
+            ],
+            rewrites: Vec::new(),
+            dev_mode: false,
+            log_format: LogFormat::Text,
+            log_sampling: LogSampling::All,
+            tls_terminated: false,
+            follow_symlinks: false,
+            rate_limit_exempt: Vec::new(),
+            route_concurrency: Vec::new(),
+            route_queue_timeout: Duration::from_secs(5),
+            trailing_slash: TrailingSlash::default(),
+            directory_slash_redirect: false,
+            default_documents: vec!["index.html".to_string(), "index.htm".to_string(), "default.html".to_string()],
         }
     }
 }
 
 pub struct RateLimiter {
     requests: RwLock<HashMap<IpAddr, Vec<Instant>>>,
+    denied: AtomicU64,
+    // Starts from the config but can be changed at runtime through the admin API
+    limit: RwLock<RateLimitSettings>,
     config: SecurityConfig,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitSettings {
+    pub requests: usize,
+    pub window: Duration,
+}
+
+// Point-in-time view for operators; `throttled` are IPs with no requests left in the window
+#[derive(Debug, Serialize)]
+pub struct RateLimiterStats {
+    pub tracked_ips: usize,
+    pub throttled: Vec<IpAddr>,
+    pub denied_total: u64,
+}
+
 impl RateLimiter {
     pub fn new(config: SecurityConfig) -> Self {
         Self {
             requests: RwLock::new(HashMap::new()),
+            denied: AtomicU64::new(0),
+            limit: RwLock::new(RateLimitSettings {
+                requests: config.rate_limit_requests,
+                window: config.rate_limit_window,
+            }),
             config,
         }
     }
 
+    pub fn limit(&self) -> RateLimitSettings {
+        match self.limit.read() {
+            Ok(limit) => *limit,
+            Err(poisoned) => *poisoned.into_inner(),
+        }
+    }
+
+    // Takes effect from the next request; history already recorded is judged against the new window
+    pub fn set_limit(&self, limit: RateLimitSettings) {
+        match self.limit.write() {
+            Ok(mut current) => *current = limit,
+            Err(poisoned) => *poisoned.into_inner() = limit,
+        }
+    }
+
+    pub fn stats(&self) -> RateLimiterStats {
+        let now = Instant::now();
+        let RateLimitSettings { requests: max_requests, window } = self.limit();
+        let (tracked_ips, throttled) = match self.requests.read() {
+            Ok(requests) => {
+                let active = requests.iter()
+                    .map(|(ip, times)| (ip, times.iter().filter(|&&time| now.duration_since(time) < window).count()))
+                    .filter(|&(_, count)| count > 0);
+                let (mut tracked, mut throttled) = (0, Vec::new());
+                for (ip, count) in active {
+                    tracked += 1;
+                    if count >= max_requests {
+                        throttled.push(*ip);
+                    }
+                }
+                (tracked, throttled)
+            },
+            Err(_) => (0, Vec::new()),
+        };
+
+        RateLimiterStats {
+            tracked_ips,
+            throttled,
+            denied_total: self.denied.load(Ordering::Relaxed),
+        }
+    }
+
     pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        // Exempt clients are not recorded, so they never show up as tracked or throttled
+        if self.config.rate_limit_exempt.iter().any(|network| network.contains(ip)) {
+            return true;
+        }
+
+        // Each IPv6 address is its own client; only IPv4-mapped ones fold into their IPv4 form, so a
+        // client seen over both socket families shares one window
+        let ip = ip.to_canonical();
         let now = Instant::now();
+        let RateLimitSettings { requests: max_requests, window } = self.limit();
         let mut requests = match self.requests.write() {
             Ok(requests) => requests,
             Err(_) => return false, // Fail securely
@@ -50,24 +270,237 @@ impl RateLimiter {
         // Clean up old entries periodically
         if requests.len() > 1000 {
             requests.retain(|_, times| {
-                times.retain(|&time| now.duration_since(time) < self.config.rate_limit_window);
+                times.retain(|&time| now.duration_since(time) < window);
                 !times.is_empty()
             });
         }
         
         let ip_requests = requests.entry(ip).or_insert_with(Vec::new);
-        ip_requests.retain(|&time| now.duration_since(time) < self.config.rate_limit_window);
+        ip_requests.retain(|&time| now.duration_since(time) < window);
         
-        if ip_requests.len() < self.config.rate_limit_requests {
+        if ip_requests.len() < max_requests {
             ip_requests.push(now);
             true
         } else {
+            self.denied.fetch_add(1, Ordering::Relaxed);
             eprintln!("🚨 Rate limit exceeded for IP: {}", ip);
             false
         }
     }
 }
 
+// An address block such as `10.0.0.0/8` or `fd00::/8`; a bare address is a single-host block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients on a dual-stack socket show up as ::ffff:a.b.c.d
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len)).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len)).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            },
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid CIDR: {}", s);
+        let (address, prefix_len) = match s.trim().split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s.trim(), None),
+        };
+        let network: IpAddr = address.parse().map_err(|_| invalid())?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse::<u8>().ok().filter(|&len| len <= max_len).ok_or_else(invalid)?,
+            None => max_len,
+        };
+        Ok(Self { network, prefix_len })
+    }
+}
+
+pub struct ConnectionLimiter {
+    connections: Mutex<HashMap<IpAddr, usize>>,
+    max_per_ip: usize,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_per_ip: usize) -> Self {
+        Self {
+            connections: Mutex::new(HashMap::new()),
+            max_per_ip,
+        }
+    }
+
+    // Returns a guard holding the slot until the connection ends, or None if the IP is at its cap
+    pub fn try_acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionGuard> {
+        let ip = ip.to_canonical();
+        let mut connections = self.connections.lock().ok()?;
+        let count = connections.entry(ip).or_insert(0);
+
+        if *count >= self.max_per_ip {
+            eprintln!("🚨 Connection limit exceeded for IP: {}", ip);
+            return None;
+        }
+
+        *count += 1;
+        Some(ConnectionGuard { limiter: Arc::clone(self), ip })
+    }
+
+    fn release(&self, ip: IpAddr) {
+        if let Ok(mut connections) = self.connections.lock() {
+            if let Some(count) = connections.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    connections.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+pub struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+// At most `max_concurrent` requests whose path is `path_prefix` or lies beneath it run at once
+#[derive(Clone, Debug)]
+pub struct ConcurrencyLimit {
+    pub path_prefix: &'static str,
+    pub max_concurrent: usize,
+}
+
+pub struct RouteLimiter {
+    routes: Vec<(&'static str, Arc<Semaphore>)>,
+    queue_timeout: Duration,
+}
+
+impl RouteLimiter {
+    pub fn new(limits: &[ConcurrencyLimit], queue_timeout: Duration) -> Self {
+        let routes = limits.iter()
+            .map(|limit| (limit.path_prefix, Arc::new(Semaphore::new(limit.max_concurrent))))
+            .collect();
+        Self { routes, queue_timeout }
+    }
+
+    // Ok(None) for unlimited routes. The permit frees the slot when dropped; Err means the request
+    // waited out the queue timeout.
+    pub async fn acquire(&self, path: &str) -> Result<Option<OwnedSemaphorePermit>, ()> {
+        let semaphore = match self.routes.iter().find(|(prefix, _)| under_prefix(path, prefix)) {
+            Some((_, semaphore)) => Arc::clone(semaphore),
+            None => return Ok(None),
+        };
+        match tokio::time::timeout(self.queue_timeout, semaphore.acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(Some(permit)),
+            _ => Err(()),
+        }
+    }
+}
+
+fn under_prefix(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+// `/old-page` -> `/new-page.html`, or with `prefix` set, `/docs/a` -> `/documentation/a`
+#[derive(Clone, Debug, Deserialize)]
+pub struct Rewrite {
+    pub from: String,
+    pub to: String,
+    #[serde(default)]
+    pub prefix: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct CorsConfig {
+    // Exact origins such as "https://app.example.com", or "*" for any
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<Method>,
+    pub allowed_headers: Vec<String>,
+    // How long a browser may cache a preflight answer (Access-Control-Max-Age)
+    pub max_age: Duration,
+}
+
+impl CorsConfig {
+    pub fn new(allowed_origins: Vec<String>) -> Self {
+        Self {
+            allowed_origins,
+            allowed_methods: vec![Method::GET, Method::HEAD, Method::POST, Method::OPTIONS],
+            allowed_headers: vec!["Content-Type".to_string(), "Authorization".to_string()],
+            max_age: Duration::from_secs(600),
+        }
+    }
+}
+
+// Strict routes `/hello/` and `/hello` separately; Redirect answers the slashed form with a 301 to
+// the bare one; Ignore routes it as if the slash were absent
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    #[default]
+    Strict,
+    Redirect,
+    Ignore,
+}
+
+impl FromStr for TrailingSlash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(Self::Strict),
+            "redirect" => Ok(Self::Redirect),
+            "ignore" => Ok(Self::Ignore),
+            _ => Err(format!("Unknown trailing slash policy: {} (expected strict, redirect or ignore)", s)),
+        }
+    }
+}
+
+// Ordered so a higher role satisfies any rule requiring a lower one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    User,
+    Admin,
+}
+
+// Requests whose path is `path_prefix` or lies beneath it and whose method is listed need `role`
+#[derive(Clone, Debug)]
+pub struct AccessRule {
+    pub path_prefix: &'static str,
+    pub methods: Vec<Method>,
+    pub role: Role,
+}
+
+impl AccessRule {
+    fn applies_to(&self, method: Method, path: &str) -> bool {
+        under_prefix(path, self.path_prefix) && self.methods.contains(&method)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AccessDenied {
+    Unauthenticated,
+    Forbidden,
+}
+
 pub struct SecurityValidator {
     config: SecurityConfig,
 }
@@ -77,8 +510,12 @@ impl SecurityValidator {
         Self { config }
     }
 
+    pub fn validate_path_length(&self, path: &str) -> bool {
+        path.len() <= self.config.max_path_length
+    }
+
     pub fn validate_path(&self, path: &str) -> Result<(), &'static str> {
-        if path.len() > self.config.max_path_length {
+        if !self.validate_path_length(path) {
             return Err("Path too long");
         }
 
@@ -97,16 +534,62 @@ impl SecurityValidator {
         Ok(())
     }
 
+    // Checks the access rules against the caller's bearer token; unrestricted routes need no credential
+    pub fn authorize(&self, method: Method, path: &str, authorization: Option<&str>) -> Result<(), AccessDenied> {
+        let required = match self.config.access_rules.iter()
+            .filter(|rule| rule.applies_to(method, path))
+            .map(|rule| rule.role)
+            .max()
+        {
+            Some(role) => role,
+            None => return Ok(()),
+        };
+
+        let token = authorization
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+            .map(|(_, token)| token.trim())
+            .ok_or(AccessDenied::Unauthenticated)?;
+
+        let role = self.config.api_tokens.iter()
+            .find(|(known, _)| constant_time_eq(known.as_bytes(), token.as_bytes()))
+            .map(|(_, role)| *role)
+            .ok_or(AccessDenied::Unauthenticated)?;
+
+        if role >= required {
+            Ok(())
+        } else {
+            Err(AccessDenied::Forbidden)
+        }
+    }
+
     pub fn validate_file_extension(&self, file_path: &str) -> bool {
-        file_path.split('.').last()
+        file_path.split('.').next_back()
             .map(|ext| self.config.allowed_file_extensions.contains(&ext.to_lowercase().as_str()))
             .unwrap_or(false)
     }
 
+    // Uploaded files are saved under the name the client sent, so it must be a plain file name
+    // with an allowed extension
+    pub fn validate_upload(&self, file_name: &str, size: usize) -> Result<(), &'static str> {
+        if file_name.is_empty() || file_name.len() > 255 || file_name.starts_with('.')
+            || file_name.contains(['/', '\\', '\0']) || file_name.contains("..")
+        {
+            return Err("has an invalid file name");
+        }
+        if !self.validate_file_extension(file_name) {
+            return Err("has a file type that is not allowed");
+        }
+        if size > self.config.max_upload_bytes {
+            return Err("is too large");
+        }
+        Ok(())
+    }
+
     pub fn validate_host(&self, host: Option<&str>) -> bool {
         match host {
             Some(host_header) => {
-                self.config.allowed_hosts.iter().any(|&allowed| host_header == allowed)
+                self.config.allowed_hosts.contains(&host_header)
             },
             None => true, // Allow requests without Host header for local testing
         }
@@ -117,4 +600,67 @@ impl SecurityValidator {
         let blocked_patterns = ["<script", "javascript:", "data:", "vbscript:", "onload="];
         !blocked_patterns.iter().any(|&pattern| user_agent.to_lowercase().contains(pattern))
     }
-}
\ No newline at end of file
+}
+
+// Avoids leaking how much of a token matched through comparison timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_cap_is_per_ip_and_released_with_the_guard() {
+        let limiter = Arc::new(ConnectionLimiter::new(2));
+        let first: IpAddr = "192.0.2.1".parse().unwrap();
+        let held = [limiter.try_acquire(first).unwrap(), limiter.try_acquire(first).unwrap()];
+
+        assert!(limiter.try_acquire(first).is_none());
+        // The IPv4-mapped form is the same client
+        assert!(limiter.try_acquire("::ffff:192.0.2.1".parse().unwrap()).is_none());
+        assert!(limiter.try_acquire("192.0.2.2".parse().unwrap()).is_some());
+
+        drop(held);
+        assert!(limiter.try_acquire(first).is_some());
+    }
+
+    #[test]
+    fn exempt_networks_are_never_throttled_or_tracked() {
+        let config = SecurityConfig {
+            rate_limit_requests: 3,
+            rate_limit_exempt: vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()],
+            ..SecurityConfig::default()
+        };
+        let limiter = RateLimiter::new(config);
+
+        for ip in ["10.1.2.3", "::1"] {
+            assert!((0..100).all(|_| limiter.is_allowed(ip.parse().unwrap())), "{}", ip);
+        }
+        let outside: IpAddr = "11.0.0.1".parse().unwrap();
+        assert_eq!((0..10).filter(|_| limiter.is_allowed(outside)).count(), 3);
+
+        let stats = limiter.stats();
+        assert_eq!(stats.tracked_ips, 1);
+        assert_eq!(stats.denied_total, 7);
+    }
+
+    #[test]
+    fn ipv6_clients_are_limited_per_address() {
+        let limiter = RateLimiter::new(SecurityConfig { rate_limit_requests: 1, ..SecurityConfig::default() });
+        let first: IpAddr = "2001:db8::1".parse().unwrap();
+
+        assert!(limiter.is_allowed(first));
+        assert!(!limiter.is_allowed(first));
+        // A neighbour in the same /64 is a different client
+        assert!(limiter.is_allowed("2001:db8::2".parse().unwrap()));
+        // An IPv4-mapped address shares its IPv4 client's window
+        assert!(limiter.is_allowed("192.0.2.1".parse().unwrap()));
+        assert!(!limiter.is_allowed("::ffff:192.0.2.1".parse().unwrap()));
+
+        let throttled = limiter.stats().throttled;
+        assert_eq!(throttled.len(), 3);
+        assert!(throttled.contains(&first) && throttled.contains(&"192.0.2.1".parse().unwrap()));
+    }
+}
@@ -1,92 +1,1819 @@
-use crate::http::{ParseError, Request, Response, StatusCode};
-use std::convert::TryFrom;
+use crate::access_log::{self, LogFormat, LogSampler, LogSampling};
+use crate::error::ServerError;
+use crate::http::chunked::{self, ChunkedStatus};
+use crate::http::compression::{CompressionLevel, ContentEncoding};
+use crate::http::request::find_head_end;
+use crate::http::response::{RenderedSecurityHeaders, DEFAULT_SERVER_HEADER};
+use crate::http::{HeaderLimits, Method, ParseError, Request, Response, SecurityHeaders, SendOptions, StatusCode, Version};
+use crate::security::{ConcurrencyLimit, ConnectionLimiter, RouteLimiter};
+use std::io;
 use std::net::SocketAddr;
-use tokio::io::AsyncReadExt;
-use tokio::net::TcpListener;
-use std::sync::Arc;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::time::error::Elapsed;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// Benign connection noise (probes, resets) is only printed when SERVER_DEBUG is set
+macro_rules! debug_log {
+    ($($arg:tt)*) => {
+        if debug_enabled() {
+            println!($($arg)*);
+        }
+    };
+}
+
+mod body;
+mod builder;
+#[cfg(feature = "http2")]
+mod http2;
+#[cfg(test)]
+pub(crate) mod testing;
+
+pub use body::BodyStream;
+pub use builder::ServerBuilder;
+
+const STARTUP_RETRY_AFTER_SECS: u64 = 5;
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+// Header rates are only judged after this long, so one small first packet isn't held against a client
+const MIN_HEADER_RATE_GRACE: Duration = Duration::from_secs(2);
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const READ_BUFFER_CAPACITY: usize = 8192;
+// Idle buffers kept for reuse; beyond this, released buffers are simply freed
+const MAX_POOLED_BUFFERS: usize = 256;
+// Buffers that grew past this for a large body are freed rather than pinned in the pool
+const MAX_POOLED_BUFFER_CAPACITY: usize = 64 * 1024;
 
 pub trait Handler: Send + Sync + 'static {
-    fn handle_request(&self, request: &Request, client_ip: SocketAddr) -> Response;
+    // Async so handlers can do I/O such as `Response::from_file` without blocking a runtime worker
+    fn handle_request(&self, request: &Request, client_ip: SocketAddr) -> impl Future<Output = Response> + Send;
 
-    fn handle_bad_request(&self, e: &ParseError) -> Response {
-        eprintln!("Failed to parse request: {}", e);
-        Response::new(StatusCode::BadRequest, Some("Invalid request format".to_string()))
+    // Every failure that becomes a response goes through here; `path` is None when the request
+    // could not be parsed. Override to customize error bodies.
+    fn handle_error(&self, error: &ServerError, path: Option<&str>) -> Response {
+        error.to_response(path)
     }
 
-    fn handle_security_violation(&self, reason: &str, client_ip: SocketAddr) -> Response {
+    fn handle_security_violation(&self, reason: &str, path: &str, client_ip: SocketAddr) -> Response {
         eprintln!("Security violation from {}: {}", client_ip, reason);
-        Response::security_error("Request blocked for security reasons")
+        self.handle_error(&ServerError::SecurityViolation, Some(path))
+    }
+
+    // Called once the response is formed, before it is sent; a hook for access logs and metrics
+    fn on_response(&self, _request: &Request, _response: &Response, _latency: Duration, _client_ip: SocketAddr) {}
+
+    // Handlers with async initialization return false until they can serve traffic
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    // Health checks bypass the readiness gate so orchestrators can probe startup
+    fn is_health_check(&self, request: &Request) -> bool {
+        matches!(request.path(), "/api/ping" | "/api/health")
+    }
+
+    // Requests this accepts go to `handle_stream` instead of `handle_request`. Over HTTP/1 with a
+    // Content-Length the body is then read as the handler consumes it rather than buffered first;
+    // the body size limit applies either way.
+    fn streams_body(&self, _request: &Request) -> bool {
+        false
+    }
+
+    // `request.body()` is empty here; the body comes from `body`. Only reached for requests
+    // `streams_body` accepted, so the default just ignores the body.
+    fn handle_stream(&self, request: &Request, _body: &mut BodyStream<'_>, client_ip: SocketAddr) -> impl Future<Output = Response> + Send {
+        self.handle_request(request, client_ip)
     }
 }
 
 pub struct Server {
     addr: String,
+    max_connections_per_ip: usize,
+    // New connections accepted per second across all clients; 0 is unlimited
+    max_accept_rate: u32,
+    max_body_bytes: usize,
+    header_limits: HeaderLimits,
+    min_header_rate: u64,
+    max_requests_per_connection: usize,
+    max_response_bytes: usize,
+    server_header: Option<Arc<str>>,
+    security_headers: Arc<RenderedSecurityHeaders>,
+    drain_timeout: Duration,
+    idle_timeout: Duration,
+    log_format: LogFormat,
+    log_sampler: Arc<LogSampler>,
+    route_limiter: Arc<RouteLimiter>,
+    compression_level: CompressionLevel,
+    secure: bool,
+    raw_requests: bool,
+    worker_threads: Option<usize>,
+}
+
+// Per-connection limits copied into each spawned connection task
+#[derive(Clone)]
+struct ConnectionSettings {
+    max_body_bytes: usize,
+    header_limits: HeaderLimits,
+    min_header_rate: u64,
+    max_requests_per_connection: usize,
+    max_response_bytes: usize,
+    idle_timeout: Duration,
+    server_header: Option<Arc<str>>,
+    security_headers: Arc<RenderedSecurityHeaders>,
+    log_format: LogFormat,
+    log_sampler: Arc<LogSampler>,
+    route_limiter: Arc<RouteLimiter>,
+    compression_level: CompressionLevel,
+    // Requests keep their wire bytes for Request::raw()
+    raw_requests: bool,
+    // Set when connections reach us over TLS (terminated in front of this server)
+    secure: bool,
+    // Flips to true once shutdown starts; connections stop taking new requests
+    shutdown: watch::Receiver<bool>,
+    in_flight: Arc<AtomicUsize>,
+    buffers: Arc<BufferPool>,
 }
 
 impl Server {
     pub fn new(addr: String) -> Self {
-        Self { addr }
+        Self {
+            addr,
+            max_connections_per_ip: usize::MAX,
+            max_accept_rate: 0,
+            max_body_bytes: usize::MAX,
+            header_limits: HeaderLimits::default(),
+            min_header_rate: 0,
+            max_requests_per_connection: usize::MAX,
+            max_response_bytes: usize::MAX,
+            server_header: Some(Arc::from(DEFAULT_SERVER_HEADER)),
+            security_headers: Arc::new(RenderedSecurityHeaders::new(&SecurityHeaders::default())),
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            log_format: LogFormat::Text,
+            log_sampler: Arc::new(LogSampler::new(LogSampling::All)),
+            route_limiter: Arc::new(RouteLimiter::new(&[], Duration::ZERO)),
+            compression_level: CompressionLevel::default(),
+            secure: false,
+            raw_requests: false,
+            worker_threads: None,
+        }
+    }
+
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    // A multi-threaded runtime sized by the builder's worker count, to run the server on
+    pub fn runtime(&self) -> io::Result<tokio::runtime::Runtime> {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        if let Some(worker_threads) = self.worker_threads {
+            builder.worker_threads(worker_threads);
+        }
+        builder.enable_all().build()
+    }
+
+    pub fn with_security_headers(mut self, security_headers: SecurityHeaders) -> Self {
+        self.security_headers = Arc::new(RenderedSecurityHeaders::new(&security_headers));
+        self
+    }
+
+    // None omits the Server header from every response
+    pub fn with_server_header(mut self, server_header: Option<String>) -> Self {
+        self.server_header = server_header.map(Arc::from);
+        self
+    }
+
+    pub fn with_max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    // Caps the request line plus header fields; larger header blocks get a 431
+    pub fn with_max_head_bytes(mut self, max_head_bytes: usize) -> Self {
+        self.header_limits.max_head_bytes = max_head_bytes;
+        self
+    }
+
+    // Bytes per second a client must sustain while sending headers; slower connections are
+    // dropped without a response. 0 disables the check.
+    pub fn with_min_header_rate(mut self, bytes_per_sec: u64) -> Self {
+        self.min_header_rate = bytes_per_sec;
+        self
+    }
+
+    // Header-flooding bounds: how many fields, and how long any one of them may be
+    pub fn with_max_headers(mut self, max_headers: usize) -> Self {
+        self.header_limits.max_headers = max_headers;
+        self
+    }
+
+    pub fn with_max_header_bytes(mut self, max_header_bytes: usize) -> Self {
+        self.header_limits.max_header_bytes = max_header_bytes;
+        self
+    }
+
+    // The response to the last allowed request says Connection: close so the client reconnects
+    pub fn with_max_requests_per_connection(mut self, max_requests_per_connection: usize) -> Self {
+        self.max_requests_per_connection = max_requests_per_connection.max(1);
+        self
+    }
+
+    // Generated response bodies above this are truncated; see SecurityConfig::max_response_bytes
+    pub fn with_max_response_bytes(mut self, max_response_bytes: usize) -> Self {
+        self.max_response_bytes = max_response_bytes;
+        self
+    }
+
+    // How long a connection may sit between requests; each request then gets its own read timeout
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    // How long shutdown waits for in-flight requests before closing anyway
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    // Json writes each access record as one structured object instead of the text line
+    pub fn with_log_format(mut self, log_format: LogFormat) -> Self {
+        self.log_format = log_format;
+        self
+    }
+
+    // Thins out access records for successful requests; errors are always logged
+    pub fn with_log_sampling(mut self, sampling: LogSampling) -> Self {
+        self.log_sampler = Arc::new(LogSampler::new(sampling));
+        self
+    }
+
+    // Requests over a route's cap queue for up to `queue_timeout`, then get a 503
+    pub fn with_route_concurrency(mut self, limits: &[ConcurrencyLimit], queue_timeout: Duration) -> Self {
+        self.route_limiter = Arc::new(RouteLimiter::new(limits, queue_timeout));
+        self
+    }
+
+    // Applies to both gzip and brotli; levels outside 1-9 are clamped
+    pub fn with_compression_level(mut self, level: u32) -> Self {
+        self.compression_level = CompressionLevel::new(level);
+        self
+    }
+
+    // Debugging aid: each request keeps a view of the bytes it was parsed from (see Request::raw).
+    // That pins the connection buffer for as long as the request lives, hence off by default.
+    pub fn with_raw_requests(mut self, raw_requests: bool) -> Self {
+        self.raw_requests = raw_requests;
+        self
+    }
+
+    // For deployments behind a TLS-terminating proxy: marks every connection as HTTPS, so
+    // Request::is_secure() is true and TLS-only headers such as HSTS are sent
+    pub fn with_secure_transport(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
     }
 
+    fn connection_settings(&self, shutdown: watch::Receiver<bool>, in_flight: Arc<AtomicUsize>) -> ConnectionSettings {
+        ConnectionSettings {
+            max_body_bytes: self.max_body_bytes,
+            header_limits: self.header_limits,
+            min_header_rate: self.min_header_rate,
+            max_requests_per_connection: self.max_requests_per_connection,
+            max_response_bytes: self.max_response_bytes,
+            idle_timeout: self.idle_timeout,
+            server_header: self.server_header.clone(),
+            security_headers: Arc::clone(&self.security_headers),
+            log_format: self.log_format,
+            log_sampler: Arc::clone(&self.log_sampler),
+            route_limiter: Arc::clone(&self.route_limiter),
+            compression_level: self.compression_level,
+            secure: self.secure,
+            raw_requests: self.raw_requests,
+            shutdown,
+            in_flight,
+            buffers: Arc::new(BufferPool::default()),
+        }
+    }
+
+    pub fn with_max_connections_per_ip(mut self, max_connections_per_ip: usize) -> Self {
+        self.max_connections_per_ip = max_connections_per_ip;
+        self
+    }
+
+    // Caps how fast connections are taken off the listen queue, so a connection flood can't spawn
+    // tasks without bound; the excess waits in the kernel backlog
+    pub fn with_max_accept_rate(mut self, max_accept_rate: u32) -> Self {
+        self.max_accept_rate = max_accept_rate;
+        self
+    }
+
+    // Serves until SIGINT/SIGTERM, then drains in-flight requests
     pub async fn run<H: Handler>(self, handler: H) -> Result<(), Box<dyn std::error::Error>> {
-        let listener = TcpListener::bind(&self.addr).await?;
+        self.run_until(handler, shutdown_signal()).await
+    }
+
+    pub async fn run_until<H: Handler>(self, handler: H, shutdown: impl Future<Output = ()>) -> Result<(), Box<dyn std::error::Error>> {
+        let listener = match inherited_listener()? {
+            Some(listener) => {
+                println!("Using inherited listener from LISTEN_FDS");
+                listener
+            },
+            None => TcpListener::bind(&self.addr).await?,
+        };
+
+        self.serve(listener, handler, shutdown).await
+    }
+
+    // Serve on an already-bound socket, e.g. one handed over by a previous process
+    pub async fn run_with_listener<H: Handler>(self, listener: std::net::TcpListener, handler: H) -> Result<(), Box<dyn std::error::Error>> {
+        listener.set_nonblocking(true)?;
+        self.serve(TcpListener::from_std(listener)?, handler, shutdown_signal()).await
+    }
+
+    async fn serve<H: Handler>(self, listener: TcpListener, handler: H, shutdown: impl Future<Output = ()>) -> Result<(), Box<dyn std::error::Error>> {
         let handler = Arc::new(handler);
+        let connection_limiter = Arc::new(ConnectionLimiter::new(self.max_connections_per_ip));
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let settings = self.connection_settings(shutdown_rx, Arc::clone(&in_flight));
         
-        println!("Listening on {}", self.addr);
+        println!("Listening on {}", listener.local_addr()?);
 
+        let mut accept_throttle = AcceptThrottle::new(self.max_accept_rate);
+        tokio::pin!(shutdown);
         loop {
-            match listener.accept().await {
+            if let Some(wait) = accept_throttle.delay() {
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => continue,
+                    _ = &mut shutdown => break,
+                }
+            }
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = &mut shutdown => break,
+            };
+
+            match accepted {
                 Ok((mut stream, addr)) => {
+                    // Responses leave in as few writes as possible, so Nagle would only add delay
+                    // (most visibly to a small response written after a 100 Continue)
+                    if let Err(e) = stream.set_nodelay(true) {
+                        eprintln!("Failed to set TCP_NODELAY for {}: {}", addr, e);
+                    }
                     let handler = Arc::clone(&handler);
+
+                    let settings = settings.clone();
+
+                    let connection_guard = match connection_limiter.try_acquire(addr.ip()) {
+                        Some(guard) => guard,
+                        None => {
+                            tokio::spawn(async move {
+                                let response = Response::new(
+                                    StatusCode::ServiceUnavailable,
+                                    Some("Too many concurrent connections".to_string())
+                                )
+                                .with_server_header(settings.server_header.as_deref())
+                                .with_default_security_headers(&settings.security_headers);
+                                let _ = response.send(&mut stream, SendOptions::closing()).await;
+                            });
+                            continue;
+                        },
+                    };
                     
                     tokio::spawn(async move {
-                        let mut buffer = vec![0; 8192];
-                        
-                        match tokio::time::timeout(
-                            std::time::Duration::from_secs(10),
-                            stream.read(&mut buffer)
-                        ).await {
-                            Ok(Ok(size)) => {
-                                if size == 0 {
-                                    return; // Connection closed
-                                }
-                                
-                                buffer.truncate(size);
-                                
-                                let response = match Request::try_from(&buffer[..]) {
-                                    Ok(request) => {
-                                        println!(" {} {} {} ({})", 
-                                            addr, 
-                                            request.method_str(), 
-                                            request.path(),
-                                            size
-                                        );
-                                        handler.handle_request(&request, addr)
-                                    },
-                                    Err(e) => {
-                                        eprintln!("Parse error from {}: {}", addr, e);
-                                        handler.handle_bad_request(&e)
-                                    },
-                                };
-
-                                if let Err(e) = response.send(&mut stream).await {
-                                    eprintln!("Failed to send response to {}: {}", addr, e);
-                                }
-                            }
-                            Ok(Err(e)) => eprintln!("Failed to read from {}: {}", addr, e),
-                            Err(_) => {
-                                eprintln!("Request timeout from {}", addr);
-                                let timeout_response = Response::new(
-                                    StatusCode::RequestTimeout, 
-                                    Some("Request timeout".to_string())
-                                );
-                                let _ = timeout_response.send(&mut stream).await;
-                            },
-                        }
+                        // Held for the lifetime of the task so the slot frees when the connection ends
+                        let _connection_guard = connection_guard;
+                        handle_connection(stream, addr, handler, settings).await;
                     });
                 }
                 Err(e) => eprintln!("Failed to establish connection: {}", e),
             }
         }
+
+        // Stop accepting, tell idle connections to close, then give running requests time to finish
+        drop(listener);
+        let _ = shutdown_tx.send(true);
+        println!("Shutting down; waiting up to {:?} for in-flight requests", self.drain_timeout);
+        self.drain(&in_flight).await;
+        Ok(())
+    }
+
+    async fn drain(&self, in_flight: &AtomicUsize) {
+        let deadline = Instant::now() + self.drain_timeout;
+        loop {
+            let remaining = in_flight.load(Ordering::Acquire);
+            if remaining == 0 {
+                println!("All in-flight requests completed");
+                return;
+            }
+            if Instant::now() >= deadline {
+                eprintln!("Drain timeout elapsed; abandoning {} in-flight request(s)", remaining);
+                return;
+            }
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
     }
-}
\ No newline at end of file
+}
+
+// Counts a request as in flight from the moment it is read until its response is written
+struct InFlightGuard(Arc<AtomicUsize>);
+
+impl InFlightGuard {
+    fn enter(counter: &Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::AcqRel);
+        Self(Arc::clone(counter))
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// Token bucket over accepts, refilled at `rate` per second and holding at most one second's worth
+struct AcceptThrottle {
+    rate: u32,
+    tokens: f64,
+    refilled: Instant,
+    throttling: bool,
+}
+
+impl AcceptThrottle {
+    fn new(rate: u32) -> Self {
+        Self { rate, tokens: f64::from(rate), refilled: Instant::now(), throttling: false }
+    }
+
+    // Takes a token and returns None, or returns how long until the next token is due
+    fn delay(&mut self) -> Option<Duration> {
+        if self.rate == 0 {
+            return None;
+        }
+        let rate = f64::from(self.rate);
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.refilled).as_secs_f64() * rate).min(rate);
+        self.refilled = now;
+        // One warning per flood: it re-arms only once the bucket has refilled completely
+        if self.tokens >= rate {
+            self.throttling = false;
+        }
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None;
+        }
+        if !self.throttling {
+            eprintln!("Accept rate limit of {}/s reached; delaying new connections", self.rate);
+            self.throttling = true;
+        }
+        Some(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+    }
+}
+
+// Read buffers recycled across connections, so connection churn doesn't reallocate them
+#[derive(Default)]
+struct BufferPool {
+    idle: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    fn take(self: &Arc<Self>) -> PooledBuffer {
+        let buffer = self.idle.lock().ok()
+            .and_then(|mut idle| idle.pop())
+            .unwrap_or_else(|| Vec::with_capacity(READ_BUFFER_CAPACITY));
+        PooledBuffer { buffer, pool: Arc::clone(self) }
+    }
+}
+
+struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        if buffer.capacity() > MAX_POOLED_BUFFER_CAPACITY {
+            return;
+        }
+        // Emptied before reuse so nothing from this connection reaches the next one
+        buffer.clear();
+        if let Ok(mut idle) = self.pool.idle.lock() {
+            if idle.len() < MAX_POOLED_BUFFERS {
+                idle.push(buffer);
+            }
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = terminate.recv() => {},
+                }
+            },
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            },
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+async fn handle_connection<H: Handler>(mut stream: TcpStream, addr: SocketAddr, handler: Arc<H>, settings: ConnectionSettings) {
+    #[cfg(feature = "http2")]
+    if http2::has_preface(&stream, settings.idle_timeout).await {
+        return http2::serve_connection(stream, addr, handler, settings).await;
+    }
+
+    let mut pooled = settings.buffers.take();
+    let buffer: &mut Vec<u8> = &mut pooled;
+    let mut shutdown = settings.shutdown.clone();
+    let mut served = 0;
+
+    // Requests are handled strictly in order, so pipelined responses go back in request order
+    loop {
+        // The idle timeout covers the wait for a request's first byte (skipped when pipelined bytes
+        // are already buffered); the request timeout starts fresh from there. Only a connection
+        // sitting between requests is cut short by shutdown.
+        let read_result = if buffer.is_empty() {
+            let wait = wait_for_request(&mut stream, buffer, settings.idle_timeout);
+            let waited = tokio::select! {
+                result = wait => result,
+                _ = shutdown.wait_for(|&stopping| stopping) => return,
+            };
+            // Nothing was started, so running out of idle time is an ordinary close, not a 408
+            if waited.is_err() {
+                debug_log!("Closing idle connection from {}", addr);
+                return;
+            }
+            waited
+        } else {
+            Ok(Ok(()))
+        };
+        let read_result = match read_result {
+            Ok(Ok(())) => {
+                let read = read_request(&mut stream, buffer, &settings, |request| handler.streams_body(request));
+                tokio::time::timeout(REQUEST_READ_TIMEOUT, read).await
+            },
+            waited => waited.map(|read| read.map(|()| false)),
+        };
+        // The body, or the rest of it, is still on the socket for a streaming handler
+        let deferred = matches!(read_result, Ok(Ok(true)));
+        let _in_flight = InFlightGuard::enter(&settings.in_flight);
+        let mut request_id = next_request_id();
+
+        let rejection = match read_result {
+            Ok(Ok(_)) => None,
+            Ok(Err(error @ (ReadError::Closed | ReadError::Io(_)))) => {
+                if error.is_client_gone() {
+                    // Includes probes and port scanners that connect and leave without a request
+                    debug_log!("Connection from {} closed by client", addr);
+                } else if let ReadError::Io(e) = error {
+                    eprintln!("Failed to read from {}: {}", addr, e);
+                }
+                return;
+            },
+            Ok(Err(ReadError::TooSlow)) => {
+                eprintln!("Dropping {}: headers arriving below {} bytes/sec", addr, settings.min_header_rate);
+                return;
+            },
+            Ok(Err(ReadError::PayloadTooLarge)) => {
+                eprintln!("[{}] Request body too large from {}", request_id, addr);
+                Some(handler.handle_error(&ServerError::TooLarge, None))
+            },
+            Ok(Err(ReadError::Parse(e))) => {
+                eprintln!("[{}] Parse error from {}: {}", request_id, addr, e);
+                Some(handler.handle_error(&ServerError::Parse(e), None))
+            },
+            // Only reachable mid-request: the request began but did not finish in time
+            Err(_) => {
+                eprintln!("[{}] Request timeout from {}", request_id, addr);
+                Some(handler.handle_error(&ServerError::Timeout, None))
+            },
+        };
+
+        // After a rejected or unparseable request the framing is unknown, so the connection is closed
+        let (response, mut options, consumed) = match rejection {
+            Some(response) => (response, SendOptions::new(Version::Http11, false, settings.secure), 0),
+            None => match Request::parse(buffer, &settings.header_limits, settings.max_body_bytes) {
+                Ok(mut request) => {
+                    // Honor a well-formed incoming id so traces can span upstream proxies
+                    if let Some(incoming) = request.header("X-Request-Id").filter(|id| is_valid_request_id(id)) {
+                        request_id = incoming.to_string();
+                    }
+                    request.set_request_id(request_id.clone());
+                    request.set_remote_addr(addr);
+                    request.set_secure(settings.secure);
+                    if settings.raw_requests {
+                        request.retain_raw(buffer);
+                    }
+
+                    let started = Instant::now();
+                    let unread_len = if deferred { request.content_length().unwrap_or(0) - request.body().len() } else { 0 };
+                    let mut body_drained = unread_len == 0;
+                    let response = if !handler.is_ready() && !handler.is_health_check(&request) {
+                        Response::service_unavailable(STARTUP_RETRY_AFTER_SECS)
+                    } else if handler.streams_body(&request) {
+                        let prefix = request.take_body();
+                        let mut body = if deferred {
+                            BodyStream::from_socket(prefix, &mut stream, unread_len)
+                        } else {
+                            BodyStream::buffered(prefix)
+                        };
+                        let response = call_limited(handler.as_ref(), &request, Some(&mut body), addr, &settings.route_limiter).await;
+                        body_drained = body.is_drained();
+                        response
+                    } else {
+                        call_limited(handler.as_ref(), &request, None, addr, &settings.route_limiter).await
+                    };
+                    let latency = started.elapsed();
+                    handler.on_response(&request, &response, latency, addr);
+                    // Sampling needs the status, so even the text line is written once the handler is done
+                    let consumed = request.consumed_len().to_string();
+                    let line = access_log::access_line(settings.log_format, &settings.log_sampler, &request, response.status_code(), latency, addr, &consumed);
+                    if let Some(line) = line {
+                        println!("{}", line);
+                    }
+                    // Leftover body bytes would be read as the next request, so the connection ends here
+                    let keep_alive = request.keep_alive() && body_drained;
+                    let options = SendOptions::new(request.version(), keep_alive, settings.secure)
+                        .with_encoding(ContentEncoding::negotiate(request.header("Accept-Encoding")))
+                        .with_compression_level(settings.compression_level)
+                        .with_head_only(*request.method() == Method::HEAD);
+                    (response, options, request.consumed_len())
+                },
+                Err(e) => {
+                    eprintln!("[{}] Parse error from {}: {}", request_id, addr, e);
+                    (handler.handle_error(&ServerError::Parse(e), None), SendOptions::new(Version::Http11, false, settings.secure), 0)
+                },
+            },
+        };
+
+        // Closing is announced on this response, not by dropping the socket after it
+        served += 1;
+        if *shutdown.borrow() || served >= settings.max_requests_per_connection {
+            options.keep_alive = false;
+        }
+        let options = options.with_max_body_bytes(settings.max_response_bytes);
+
+        let response = response
+            .with_request_id(&request_id)
+            .with_server_header(settings.server_header.as_deref())
+            .with_default_security_headers(&settings.security_headers);
+        match response.send(&mut stream, options).await {
+            Ok(()) => {},
+            Err(e) if is_client_disconnect(&e) => {
+                debug_log!(" [{}] Client {} disconnected before the response completed", request_id, addr);
+                return;
+            },
+            Err(e) => {
+                eprintln!("[{}] Failed to send response to {}: {}", request_id, addr, e);
+                return;
+            },
+        }
+
+        if !options.keep_alive {
+            return;
+        }
+
+        // Keep any pipelined bytes that follow this request for the next iteration
+        buffer.drain(..consumed);
+    }
+}
+
+// Runs the handler once the route has a free concurrency slot, holding it until the handler returns
+async fn call_limited<H: Handler>(handler: &H, request: &Request<'_>, body: Option<&mut BodyStream<'_>>, addr: SocketAddr, limiter: &RouteLimiter) -> Response {
+    match limiter.acquire(request.path()).await {
+        Ok(_permit) => match body {
+            Some(body) => call_handler(handler, request, addr, handler.handle_stream(request, body, addr)).await,
+            None => call_handler(handler, request, addr, handler.handle_request(request, addr)).await,
+        },
+        Err(()) => {
+            eprintln!("[{}] Concurrency limit for {} still full after queueing", request.request_id(), request.path());
+            handler.handle_error(&ServerError::Overloaded, Some(request.path()))
+        },
+    }
+}
+
+// A panicking handler must not take the connection down silently, so convert panics into a 500
+async fn call_handler<H: Handler>(handler: &H, request: &Request<'_>, addr: SocketAddr, response: impl Future<Output = Response>) -> Response {
+    match CatchUnwind(Box::pin(response)).await {
+        Ok(response) => response,
+        Err(payload) => {
+            let message = payload.downcast_ref::<&str>().copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic");
+            eprintln!("[{}] Handler panicked on {} {} from {}: {}", request.request_id(), request.method_str(), request.path(), addr, message);
+            handler.handle_error(&ServerError::Internal, Some(request.path()))
+        },
+    }
+}
+
+// Polls the wrapped future inside catch_unwind, so a panic at any await point surfaces as Err
+struct CatchUnwind<F>(Pin<Box<F>>);
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = std::thread::Result<F::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = self.get_mut().0.as_mut();
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(poll) => poll.map(Ok),
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+fn debug_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| std::env::var("SERVER_DEBUG").is_ok_and(|value| !value.is_empty() && value != "0"))
+}
+
+// Process-unique ids: a per-start random-ish prefix plus a monotonically increasing counter
+fn next_request_id() -> String {
+    static PREFIX: OnceLock<u32> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let prefix = PREFIX.get_or_init(|| {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or(0);
+        nanos ^ std::process::id().rotate_left(16)
+    });
+    let sequence = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    format!("{:08x}-{:012x}", prefix, sequence)
+}
+
+// Incoming ids end up in logs and headers, so only accept short, plain tokens
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+enum ReadError {
+    Closed,
+    Io(io::Error),
+    Parse(ParseError),
+    PayloadTooLarge,
+    // Header bytes arriving below the minimum rate, the slow-loris pattern
+    TooSlow,
+}
+
+impl ReadError {
+    // Closes and resets are the client leaving, which is routine and only logged at debug level
+    fn is_client_gone(&self) -> bool {
+        match self {
+            Self::Closed => true,
+            Self::Io(e) => is_client_disconnect(e),
+            _ => false,
+        }
+    }
+}
+
+// Waits for the first bytes of the next request
+async fn wait_for_request(stream: &mut TcpStream, buffer: &mut Vec<u8>, idle_timeout: Duration) -> Result<Result<(), ReadError>, Elapsed> {
+    Ok(match tokio::time::timeout(idle_timeout, read_more(stream, buffer)).await? {
+        Ok(0) => Err(ReadError::Closed),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e),
+    })
+}
+
+// Reads the header block, then exactly Content-Length body bytes, enforcing the body limit as data
+// arrives. When `defer_body` accepts the head, a Content-Length body is left on the socket for the
+// handler to stream and Ok(true) is returned.
+async fn read_request(stream: &mut TcpStream, buffer: &mut Vec<u8>, settings: &ConnectionSettings, defer_body: impl Fn(&Request) -> bool) -> Result<bool, ReadError> {
+    let max_body_bytes = settings.max_body_bytes;
+
+    // The rate clock starts with the request's first byte, so idle keep-alive time doesn't count
+    let mut started = (!buffer.is_empty()).then(Instant::now);
+    let initial_len = buffer.len();
+
+    // Reads continue from any pipelined bytes already left in the buffer
+    let head_end = loop {
+        if let Some((_, body_start)) = find_head_end(buffer) {
+            break body_start;
+        }
+        if buffer.len() > settings.header_limits.max_head_bytes {
+            return Err(ReadError::Parse(ParseError::HeadersTooLarge));
+        }
+        if read_more(stream, buffer).await? == 0 {
+            // A client that closes mid-headers still gets its partial request parsed (and most likely rejected)
+            return if buffer.is_empty() { Err(ReadError::Closed) } else { Ok(false) };
+        }
+        let started = *started.get_or_insert_with(Instant::now);
+        let elapsed = started.elapsed();
+        if elapsed > MIN_HEADER_RATE_GRACE
+            && ((buffer.len() - initial_len) as f64) < settings.min_header_rate as f64 * elapsed.as_secs_f64()
+        {
+            return Err(ReadError::TooSlow);
+        }
+    };
+
+    let (chunked, content_length, expects_continue, deferred) = match Request::parse_head(&buffer[..head_end], &settings.header_limits) {
+        Ok(request) => (
+            request.is_chunked(),
+            // A request with neither Content-Length nor chunked framing has no body (RFC 9112 §6.3),
+            // so an empty POST completes here without waiting for bytes
+            request.content_length().unwrap_or(0),
+            request.header("Expect").is_some_and(|value| value.eq_ignore_ascii_case("100-continue")),
+            // Chunked bodies are still buffered; decoding them incrementally isn't supported
+            !request.is_chunked() && defer_body(&request),
+        ),
+        Err(e) => return Err(ReadError::Parse(e)),
+    };
+
+    // Reject up front rather than buffering a body we already know is too large
+    if content_length > max_body_bytes {
+        return Err(ReadError::PayloadTooLarge);
+    }
+
+    // The client is holding the body back until we agree to accept it; never true for an empty body
+    let body_pending = if chunked { buffer.len() == head_end } else { buffer.len() - head_end < content_length };
+    if expects_continue && body_pending {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await.map_err(ReadError::Io)?;
+    }
+
+    if deferred {
+        return Ok(true);
+    }
+    if chunked {
+        return read_chunked_body(stream, buffer, head_end, max_body_bytes).await.map(|()| false);
+    }
+
+    while buffer.len() - head_end < content_length {
+        if read_more(stream, buffer).await? == 0 {
+            return Err(ReadError::Closed);
+        }
+        if buffer.len() - head_end > max_body_bytes {
+            return Err(ReadError::PayloadTooLarge);
+        }
+    }
+
+    Ok(false)
+}
+
+// Chunked bodies have no declared length, so the limit is enforced on the data as it arrives
+async fn read_chunked_body(stream: &mut TcpStream, buffer: &mut Vec<u8>, head_end: usize, max_body_bytes: usize) -> Result<(), ReadError> {
+    loop {
+        match chunked::decode(&buffer[head_end..], None, max_body_bytes) {
+            Ok(ChunkedStatus::Complete { .. }) => return Ok(()),
+            Ok(ChunkedStatus::Incomplete { decoded_len }) => {
+                // Framing overhead is small; a raw size far past the limit means padding or junk extensions
+                if decoded_len > max_body_bytes || buffer.len() - head_end > max_body_bytes.saturating_mul(2) {
+                    return Err(ReadError::PayloadTooLarge);
+                }
+            },
+            Err(ParseError::BodyTooLarge) => return Err(ReadError::PayloadTooLarge),
+            Err(e) => return Err(ReadError::Parse(e)),
+        }
+
+        if read_more(stream, buffer).await? == 0 {
+            return Err(ReadError::Closed);
+        }
+    }
+}
+
+async fn read_more(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> Result<usize, ReadError> {
+    buffer.reserve(4096);
+    stream.read_buf(buffer).await.map_err(ReadError::Io)
+}
+
+// systemd socket activation: inherited descriptors start at fd 3 and LISTEN_PID names the intended process
+#[cfg(unix)]
+fn inherited_listener() -> io::Result<Option<TcpListener>> {
+    use std::os::unix::io::FromRawFd;
+
+    const SD_LISTEN_FDS_START: i32 = 3;
+
+    let fd_count = match std::env::var("LISTEN_FDS").ok().and_then(|fds| fds.parse::<u32>().ok()) {
+        Some(count) if count > 0 => count,
+        _ => return Ok(None),
+    };
+
+    if let Some(pid) = std::env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) {
+        if pid != std::process::id() {
+            return Ok(None);
+        }
+    }
+
+    if fd_count > 1 {
+        eprintln!("LISTEN_FDS={} but only the first inherited socket is used", fd_count);
+    }
+
+    // SAFETY: socket activation hands this process ownership of fd 3, which nothing else has opened
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener).map(Some)
+}
+
+#[cfg(not(unix))]
+fn inherited_listener() -> io::Result<Option<TcpListener>> {
+    Ok(None)
+}
+
+// Broken pipes and resets mean the client went away, which is routine rather than a server fault
+fn is_client_disconnect(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted | io::ErrorKind::UnexpectedEof
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::{self, Echo};
+    use super::*;
+    use crate::security::SecurityConfig;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpStream;
+    use tokio::sync::{oneshot, Notify};
+    use tokio::task::JoinHandle;
+
+    #[tokio::test]
+    async fn responses_echo_the_request_version() {
+        let addr = testing::spawn(Server::new(String::new()), Echo).await;
+        let response = testing::exchange(addr, b"GET / HTTP/1.0\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{}", response);
+        assert_eq!(testing::header(&response, "Connection"), Some("close"));
+
+        let response = testing::exchange(addr, b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+        let response = testing::exchange(addr, b"GET / HTTP/3.0\r\nHost: localhost\r\n\r\n").await;
+        assert_eq!(testing::status(&response), 505);
+    }
+
+    // Echoes once `ready` is set
+    struct Warming(Arc<AtomicBool>);
+
+    impl Handler for Warming {
+        async fn handle_request(&self, request: &Request<'_>, client_ip: SocketAddr) -> Response {
+            Echo.handle_request(request, client_ip).await
+        }
+
+        fn is_ready(&self) -> bool {
+            self.0.load(Ordering::Acquire)
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_before_ready_get_503_except_health_checks() {
+        let ready = Arc::new(AtomicBool::new(false));
+        let addr = testing::spawn(Server::new(String::new()), Warming(Arc::clone(&ready))).await;
+        let page = b"GET /page HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+        let response = testing::exchange(addr, page).await;
+        assert_eq!(testing::status(&response), 503);
+        assert_eq!(testing::header(&response, "Retry-After"), Some("5"));
+        let response = testing::exchange(addr, b"GET /api/ping HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        assert_eq!(testing::status(&response), 200);
+
+        ready.store(true, Ordering::Release);
+        let response = testing::exchange(addr, page).await;
+        assert_eq!(testing::status(&response), 200);
+        assert_eq!(testing::body(&response), "GET /page");
+    }
+
+    #[tokio::test]
+    async fn a_reader_closing_mid_response_is_a_client_disconnect() {
+        let (mut writer, mut reader) = tokio::io::duplex(64);
+        let early_close = tokio::spawn(async move {
+            let mut start = [0; 16];
+            reader.read_exact(&mut start).await.unwrap();
+        });
+        let response = Response::new(StatusCode::Ok, Some("a".repeat(64 * 1024)));
+        let error = response.send(&mut writer, SendOptions::closing()).await.unwrap_err();
+        early_close.await.unwrap();
+        assert!(is_client_disconnect(&error), "{:?}", error);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_on_a_handed_over_listener() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let request = testing::exchange(addr, b"GET /handoff HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        let response = tokio::select! {
+            result = Server::new(String::new()).run_with_listener(listener, Echo) => panic!("server stopped: {:?}", result.err()),
+            response = request => response,
+        };
+        assert_eq!(testing::body(&response), "GET /handoff");
+    }
+
+    // Other loopback addresses need no setup on Linux
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn connections_beyond_the_per_ip_cap_get_503() {
+        let addr = testing::spawn(Server::new(String::new()).with_max_connections_per_ip(1), Echo).await;
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        let mut held = TcpStream::connect(addr).await.unwrap();
+
+        // Refused on accept, before any request is read
+        let mut refused = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(testing::status(&testing::read_to_close(&mut refused).await), 503);
+
+        let other_ip = tokio::net::TcpSocket::new_v4().unwrap();
+        other_ip.bind("127.0.0.2:0".parse().unwrap()).unwrap();
+        let mut stream = other_ip.connect(addr).await.unwrap();
+        stream.write_all(request).await.unwrap();
+        assert_eq!(testing::status(&testing::read_to_close(&mut stream).await), 200);
+
+        held.write_all(request).await.unwrap();
+        assert_eq!(testing::status(&testing::read_to_close(&mut held).await), 200);
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_rejected_up_front_or_as_they_stream() {
+        let addr = testing::spawn(Server::new(String::new()).with_max_body_bytes(1024), Echo).await;
+
+        // Only the head is sent: the declared length alone is enough to refuse
+        let response = testing::exchange(addr, b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2048\r\n\r\n").await;
+        assert_eq!(testing::status(&response), 413);
+
+        // Each chunk is within the limit; the running total is not
+        let piece = format!("200\r\n{}\r\n", "a".repeat(0x200));
+        let request = format!("POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n{}", piece.repeat(3));
+        let response = testing::exchange(addr, request.as_bytes()).await;
+        assert_eq!(testing::status(&response), 413);
+
+        let request = format!("POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 1024\r\nConnection: close\r\n\r\n{}", "a".repeat(1024));
+        assert_eq!(testing::status(&testing::exchange(addr, request.as_bytes()).await), 200);
+    }
+
+    // Panics on /sync before doing anything, and on /async after an await point
+    struct Panics;
+
+    impl Handler for Panics {
+        async fn handle_request(&self, request: &Request<'_>, _client_ip: SocketAddr) -> Response {
+            if request.path() == "/async" {
+                tokio::task::yield_now().await;
+            }
+            panic!("handler bug on {}", request.path());
+        }
+
+        fn streams_body(&self, request: &Request) -> bool {
+            request.path() == "/upload"
+        }
+
+        async fn handle_stream(&self, request: &Request<'_>, body: &mut BodyStream<'_>, _client_ip: SocketAddr) -> Response {
+            body.next_chunk().await.unwrap();
+            panic!("streaming handler bug on {}", request.path());
+        }
+    }
+
+    #[tokio::test]
+    async fn handler_panics_become_500s() {
+        let addr = testing::spawn(Server::new(String::new()), Panics).await;
+        for path in ["/sync", "/async"] {
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+            let response = testing::exchange(addr, request.as_bytes()).await;
+            assert_eq!(testing::status(&response), 500, "{}", path);
+            assert!(testing::body(&response).starts_with("Internal server error (request id "), "{}", response);
+        }
+
+        // Streaming handlers run behind the same guard
+        let response = testing::exchange(addr, b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello").await;
+        assert_eq!(testing::status(&response), 500);
+    }
+
+    // 404s /missing and records every status the server reports back through on_response
+    #[derive(Default)]
+    struct Observed(Arc<Mutex<Vec<(String, StatusCode)>>>);
+
+    impl Handler for Observed {
+        async fn handle_request(&self, request: &Request<'_>, _client_ip: SocketAddr) -> Response {
+            match request.path() {
+                "/missing" => Response::new(StatusCode::NotFound, None),
+                _ => Response::new(StatusCode::Ok, None),
+            }
+        }
+
+        fn on_response(&self, request: &Request, response: &Response, _latency: Duration, _client_ip: SocketAddr) {
+            self.0.lock().unwrap().push((request.path().to_string(), response.status_code()));
+        }
+    }
+
+    #[tokio::test]
+    async fn on_response_sees_the_final_status() {
+        let observed = Observed::default();
+        let seen = Arc::clone(&observed.0);
+        let addr = testing::spawn(Server::new(String::new()), observed).await;
+        testing::exchange(addr, b"GET /found HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        testing::exchange(addr, b"GET /missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+
+        assert_eq!(*seen.lock().unwrap(), [
+            ("/found".to_string(), StatusCode::Ok),
+            ("/missing".to_string(), StatusCode::NotFound),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn expect_continue_is_acknowledged_before_the_body_is_read() {
+        let addr = testing::spawn(Server::new(String::new()).with_max_body_bytes(1024), Echo).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-continue\r\nConnection: close\r\n\r\n").await.unwrap();
+
+        let mut interim = [0; 25];
+        tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut interim)).await.unwrap().unwrap();
+        assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+        stream.write_all(b"hello").await.unwrap();
+        assert_eq!(testing::status(&testing::read_to_close(&mut stream).await), 200);
+
+        // Too large to accept, so the final status comes instead of the go-ahead
+        let response = testing::exchange(addr, b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4096\r\nExpect: 100-continue\r\n\r\n").await;
+        assert_eq!(testing::status(&response), 413);
+    }
+
+    #[tokio::test]
+    async fn pipelined_requests_are_answered_in_order() {
+        let addr = testing::spawn(Server::new(String::new()), Echo).await;
+        let response = testing::exchange(addr, b"GET /first HTTP/1.1\r\nHost: localhost\r\n\r\nGET /second HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+
+        let bodies: Vec<&str> = response.split("HTTP/1.1 ").skip(1).map(testing::body).collect();
+        assert_eq!(bodies, ["GET /first", "GET /second"], "{}", response);
+
+        // A body in the same write ends where its Content-Length says
+        let response = testing::exchange(addr, b"POST /form HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\n\r\nGETGET /after HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        let bodies: Vec<&str> = response.split("HTTP/1.1 ").skip(1).map(testing::body).collect();
+        assert_eq!(bodies, ["POST /form", "GET /after"], "{}", response);
+    }
+
+    #[tokio::test]
+    async fn server_header_can_be_customized_or_omitted() {
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        for (configured, expected) in [(None, None), (Some("Custom/2.0"), Some("Custom/2.0"))] {
+            let server = Server::new(String::new()).with_server_header(configured.map(str::to_string));
+            let response = testing::exchange(testing::spawn(server, Echo).await, request).await;
+            assert_eq!(testing::header(&response, "Server"), expected);
+        }
+        let response = testing::exchange(testing::spawn(Server::new(String::new()), Echo).await, request).await;
+        assert_eq!(testing::header(&response, "Server"), Some(DEFAULT_SERVER_HEADER));
+    }
+
+    #[tokio::test]
+    async fn hsts_is_only_sent_on_secure_listeners() {
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        for secure in [false, true] {
+            let addr = testing::spawn(Server::new(String::new()).with_secure_transport(secure), Echo).await;
+            let response = testing::exchange(addr, request).await;
+            assert_eq!(testing::header(&response, "Strict-Transport-Security").is_some(), secure);
+        }
+    }
+
+    #[tokio::test]
+    async fn request_ids_are_generated_or_preserved() {
+        let addr = testing::spawn(Server::new(String::new()), Echo).await;
+        let response = testing::exchange(addr, b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        let generated = testing::header(&response, "X-Request-Id").expect("no request id");
+        assert!(is_valid_request_id(generated), "{}", generated);
+
+        let response = testing::exchange(addr, b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Request-Id: trace-42\r\nConnection: close\r\n\r\n").await;
+        assert_eq!(testing::header(&response, "X-Request-Id"), Some("trace-42"));
+        // Ids that could forge log lines are replaced
+        let response = testing::exchange(addr, b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Request-Id: a b\"c\r\nConnection: close\r\n\r\n").await;
+        assert_ne!(testing::header(&response, "X-Request-Id"), Some("a b\"c"));
+    }
+
+    #[tokio::test]
+    async fn chunked_post_is_decoded_and_oversized_chunks_rejected() {
+        let addr = testing::spawn(Server::new(String::new()).with_max_body_bytes(1024), Echo).await;
+
+        let response = testing::exchange(addr, b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n4\r\nWiki\r\n0\r\n\r\n").await;
+        assert_eq!(testing::status(&response), 200);
+
+        // A size line of usize::MAX must not overflow the decoder and panic the connection task
+        let response = testing::exchange(addr, b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\nFFFFFFFFFFFFFFFF\r\n").await;
+        assert_eq!(testing::status(&response), 413);
+
+        let response = testing::exchange(addr, b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n").await;
+        assert_eq!(testing::status(&response), 400);
+    }
+
+    #[tokio::test]
+    async fn unsupported_transfer_encoding_and_method_get_501() {
+        let addr = testing::spawn(Server::new(String::new()), Echo).await;
+        let response = testing::exchange(addr, b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: gzip\r\n\r\n0\r\n\r\n").await;
+        assert_eq!(testing::status(&response), 501);
+        let response = testing::exchange(addr, b"PROPFIND / HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert_eq!(testing::status(&response), 501);
+    }
+
+    #[tokio::test]
+    async fn maximal_chunk_size_without_a_body_limit_is_a_bad_request() {
+        let addr = testing::spawn(Server::new(String::new()), Echo).await;
+        let response = testing::exchange(addr, b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\nFFFFFFFFFFFFFFFF\r\nabc\r\n0\r\n\r\n").await;
+        assert_eq!(testing::status(&response), 400);
+    }
+
+    #[tokio::test]
+    async fn oversized_header_blocks_get_431() {
+        let addr = testing::spawn(Server::new(String::new()).with_max_head_bytes(1024), Echo).await;
+        let request = format!("GET / HTTP/1.1\r\nHost: localhost\r\nX-Bloat: {}\r\n\r\n", "a".repeat(2048));
+        assert_eq!(testing::status(&testing::exchange(addr, request.as_bytes()).await), 431);
+
+        let request = format!("GET / HTTP/1.1\r\nHost: localhost\r\nX-Fine: {}\r\nConnection: close\r\n\r\n", "a".repeat(512));
+        assert_eq!(testing::status(&testing::exchange(addr, request.as_bytes()).await), 200);
+    }
+
+    #[test]
+    fn closes_and_resets_are_not_read_errors() {
+        assert!(ReadError::Closed.is_client_gone());
+        for kind in [io::ErrorKind::ConnectionReset, io::ErrorKind::ConnectionAborted, io::ErrorKind::UnexpectedEof] {
+            assert!(ReadError::Io(kind.into()).is_client_gone(), "{:?}", kind);
+        }
+        assert!(!ReadError::Io(io::ErrorKind::PermissionDenied.into()).is_client_gone());
+        assert!(!ReadError::PayloadTooLarge.is_client_gone());
+    }
+
+    // Counts every trip through the error hook, which is where failures become responses
+    #[derive(Default)]
+    struct ErrorCount(Arc<AtomicUsize>);
+
+    impl Handler for ErrorCount {
+        async fn handle_request(&self, _request: &Request<'_>, _client_ip: SocketAddr) -> Response {
+            Response::new(StatusCode::Ok, None)
+        }
+
+        fn handle_error(&self, error: &ServerError, path: Option<&str>) -> Response {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            error.to_response(path)
+        }
+    }
+
+    #[tokio::test]
+    async fn probes_that_connect_and_leave_are_quiet() {
+        let handler = ErrorCount::default();
+        let errors = Arc::clone(&handler.0);
+        let addr = testing::spawn(Server::new(String::new()), handler).await;
+
+        drop(TcpStream::connect(addr).await.unwrap());
+        let reset = TcpStream::connect(addr).await.unwrap();
+        // A zero linger makes the drop send a RST; with no timeout there is nothing to block on
+        #[allow(deprecated)]
+        reset.set_linger(Some(Duration::ZERO)).unwrap();
+        drop(reset);
+
+        let response = testing::exchange(addr, b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        assert_eq!(testing::status(&response), 200);
+        assert_eq!(errors.load(Ordering::Relaxed), 0);
+    }
+
+    // Answers every error with 406 so tests can tell the override ran
+    struct OverridesErrors;
+
+    impl Handler for OverridesErrors {
+        async fn handle_request(&self, _request: &Request<'_>, _client_ip: SocketAddr) -> Response {
+            Response::new(StatusCode::Ok, None)
+        }
+
+        fn handle_error(&self, error: &ServerError, _path: Option<&str>) -> Response {
+            Response::new(StatusCode::NotAcceptable, Some(format!("{} ({})", error, error.status_code() as u16)))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn mid_request_timeout_goes_through_handle_error() {
+        let addr = testing::spawn(Server::new(String::new()), OverridesErrors).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: local").await.unwrap();
+        // No timeout of our own: with the clock paused it would fire before the server's
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert_eq!(testing::status(&response), 406);
+        assert_eq!(testing::body(&response), "Request timeout (408)");
+    }
+
+    // Announces each request on `started`, then takes `delay` to answer it
+    struct Slow {
+        delay: Duration,
+        started: Arc<Notify>,
+    }
+
+    impl Handler for Slow {
+        async fn handle_request(&self, _request: &Request<'_>, _client_ip: SocketAddr) -> Response {
+            self.started.notify_one();
+            tokio::time::sleep(self.delay).await;
+            Response::new(StatusCode::Ok, None)
+        }
+    }
+
+    // How long the server takes to stop once shut down; with a delay, a request is in flight first
+    async fn time_shutdown(delay: Option<Duration>, drain_timeout: Duration) -> (Duration, Option<JoinHandle<String>>) {
+        let started = Arc::new(Notify::new());
+        let handler = Slow { delay: delay.unwrap_or_default(), started: Arc::clone(&started) };
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = Server::new(String::new()).with_drain_timeout(drain_timeout);
+        let (addr, task) = testing::spawn_until(server, handler, async { let _ = stopped.await; }).await;
+
+        let client = delay.map(|_| tokio::spawn(testing::exchange(addr, b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")));
+        if client.is_some() {
+            started.notified().await;
+        }
+        let shutdown_at = Instant::now();
+        stop.send(()).unwrap();
+        task.await.unwrap();
+        (shutdown_at.elapsed(), client)
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_in_flight_requests_up_to_the_drain_timeout() {
+        let (idle, _) = time_shutdown(None, Duration::from_secs(30)).await;
+        assert!(idle < Duration::from_secs(1), "{:?}", idle);
+
+        let (finished, client) = time_shutdown(Some(Duration::from_millis(500)), Duration::from_secs(30)).await;
+        assert!(finished >= Duration::from_millis(400) && finished < Duration::from_secs(5), "{:?}", finished);
+        assert_eq!(testing::status(&client.unwrap().await.unwrap()), 200);
+
+        let (abandoned, _) = time_shutdown(Some(Duration::from_secs(60)), Duration::from_millis(300)).await;
+        assert!(abandoned >= Duration::from_millis(300) && abandoned < Duration::from_secs(5), "{:?}", abandoned);
+    }
+
+    #[test]
+    fn pooled_buffers_come_back_empty_and_large_ones_are_freed() {
+        let pool = Arc::new(BufferPool::default());
+        let mut buffer = pool.take();
+        buffer.extend_from_slice(b"POST /login HTTP/1.1\r\n\r\npassword=hunter2");
+        let allocation = buffer.as_ptr();
+        drop(buffer);
+
+        let reused = pool.take();
+        assert_eq!(reused.as_ptr(), allocation);
+        assert!(reused.is_empty());
+        drop(reused);
+
+        pool.take().reserve(MAX_POOLED_BUFFER_CAPACITY + 1);
+        assert!(pool.idle.lock().unwrap().is_empty());
+    }
+
+    // Answers with the request body, so anything left over from an earlier request would show
+    struct BodyEcho;
+
+    impl Handler for BodyEcho {
+        async fn handle_request(&self, request: &Request<'_>, _client_ip: SocketAddr) -> Response {
+            Response::with_bytes(StatusCode::Ok, request.body().to_vec(), "application/octet-stream")
+        }
+    }
+
+    #[tokio::test]
+    async fn nothing_from_one_request_reaches_the_next() {
+        let addr = testing::spawn(Server::new(String::new()), BodyEcho).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        stream.write_all(b"POST /login HTTP/1.1\r\nHost: localhost\r\nContent-Length: 16\r\n\r\npassword=hunter2").await.unwrap();
+        assert_eq!(testing::body(&testing::read_response(&mut stream).await), "password=hunter2");
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        assert_eq!(testing::body(&testing::read_response(&mut stream).await), "");
+        drop(stream);
+
+        // A new connection may be handed the first one's buffer from the pool
+        let response = testing::exchange(addr, b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        assert_eq!(testing::status(&response), 200);
+        assert_eq!(testing::body(&response), "");
+    }
+
+    #[tokio::test]
+    async fn connection_header_follows_the_version_and_the_client() {
+        let addr = testing::spawn(Server::new(String::new()), Echo).await;
+        let cases = [
+            ("HTTP/1.1", "", true),
+            ("HTTP/1.1", "Connection: close\r\n", false),
+            ("HTTP/1.0", "", false),
+            ("HTTP/1.0", "Connection: keep-alive\r\n", true),
+        ];
+        for (version, connection, keep_alive) in cases {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET / {}\r\nHost: localhost\r\n{}\r\n", version, connection);
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let response = testing::read_response(&mut stream).await;
+            assert_eq!(testing::header(&response, "Connection"), Some(if keep_alive { "keep-alive" } else { "close" }), "{} {:?}", version, connection);
+
+            // The socket is only reusable when keep-alive was sent
+            let mut rest = Vec::new();
+            let closed = tokio::time::timeout(Duration::from_millis(200), stream.read_to_end(&mut rest)).await.is_ok();
+            assert_eq!(closed, !keep_alive, "{} {:?}", version, connection);
+        }
+    }
+
+    #[tokio::test]
+    async fn the_last_allowed_request_is_told_the_connection_closes() {
+        let addr = testing::spawn(Server::new(String::new()).with_max_requests_per_connection(2), Echo).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /1 HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        assert_eq!(testing::header(&testing::read_response(&mut stream).await, "Connection"), Some("keep-alive"));
+        stream.write_all(b"GET /2 HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        assert_eq!(testing::header(&testing::read_to_close(&mut stream).await, "Connection"), Some("close"));
+    }
+
+    // Answers with the scheme the request reports
+    struct Scheme;
+
+    impl Handler for Scheme {
+        async fn handle_request(&self, request: &Request<'_>, _client_ip: SocketAddr) -> Response {
+            Response::new(StatusCode::Ok, Some(if request.is_secure() { "https" } else { "http" }.to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_know_whether_the_listener_is_secure() {
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        for (secure, scheme) in [(false, "http"), (true, "https")] {
+            let addr = testing::spawn(Server::new(String::new()).with_secure_transport(secure), Scheme).await;
+            assert_eq!(testing::body(&testing::exchange(addr, request).await), scheme);
+        }
+    }
+
+    #[tokio::test]
+    async fn header_floods_from_the_security_config_get_431() {
+        let config = SecurityConfig { max_head_bytes: 1024 * 1024, ..SecurityConfig::default() };
+        let addr = testing::spawn(Server::builder().security_config(&config).build(), Echo).await;
+
+        let many: String = (0..2000).map(|i| format!("X-{}: 1\r\n", i)).collect();
+        let long = format!("X-Long: {}\r\n", "a".repeat(config.max_header_bytes));
+        for headers in [many, long] {
+            let request = format!("GET / HTTP/1.1\r\nHost: localhost\r\n{}\r\n", headers);
+            assert_eq!(testing::status(&testing::exchange(addr, request.as_bytes()).await), 431);
+        }
+    }
+
+    #[tokio::test]
+    async fn bare_lf_requests_are_answered_with_crlf() {
+        let addr = testing::spawn(Server::new(String::new()), Echo).await;
+        let response = testing::exchange(addr, b"GET /lf HTTP/1.1\nHost: localhost\nConnection: close\n\n").await;
+        assert_eq!(testing::status(&response), 200);
+        assert_eq!(testing::body(&response), "GET /lf");
+        let head = response.split("\r\n\r\n").next().unwrap();
+        assert!(!head.replace("\r\n", "").contains('\n'), "{}", head);
+    }
+
+    #[test]
+    fn a_single_worker_runtime_serves_concurrent_requests() {
+        let server = Server::builder().bind("127.0.0.1:0").worker_threads(1).build();
+        let runtime = server.runtime().unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 1);
+
+        runtime.block_on(async {
+            let addr = testing::spawn(server, Echo).await;
+            let requests = ["/a", "/b", "/c"].map(|path| format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path));
+            let (a, b, c) = tokio::join!(
+                testing::exchange(addr, requests[0].as_bytes()),
+                testing::exchange(addr, requests[1].as_bytes()),
+                testing::exchange(addr, requests[2].as_bytes()),
+            );
+            assert_eq!([testing::body(&a), testing::body(&b), testing::body(&c)], ["GET /a", "GET /b", "GET /c"]);
+        });
+    }
+
+    #[tokio::test]
+    async fn a_trickling_sender_is_dropped_before_finishing_its_request() {
+        let addr = testing::spawn(Server::new(String::new()).with_min_header_rate(100), Echo).await;
+        let (mut reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\nUser-Agent: slowloris\r\n\r\n";
+
+        // At a byte every 250ms the whole request would take over 15 seconds
+        let trickle = tokio::spawn(async move {
+            for byte in request {
+                if writer.write_all(&[*byte]).await.is_err() {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(250)).await;
+            }
+        });
+
+        let started = Instant::now();
+        let mut response = Vec::new();
+        let closed = tokio::time::timeout(Duration::from_secs(8), reader.read_to_end(&mut response)).await;
+        assert!(closed.is_ok(), "slow sender was not dropped");
+        assert!(response.is_empty());
+        assert!(started.elapsed() >= MIN_HEADER_RATE_GRACE);
+        trickle.abort();
+    }
+
+    #[tokio::test]
+    async fn small_keep_alive_responses_are_not_held_back_by_nagle() {
+        let addr = testing::spawn(Server::new(String::new()), Echo).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.set_nodelay(true).unwrap();
+
+        // Nagle plus delayed ACKs stalls a round trip for ~40ms; 20 of them would take most of a second
+        let started = Instant::now();
+        for _ in 0..20 {
+            stream.write_all(b"GET /small HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+            assert_eq!(testing::body(&testing::read_response(&mut stream).await), "GET /small");
+        }
+        assert!(started.elapsed() < Duration::from_millis(400), "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn serves_ipv6_clients() {
+        // Skipped where the host has no IPv6 loopback
+        let Ok(listener) = TcpListener::bind("[::1]:0").await else { return };
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            Server::new(String::new()).serve(listener, Echo, std::future::pending()).await.unwrap();
+        });
+
+        let response = testing::exchange(addr, b"GET /v6 HTTP/1.1\r\nHost: [::1]\r\nConnection: close\r\n\r\n").await;
+        assert_eq!(testing::body(&response), "GET /v6");
+    }
+
+    #[tokio::test]
+    async fn requests_past_a_route_limit_queue_then_get_503() {
+        use crate::security::ConcurrencyLimit;
+
+        let request = b"GET /slow HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        let run_two = |queue_timeout| async move {
+            let limits = [ConcurrencyLimit { path_prefix: "/slow", max_concurrent: 1 }];
+            let handler = Slow { delay: Duration::from_millis(300), started: Arc::new(Notify::new()) };
+            let addr = testing::spawn(Server::new(String::new()).with_route_concurrency(&limits, queue_timeout), handler).await;
+            let started = Instant::now();
+            let (a, b) = tokio::join!(testing::exchange(addr, request), testing::exchange(addr, request));
+            let mut statuses = [testing::status(&a), testing::status(&b)];
+            statuses.sort();
+            (statuses, started.elapsed())
+        };
+
+        // The second request waits for the first to finish, so the pair takes two handler runs
+        let (statuses, elapsed) = run_two(Duration::from_secs(5)).await;
+        assert_eq!(statuses, [200, 200]);
+        assert!(elapsed >= Duration::from_millis(600), "{:?}", elapsed);
+
+        let (statuses, _) = run_two(Duration::from_millis(50)).await;
+        assert_eq!(statuses, [200, 503]);
+    }
+
+    #[tokio::test]
+    async fn the_idle_timeout_restarts_for_every_request() {
+        let addr = testing::spawn(Server::new(String::new()).with_idle_timeout(Duration::from_millis(300)), Echo).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        // Together the gaps exceed the idle timeout, but none does on its own
+        for i in 0..4 {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            stream.write_all(format!("GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n", i).as_bytes()).await.unwrap();
+            assert_eq!(testing::body(&testing::read_response(&mut stream).await), format!("GET /{}", i));
+        }
+
+        let started = Instant::now();
+        assert_eq!(testing::read_to_close(&mut stream).await, "");
+        assert!(started.elapsed() >= Duration::from_millis(250), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn accept_throttle_allows_a_burst_then_spaces_accepts_out() {
+        let mut unlimited = AcceptThrottle::new(0);
+        assert!((0..1000).all(|_| unlimited.delay().is_none()));
+
+        let mut throttle = AcceptThrottle::new(5);
+        assert!((0..5).all(|_| throttle.delay().is_none()));
+        let wait = throttle.delay().expect("burst beyond the rate was not throttled");
+        assert!(wait > Duration::from_millis(150) && wait <= Duration::from_millis(200), "{:?}", wait);
+
+        std::thread::sleep(wait);
+        assert!(throttle.delay().is_none());
+    }
+
+    #[tokio::test]
+    async fn connection_bursts_beyond_the_accept_rate_are_spread_out() {
+        let addr = testing::spawn(Server::new(String::new()).with_max_accept_rate(5), Echo).await;
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+        // Five go straight through; the other five wait for a token each, 200ms apart
+        let started = Instant::now();
+        let clients: Vec<_> = (0..10).map(|_| tokio::spawn(testing::exchange(addr, request))).collect();
+        for client in clients {
+            assert_eq!(testing::status(&client.await.unwrap()), 200);
+        }
+        assert!(started.elapsed() >= Duration::from_millis(800), "{:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn idle_keep_alive_connections_close_without_a_408() {
+        let handler = ErrorCount::default();
+        let errors = Arc::clone(&handler.0);
+        let addr = testing::spawn(Server::new(String::new()).with_idle_timeout(Duration::from_millis(100)), handler).await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        assert_eq!(testing::header(&testing::read_response(&mut stream).await, "Connection"), Some("keep-alive"));
+        assert_eq!(testing::read_to_close(&mut stream).await, "");
+
+        // Nor does a connection that never sent anything
+        let mut silent = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(testing::read_to_close(&mut silent).await, "");
+        assert_eq!(errors.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn repeated_host_headers_are_rejected_before_reaching_the_handler() {
+        let addr = testing::spawn(Server::new(String::new()), testing::Echo).await;
+        // Both copies name an allowed host, so only the repetition itself can be the reason
+        let repeated = testing::exchange(addr, b"GET / HTTP/1.1\r\nHost: localhost:8080\r\nHost: localhost:8080\r\n\r\n").await;
+        assert_eq!(testing::status(&repeated), 400);
+        assert_ne!(testing::body(&repeated), "GET /");
+
+        let single = testing::exchange(addr, b"GET / HTTP/1.1\r\nHost: localhost:8080\r\nConnection: close\r\n\r\n").await;
+        assert_eq!(testing::body(&single), "GET /");
+    }
+
+    // Streams every body and counts it, remembering the largest piece it was ever handed at once
+    #[derive(Clone, Default)]
+    struct ByteCounter {
+        calls: Arc<AtomicUsize>,
+        largest_piece: Arc<AtomicUsize>,
+    }
+
+    impl Handler for ByteCounter {
+        async fn handle_request(&self, _request: &Request<'_>, _client_ip: SocketAddr) -> Response {
+            Response::new(StatusCode::Ok, None)
+        }
+
+        fn streams_body(&self, _request: &Request) -> bool {
+            true
+        }
+
+        async fn handle_stream(&self, _request: &Request<'_>, body: &mut BodyStream<'_>, _client_ip: SocketAddr) -> Response {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            let mut received = 0;
+            while let Some(piece) = body.next_chunk().await.unwrap() {
+                received += piece.len();
+                self.largest_piece.fetch_max(piece.len(), Ordering::Relaxed);
+            }
+            Response::new(StatusCode::Ok, Some(received.to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn streaming_handlers_count_bodies_without_buffering_them() {
+        const BODY_LEN: usize = 256 * 1024;
+        let counter = ByteCounter::default();
+        let addr = testing::spawn(Server::new(String::new()).with_max_body_bytes(BODY_LEN), counter.clone()).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        let head = format!("POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n", BODY_LEN);
+        stream.write_all(head.as_bytes()).await.unwrap();
+        for _ in 0..BODY_LEN / 4096 {
+            stream.write_all(&[b'x'; 4096]).await.unwrap();
+        }
+        assert_eq!(testing::body(&testing::read_response(&mut stream).await), BODY_LEN.to_string());
+        assert!(counter.largest_piece.load(Ordering::Relaxed) < BODY_LEN / 4);
+
+        // The connection stays usable, and the size limit still holds before anything is streamed
+        stream.write_all(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello").await.unwrap();
+        assert_eq!(testing::body(&testing::read_response(&mut stream).await), "5");
+        let head = format!("POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n", BODY_LEN + 1);
+        assert_eq!(testing::status(&testing::exchange(addr, head.as_bytes()).await), 413);
+        assert_eq!(counter.calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn server_errors_quote_the_request_id_without_internal_detail() {
+        let addr = testing::spawn(Server::new(String::new()), Panics).await;
+
+        let page = testing::exchange(addr, b"GET /sync HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        let request_id = testing::header(&page, "X-Request-Id").expect("no request id");
+        assert_eq!(testing::body(&page), format!("Internal server error (request id {})", request_id));
+
+        // A supplied id is echoed back and quoted, and API clients get it as a JSON field
+        let api = testing::exchange(addr, b"GET /api/sync HTTP/1.1\r\nHost: localhost\r\nX-Request-Id: support-ticket-42\r\nConnection: close\r\n\r\n").await;
+        assert_eq!(testing::header(&api, "X-Request-Id"), Some("support-ticket-42"));
+        let body: serde_json::Value = serde_json::from_str(testing::body(&api)).unwrap();
+        assert_eq!(body["request_id"], "support-ticket-42");
+        assert_eq!(body["success"], false);
+
+        for response in [&page, &api] {
+            assert!(!response.contains("handler bug") && !response.contains("panicked"), "{}", response);
+        }
+    }
+
+    #[tokio::test]
+    async fn builder_setters_override_the_security_config() {
+        let config = SecurityConfig {
+            keep_alive_timeout: Duration::from_secs(60),
+            max_connections_per_ip: 64,
+            ..SecurityConfig::default()
+        };
+        // Called before security_config on purpose: the explicit setters still win
+        let server = Server::builder()
+            .idle_timeout(Duration::from_millis(100))
+            .max_connections_per_ip(1)
+            .security_config(&config)
+            .build();
+        let addr = testing::spawn(server, Echo).await;
+
+        let mut held = TcpStream::connect(addr).await.unwrap();
+        let mut refused = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(testing::status(&testing::read_to_close(&mut refused).await), 503);
+        // Well inside the config's 60s keep-alive, so only the builder's idle timeout closes it
+        let started = Instant::now();
+        assert_eq!(testing::read_to_close(&mut held).await, "");
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn blank_and_method_only_request_lines_get_400() {
+        let addr = testing::spawn(Server::new(String::new()), Echo).await;
+        for raw in [&b"\r\n\r\n"[..], b" \r\n\r\n", b"GET\r\n\r\n"] {
+            let response = testing::exchange(addr, raw).await;
+            assert_eq!(testing::status(&response), 400, "{:?}", String::from_utf8_lossy(raw));
+        }
+        // None of them took the server down
+        let response = testing::exchange(addr, b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        assert_eq!(testing::body(&response), "GET /");
+    }
+
+    // A body large and repetitive enough to be worth compressing
+    struct Compressible;
+
+    impl Handler for Compressible {
+        async fn handle_request(&self, _request: &Request<'_>, _client_ip: SocketAddr) -> Response {
+            Response::new(StatusCode::Ok, Some("compressible text ".repeat(200)))
+        }
+    }
+
+    #[tokio::test]
+    async fn head_responses_match_get_headers_but_send_no_body() {
+        let addr = testing::spawn(Server::new(String::new()), Compressible).await;
+        let request = |method: &str| format!("{} /page HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nConnection: close\r\n\r\n", method);
+
+        let get = testing::exchange(addr, request("GET").as_bytes()).await;
+        let head = testing::exchange(addr, request("HEAD").as_bytes()).await;
+        assert_eq!(testing::header(&get, "Content-Encoding"), Some("gzip"));
+        for name in ["Content-Encoding", "Content-Length", "Content-Type", "Vary"] {
+            assert_eq!(testing::header(&head, name), testing::header(&get, name), "{}", name);
+        }
+        // The gzip body isn't UTF-8, so measure it from the raw bytes
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request("GET").as_bytes()).await.unwrap();
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw).await.unwrap();
+        let body_len = raw.len() - raw.windows(4).position(|window| window == b"\r\n\r\n").unwrap() - 4;
+        assert_eq!(testing::header(&head, "Content-Length").unwrap(), body_len.to_string());
+        assert!(head.ends_with("\r\n\r\n"), "{:?}", head);
+        assert_eq!(testing::body(&head), "");
+    }
+
+    // Answers with the request's raw bytes, or 404 when the server didn't keep them
+    struct Raw;
+
+    impl Handler for Raw {
+        async fn handle_request(&self, request: &Request<'_>, _client_ip: SocketAddr) -> Response {
+            match request.raw() {
+                Some(raw) => Response::with_bytes(StatusCode::Ok, raw.to_vec(), "application/octet-stream"),
+                None => Response::new(StatusCode::NotFound, None),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn raw_requests_are_kept_only_when_enabled() {
+        let request = b"GET /debug HTTP/1.1\r\nhost: localhost\r\nX-Trace:\tkept as sent\r\nConnection: close\r\n\r\n";
+
+        let addr = testing::spawn(Server::new(String::new()).with_raw_requests(true), Raw).await;
+        let response = testing::exchange(addr, request).await;
+        assert_eq!(testing::body(&response).as_bytes(), request);
+
+        let addr = testing::spawn(Server::new(String::new()), Raw).await;
+        assert_eq!(testing::status(&testing::exchange(addr, request).await), 404);
+    }
+}
@@ -0,0 +1,63 @@
+// Request bodies handed to `Handler::handle_stream` piece by piece instead of as one buffered slice.
+// Whatever arrived together with the head comes first; the rest is read from the connection on
+// demand, never past the declared Content-Length, so pipelined requests stay untouched.
+use super::REQUEST_READ_TIMEOUT;
+use std::borrow::Cow;
+use std::io;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+const STREAM_CHUNK_BYTES: usize = 16 * 1024;
+
+pub struct BodyStream<'a> {
+    buffered: Cow<'a, [u8]>,
+    buffered_taken: bool,
+    socket: Option<&'a mut TcpStream>,
+    remaining: usize,
+    chunk: Vec<u8>,
+}
+
+impl<'a> BodyStream<'a> {
+    // The whole body is already in memory (HTTP/2, chunked uploads)
+    pub(crate) fn buffered(body: Cow<'a, [u8]>) -> Self {
+        Self { buffered: body, buffered_taken: false, socket: None, remaining: 0, chunk: Vec::new() }
+    }
+
+    // `remaining` bytes of the body are still on `socket`; the size limit was checked against the
+    // declared length before any of it was read
+    pub(crate) fn from_socket(prefix: Cow<'a, [u8]>, socket: &'a mut TcpStream, remaining: usize) -> Self {
+        Self { buffered: prefix, buffered_taken: false, socket: Some(socket), remaining, chunk: Vec::new() }
+    }
+
+    // The next piece of the body, or None once all of it has been read. A client that stalls for
+    // longer than the request timeout gets a TimedOut error.
+    pub async fn next_chunk(&mut self) -> io::Result<Option<&[u8]>> {
+        if !self.buffered_taken {
+            self.buffered_taken = true;
+            if !self.buffered.is_empty() {
+                return Ok(Some(&self.buffered));
+            }
+        }
+        let socket = match self.socket.as_deref_mut() {
+            Some(socket) if self.remaining > 0 => socket,
+            _ => return Ok(None),
+        };
+
+        self.chunk.resize(self.remaining.min(STREAM_CHUNK_BYTES), 0);
+        let read = match tokio::time::timeout(REQUEST_READ_TIMEOUT, socket.read(&mut self.chunk)).await {
+            Ok(read) => read?,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "request body stalled")),
+        };
+        if read == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed mid-body"));
+        }
+        self.remaining -= read;
+        Ok(Some(&self.chunk[..read]))
+    }
+
+    // False when the handler stopped early and body bytes are still waiting on the connection,
+    // which then cannot carry another request
+    pub fn is_drained(&self) -> bool {
+        self.remaining == 0
+    }
+}
@@ -0,0 +1,101 @@
+// One place to configure a Server: the security config supplies every limit, and the setters here
+// override individual knobs regardless of the order they are called in
+use super::Server;
+use crate::security::SecurityConfig;
+use std::time::Duration;
+
+const DEFAULT_BIND_ADDR: &str = "127.0.0.1:8080";
+
+#[derive(Default)]
+pub struct ServerBuilder {
+    addr: Option<String>,
+    worker_threads: Option<usize>,
+    security_config: Option<SecurityConfig>,
+    idle_timeout: Option<Duration>,
+    drain_timeout: Option<Duration>,
+    max_connections_per_ip: Option<usize>,
+    tls_terminated: Option<bool>,
+}
+
+impl ServerBuilder {
+    // IPv6 addresses take brackets, e.g. "[::1]:8080"
+    pub fn bind(mut self, addr: impl Into<String>) -> Self {
+        self.addr = Some(addr.into());
+        self
+    }
+
+    // Sizes the runtime from Server::runtime(); unset keeps tokio's one worker per core
+    pub fn worker_threads(mut self, worker_threads: usize) -> Self {
+        self.worker_threads = Some(worker_threads.max(1));
+        self
+    }
+
+    // Body, header and connection limits, timeouts, logging, headers and route concurrency
+    pub fn security_config(mut self, security_config: &SecurityConfig) -> Self {
+        self.security_config = Some(security_config.clone());
+        self
+    }
+
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = Some(drain_timeout);
+        self
+    }
+
+    pub fn max_connections_per_ip(mut self, max_connections_per_ip: usize) -> Self {
+        self.max_connections_per_ip = Some(max_connections_per_ip);
+        self
+    }
+
+    // TLS is terminated by a proxy in front of the server; see Server::with_secure_transport
+    pub fn tls_terminated(mut self, tls_terminated: bool) -> Self {
+        self.tls_terminated = Some(tls_terminated);
+        self
+    }
+
+    pub fn build(self) -> Server {
+        let mut server = Server::new(self.addr.unwrap_or_else(|| DEFAULT_BIND_ADDR.to_string()));
+        server.worker_threads = self.worker_threads;
+
+        if let Some(config) = &self.security_config {
+            server = server
+                .with_max_connections_per_ip(config.max_connections_per_ip)
+                .with_max_accept_rate(config.max_accept_rate)
+                .with_max_body_bytes(config.max_body_bytes)
+                .with_max_head_bytes(config.max_head_bytes)
+                .with_max_headers(config.max_headers)
+                .with_max_header_bytes(config.max_header_bytes)
+                .with_min_header_rate(config.min_header_rate)
+                .with_max_requests_per_connection(config.max_requests_per_connection)
+                .with_max_response_bytes(config.max_response_bytes)
+                .with_drain_timeout(config.shutdown_drain_timeout)
+                .with_idle_timeout(config.keep_alive_timeout)
+                .with_log_format(config.log_format)
+                .with_log_sampling(config.log_sampling)
+                .with_route_concurrency(&config.route_concurrency, config.route_queue_timeout)
+                .with_compression_level(config.compression_level)
+                .with_secure_transport(config.tls_terminated)
+                .with_raw_requests(config.dev_mode)
+                .with_server_header(config.server_header.clone())
+                .with_security_headers(config.security_headers.clone());
+        }
+
+        if let Some(idle_timeout) = self.idle_timeout {
+            server = server.with_idle_timeout(idle_timeout);
+        }
+        if let Some(drain_timeout) = self.drain_timeout {
+            server = server.with_drain_timeout(drain_timeout);
+        }
+        if let Some(max_connections_per_ip) = self.max_connections_per_ip {
+            server = server.with_max_connections_per_ip(max_connections_per_ip);
+        }
+        if let Some(tls_terminated) = self.tls_terminated {
+            server = server.with_secure_transport(tls_terminated);
+        }
+        server
+    }
+}
@@ -0,0 +1,290 @@
+// HTTP/2 over cleartext TCP with prior knowledge (h2c). There is no TLS listener to negotiate ALPN
+// on, so clients opt in by opening with the connection preface; everything else is HTTP/1.
+use super::{call_limited, debug_enabled, is_client_disconnect, is_valid_request_id, next_request_id, BodyStream, ConnectionSettings, Handler, InFlightGuard};
+use super::{REQUEST_READ_TIMEOUT, STARTUP_RETRY_AFTER_SECS};
+use crate::access_log;
+use crate::error::ServerError;
+use crate::http::compression::ContentEncoding;
+use crate::http::{Method, Request, Response, SendOptions, Version};
+use bytes::Bytes;
+use h2::server::SendResponse;
+use h2::RecvStream;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const PREFACE_POLL_INTERVAL: Duration = Duration::from_millis(5);
+// Each stream runs as its own task, so this bounds the tasks one connection can start
+const MAX_CONCURRENT_STREAMS: u32 = 100;
+
+// Connection-specific fields are forbidden in HTTP/2 (RFC 9113 §8.2.2)
+const HOP_BY_HOP: [&str; 4] = ["connection", "keep-alive", "transfer-encoding", "upgrade"];
+
+// Peeks without consuming, so an HTTP/1 connection is handed on untouched. A client that stays
+// silent is given no longer than the idle timeout, as it would be between HTTP/1 requests.
+pub(super) async fn has_preface(stream: &TcpStream, idle_timeout: Duration) -> bool {
+    let deadline = Instant::now() + idle_timeout.min(REQUEST_READ_TIMEOUT);
+    let mut peeked = [0u8; PREFACE.len()];
+    loop {
+        let n = match tokio::time::timeout_at(deadline.into(), stream.peek(&mut peeked)).await {
+            Ok(Ok(n)) => n,
+            _ => return false,
+        };
+        if n == 0 || peeked[..n] != PREFACE[..n] {
+            return false;
+        }
+        if n == PREFACE.len() {
+            return true;
+        }
+        // Only part of the preface has arrived; peek returns immediately until more does
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(PREFACE_POLL_INTERVAL).await;
+    }
+}
+
+pub(super) async fn serve_connection<H: Handler>(stream: TcpStream, addr: SocketAddr, handler: Arc<H>, settings: ConnectionSettings) {
+    // Headers get the same budget as an HTTP/1 head; h2 adds 32 bytes per field to what it counts
+    let max_header_list_size = u32::try_from(settings.header_limits.max_head_bytes).unwrap_or(u32::MAX);
+    let handshake = h2::server::Builder::new()
+        .max_concurrent_streams(MAX_CONCURRENT_STREAMS)
+        .max_header_list_size(max_header_list_size)
+        .handshake(stream);
+    let mut connection = match handshake.await {
+        Ok(connection) => connection,
+        Err(e) => {
+            debug_log!("HTTP/2 handshake with {} failed: {}", addr, e);
+            return;
+        },
+    };
+    let mut shutdown = settings.shutdown.clone();
+    let mut closing = false;
+
+    // Streams run concurrently; the accept loop also drives the connection's I/O
+    loop {
+        let accepted = tokio::select! {
+            accepted = connection.accept() => accepted,
+            _ = shutdown.wait_for(|&stopping| stopping), if !closing => {
+                // GOAWAY: open streams finish, new ones are refused
+                connection.graceful_shutdown();
+                closing = true;
+                continue;
+            },
+        };
+        match accepted {
+            Some(Ok((request, respond))) => {
+                tokio::spawn(handle_stream(request, respond, addr, Arc::clone(&handler), settings.clone()));
+            },
+            Some(Err(e)) => {
+                if e.get_io().is_some_and(is_client_disconnect) {
+                    debug_log!("HTTP/2 connection from {} reset: {}", addr, e);
+                } else {
+                    eprintln!("HTTP/2 error from {}: {}", addr, e);
+                }
+                return;
+            },
+            None => return,
+        }
+    }
+}
+
+async fn handle_stream<H: Handler>(
+    request: http::Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    addr: SocketAddr,
+    handler: Arc<H>,
+    settings: ConnectionSettings,
+) {
+    let _in_flight = InFlightGuard::enter(&settings.in_flight);
+    let mut request_id = next_request_id();
+    let (parts, body) = request.into_parts();
+
+    let body = match tokio::time::timeout(REQUEST_READ_TIMEOUT, read_body(body, settings.max_body_bytes)).await {
+        Ok(Ok(body)) => body,
+        Ok(Err(ServerError::Io(e))) => {
+            debug_log!("[{}] HTTP/2 stream from {} failed: {}", request_id, addr, e);
+            return;
+        },
+        Ok(Err(error)) => {
+            eprintln!("[{}] Request body too large from {}", request_id, addr);
+            let response = handler.handle_error(&error, Some(parts.uri.path()));
+            send_response(&mut respond, &response, &settings, ContentEncoding::Identity, false);
+            return;
+        },
+        Err(_) => {
+            eprintln!("[{}] Request timeout from {}", request_id, addr);
+            let response = handler.handle_error(&ServerError::Timeout, Some(parts.uri.path()));
+            send_response(&mut respond, &response, &settings, ContentEncoding::Identity, false);
+            return;
+        },
+    };
+
+    let method = match parts.method.as_str().parse::<Method>() {
+        Ok(method) => method,
+        Err(e) => {
+            let error = ServerError::Parse(e.into());
+            send_response(&mut respond, &handler.handle_error(&error, None), &settings, ContentEncoding::Identity, false);
+            return;
+        },
+    };
+    let target = parts.uri.path_and_query().map_or("/", |target| target.as_str());
+    let headers: Vec<(&str, &str)> = parts.headers.iter()
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+        .collect();
+
+    // :authority stands in for Host, as absolute-form targets do in HTTP/1
+    let mut request = Request::from_parts(method, Version::Http2, target, parts.uri.authority().map(|a| a.as_str()), &headers, &body);
+    if let Some(incoming) = request.header("X-Request-Id").filter(|id| is_valid_request_id(id)) {
+        request_id = incoming.to_string();
+    }
+    request.set_request_id(request_id.clone());
+    request.set_remote_addr(addr);
+    request.set_secure(settings.secure);
+
+    let started = Instant::now();
+    // h2 bodies were read in full above, so a streaming handler reads them from memory
+    let response = if !handler.is_ready() && !handler.is_health_check(&request) {
+        Response::service_unavailable(STARTUP_RETRY_AFTER_SECS)
+    } else if handler.streams_body(&request) {
+        let mut body = BodyStream::buffered(request.take_body());
+        call_limited(handler.as_ref(), &request, Some(&mut body), addr, &settings.route_limiter).await
+    } else {
+        call_limited(handler.as_ref(), &request, None, addr, &settings.route_limiter).await
+    };
+    let latency = started.elapsed();
+    handler.on_response(&request, &response, latency, addr);
+    if let Some(line) = access_log::access_line(settings.log_format, &settings.log_sampler, &request, response.status_code(), latency, addr, "HTTP/2") {
+        println!("{}", line);
+    }
+
+    let encoding = ContentEncoding::negotiate(request.header("Accept-Encoding"));
+    let head_only = *request.method() == Method::HEAD;
+    send_response(&mut respond, &response.with_request_id(&request_id), &settings, encoding, head_only);
+}
+
+async fn read_body(mut body: RecvStream, max_body_bytes: usize) -> Result<Vec<u8>, ServerError> {
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| ServerError::Io(io::Error::other(e)))?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        data.extend_from_slice(&chunk);
+        if data.len() > max_body_bytes {
+            return Err(ServerError::TooLarge);
+        }
+    }
+    Ok(data)
+}
+
+// Reuses the HTTP/1 encoding so both protocols send the same header set, minus hop-by-hop fields
+fn send_response(respond: &mut SendResponse<Bytes>, response: &Response, settings: &ConnectionSettings, encoding: ContentEncoding, head_only: bool) {
+    let options = SendOptions::new(Version::Http2, true, settings.secure)
+        .with_encoding(encoding)
+        .with_compression_level(settings.compression_level)
+        .with_head_only(head_only)
+        .with_max_body_bytes(settings.max_response_bytes);
+    let (encoded, head_len) = match response.encode(&options) {
+        Ok(encoded) => encoded,
+        Err(e) => {
+            eprintln!("Failed to encode HTTP/2 response: {}", e);
+            respond.send_reset(h2::Reason::INTERNAL_ERROR);
+            return;
+        },
+    };
+
+    let mut builder = http::Response::builder().status(response.status_code() as u16);
+    // Skip the status line; the head ends with a blank line
+    let head = String::from_utf8_lossy(&encoded[..head_len]);
+    for line in head.split("\r\n").skip(1).filter(|line| !line.is_empty()) {
+        if let Some((name, value)) = line.split_once(": ") {
+            if !HOP_BY_HOP.iter().any(|hop| name.eq_ignore_ascii_case(hop)) {
+                builder = builder.header(name, value);
+            }
+        }
+    }
+    let head = match builder.body(()) {
+        Ok(head) => head,
+        Err(e) => {
+            eprintln!("Invalid HTTP/2 response head: {}", e);
+            respond.send_reset(h2::Reason::INTERNAL_ERROR);
+            return;
+        },
+    };
+
+    let body = Bytes::copy_from_slice(&encoded[head_len..]);
+    // h2 buffers the data and sends it as the peer's flow-control window allows
+    let sent = respond.send_response(head, body.is_empty())
+        .and_then(|mut stream| if body.is_empty() { Ok(()) } else { stream.send_data(body, true) });
+    if let Err(e) = sent {
+        debug_log!("Failed to send HTTP/2 response: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{testing, Server};
+    use super::*;
+    use crate::security::SecurityConfig;
+    use crate::website_handler::WebsiteHandler;
+
+    #[tokio::test]
+    async fn prior_knowledge_client_gets_api_ping() {
+        let handler = WebsiteHandler::new(std::env::temp_dir(), SecurityConfig::default());
+        let addr = testing::spawn(Server::new(String::new()), handler).await;
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (client, connection) = h2::client::handshake(stream).await.unwrap();
+        tokio::spawn(connection);
+        let mut client = client.ready().await.unwrap();
+        // The authority stands in for Host, which the handler checks against its allowlist
+        let request = http::Request::get("http://localhost:8080/api/ping").body(()).unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+        let response = response.await.unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "application/json; charset=utf-8");
+        let mut body = response.into_body();
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.unwrap();
+            let _ = body.flow_control().release_capacity(chunk.len());
+            data.extend_from_slice(&chunk);
+        }
+        assert_eq!(data, br#"{"status": "ok", "message": "pong"}"#);
+    }
+
+    #[tokio::test]
+    async fn streams_and_header_lists_are_capped() {
+        let addr = testing::spawn(Server::new(String::new()).with_max_head_bytes(1024), testing::Echo).await;
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (client, connection) = h2::client::handshake(stream).await.unwrap();
+        tokio::spawn(connection);
+        let mut client = client.ready().await.unwrap();
+
+        let request = http::Request::get("http://localhost/").header("x-padding", "a".repeat(2048)).body(()).unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+        // h2 answers an oversized header list itself, before the handler sees it
+        assert!(response.await.map_or(true, |response| response.status() == 431));
+        // By now the server's SETTINGS have arrived
+        assert_eq!(client.current_max_send_streams(), MAX_CONCURRENT_STREAMS as usize);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_stalled_body_gets_a_408_like_http1() {
+        let addr = testing::spawn(Server::new(String::new()), testing::Echo).await;
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (client, connection) = h2::client::handshake(stream).await.unwrap();
+        tokio::spawn(connection);
+        let mut client = client.ready().await.unwrap();
+        let request = http::Request::post("http://localhost/upload").body(()).unwrap();
+        // The body is announced but never sent
+        let (response, _body) = client.send_request(request, false).unwrap();
+
+        assert_eq!(response.await.unwrap().status(), 408);
+    }
+}
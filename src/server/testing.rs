@@ -0,0 +1,93 @@
+// Helpers for tests that drive a real Server over loopback with raw HTTP bytes
+use super::{Handler, Server};
+use crate::http::{Request, Response, StatusCode};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+const EXCHANGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A handler answering every request with its method and path, for tests that only exercise the server
+pub(crate) struct Echo;
+
+impl Handler for Echo {
+    async fn handle_request(&self, request: &Request<'_>, _client_ip: SocketAddr) -> Response {
+        Response::new(StatusCode::Ok, Some(format!("{} {}", request.method_str(), request.path())))
+    }
+}
+
+// Serves on an ephemeral loopback port until the test's runtime goes away
+pub(crate) async fn spawn<H: Handler>(server: Server, handler: H) -> SocketAddr {
+    spawn_until(server, handler, std::future::pending()).await.0
+}
+
+pub(crate) async fn spawn_until<H: Handler>(
+    server: Server,
+    handler: H,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> (SocketAddr, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let task = tokio::spawn(async move {
+        server.serve(listener, handler, shutdown).await.unwrap();
+    });
+    (addr, task)
+}
+
+// Writes `request` and reads until the server closes the connection
+pub(crate) async fn exchange(addr: SocketAddr, request: &[u8]) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    read_to_close(&mut stream).await
+}
+
+pub(crate) async fn read_to_close(stream: &mut TcpStream) -> String {
+    let mut response = Vec::new();
+    tokio::time::timeout(EXCHANGE_TIMEOUT, stream.read_to_end(&mut response))
+        .await
+        .expect("server did not close the connection")
+        .unwrap();
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+// Reads exactly one response with a Content-Length body, leaving the connection open
+pub(crate) async fn read_response(stream: &mut TcpStream) -> String {
+    let mut response = Vec::new();
+    let read = async {
+        loop {
+            if let Some(head_end) = response.windows(4).position(|window| window == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&response[..head_end]).into_owned();
+                let length = header(&head, "Content-Length").map_or(0, |value| value.parse().unwrap());
+                if response.len() >= head_end + 4 + length {
+                    return;
+                }
+            }
+            let mut chunk = [0; 4096];
+            let read = stream.read(&mut chunk).await.unwrap();
+            assert!(read > 0, "connection closed mid-response");
+            response.extend_from_slice(&chunk[..read]);
+        }
+    };
+    tokio::time::timeout(EXCHANGE_TIMEOUT, read).await.expect("no complete response");
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+pub(crate) fn status(response: &str) -> u16 {
+    response.split(' ').nth(1).and_then(|code| code.parse().ok()).expect("no status line")
+}
+
+// First value of a header in a raw response, matched case-insensitively
+pub(crate) fn header<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+    let head = response.split("\r\n\r\n").next()?;
+    head.lines().skip(1).find_map(|line| {
+        let (field, value) = line.split_once(':')?;
+        field.eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+pub(crate) fn body(response: &str) -> &str {
+    response.split_once("\r\n\r\n").map_or("", |(_, body)| body)
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+// Replaces `{{name}}` placeholders with HTML-escaped values; unknown placeholders are left intact
+pub fn render(template: &str, context: &HashMap<&str, String>) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after_open = &rest[start + 2..];
+
+        match after_open.find("}}") {
+            Some(end) => {
+                let name = after_open[..end].trim();
+                match context.get(name) {
+                    Some(value) => output.push_str(&escape_html(value)),
+                    None => output.push_str(&rest[start..start + 2 + end + 2]),
+                }
+                rest = &after_open[end + 2..];
+            }
+            None => {
+                output.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_placeholders_are_escaped_and_unknown_ones_kept() {
+        let context = HashMap::from([("version", "1.2.3".to_string()), ("name", "<b>&\"'".to_string())]);
+        assert_eq!(render("v{{version}} {{ name }}", &context), "v1.2.3 &lt;b&gt;&amp;&quot;&#x27;");
+        assert_eq!(render("{{missing}} and {{unclosed", &context), "{{missing}} and {{unclosed");
+    }
+}
@@ -0,0 +1,109 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::ops::RangeInclusive;
+
+// One problem with one input field, reported to API clients alongside a 422
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: &'static str,
+}
+
+// Collects every field problem in a JSON object instead of stopping at the first, so a client can
+// fix its input in one round trip
+pub struct Validator<'a> {
+    input: &'a Value,
+    errors: Vec<FieldError>,
+}
+
+impl<'a> Validator<'a> {
+    pub fn new(input: &'a Value) -> Self {
+        Self { input, errors: Vec::new() }
+    }
+
+    // A string that is present and not just whitespace, at most `max_len` characters
+    pub fn required_string(&mut self, field: &'static str, max_len: usize) -> Option<&'a str> {
+        match self.input.get(field) {
+            None | Some(Value::Null) => self.fail(field, "is required"),
+            Some(Value::String(value)) if value.trim().is_empty() => self.fail(field, "must not be empty"),
+            Some(Value::String(value)) if value.chars().count() > max_len => self.fail(field, "is too long"),
+            Some(Value::String(value)) => return Some(value),
+            Some(_) => self.fail(field, "must be a string"),
+        }
+        None
+    }
+
+    // Deliberately loose: one `@` with something on each side and a dot in the domain
+    pub fn required_email(&mut self, field: &'static str) -> Option<&'a str> {
+        let value = self.required_string(field, 254)?;
+        let valid = match value.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty() && !domain.contains('@') && !value.contains(char::is_whitespace)
+                    && domain.split('.').count() > 1 && domain.split('.').all(|label| !label.is_empty())
+            },
+            None => false,
+        };
+        if valid {
+            Some(value)
+        } else {
+            self.fail(field, "must be a valid email address");
+            None
+        }
+    }
+
+    // A whole number within `range`; floats and numeric strings are rejected rather than coerced
+    pub fn required_integer(&mut self, field: &'static str, range: RangeInclusive<u64>) -> Option<u64> {
+        match self.input.get(field) {
+            None | Some(Value::Null) => self.fail(field, "is required"),
+            Some(Value::Number(value)) => match value.as_u64() {
+                Some(value) if range.contains(&value) => return Some(value),
+                Some(_) => self.fail(field, "is out of range"),
+                None => self.fail(field, "must be a non-negative integer"),
+            },
+            Some(_) => self.fail(field, "must be an integer"),
+        }
+        None
+    }
+
+    pub fn finish(self) -> Result<(), Vec<FieldError>> {
+        if self.errors.is_empty() { Ok(()) } else { Err(self.errors) }
+    }
+
+    fn fail(&mut self, field: &'static str, message: &'static str) {
+        self.errors.push(FieldError { field, message });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn errors(input: Value) -> Vec<(&'static str, &'static str)> {
+        let mut validator = Validator::new(&input);
+        validator.required_string("name", 5);
+        validator.required_email("email");
+        validator.required_integer("age", 0..=150);
+        validator.finish().err().unwrap_or_default().into_iter().map(|error| (error.field, error.message)).collect()
+    }
+
+    #[test]
+    fn every_bad_field_is_reported() {
+        assert!(errors(json!({ "name": "Ann", "email": "ann@example.com", "age": 30 })).is_empty());
+        assert_eq!(errors(json!({ "email": "ann@", "age": 30.5 })), [
+            ("name", "is required"),
+            ("email", "must be a valid email address"),
+            ("age", "must be a non-negative integer"),
+        ]);
+        assert_eq!(errors(json!({ "name": "Annabel", "email": "a b@x.io", "age": "30" })), [
+            ("name", "is too long"),
+            ("email", "must be a valid email address"),
+            ("age", "must be an integer"),
+        ]);
+        assert_eq!(errors(json!({ "name": " ", "email": "ann@example..com", "age": 200 })), [
+            ("name", "must not be empty"),
+            ("email", "must be a valid email address"),
+            ("age", "is out of range"),
+        ]);
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileChange {
+    Added(PathBuf),
+    Modified(PathBuf),
+    Removed(PathBuf),
+}
+
+impl FileChange {
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Added(path) | Self::Modified(path) | Self::Removed(path) => path,
+        }
+    }
+}
+
+// Polls modification times rather than using OS notifications, which keeps it dependency-free
+// and portable; a dev server doesn't need sub-second precision.
+pub struct DirectoryWatcher {
+    root: PathBuf,
+    interval: Duration,
+}
+
+impl DirectoryWatcher {
+    pub fn new(root: PathBuf, interval: Duration) -> Self {
+        Self { root, interval }
+    }
+
+    // Runs until the runtime shuts down, calling `on_change` for every detected change
+    pub fn spawn<F>(self, on_change: F) -> tokio::task::JoinHandle<()>
+    where
+        F: Fn(&FileChange) + Send + 'static,
+    {
+        tokio::spawn(async move {
+            let root = self.root.clone();
+            let mut previous = snapshot_blocking(root.clone()).await;
+
+            loop {
+                tokio::time::sleep(self.interval).await;
+                let current = snapshot_blocking(root.clone()).await;
+                for change in diff(&previous, &current) {
+                    on_change(&change);
+                }
+                previous = current;
+            }
+        })
+    }
+}
+
+async fn snapshot_blocking(root: PathBuf) -> HashMap<PathBuf, SystemTime> {
+    tokio::task::spawn_blocking(move || snapshot(&root))
+        .await
+        .unwrap_or_default()
+}
+
+pub fn snapshot(root: &Path) -> HashMap<PathBuf, SystemTime> {
+    let mut files = HashMap::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else if let Ok(modified) = metadata.modified() {
+                files.insert(entry.path(), modified);
+            }
+        }
+    }
+
+    files
+}
+
+pub fn diff(previous: &HashMap<PathBuf, SystemTime>, current: &HashMap<PathBuf, SystemTime>) -> Vec<FileChange> {
+    let mut changes = Vec::new();
+
+    for (path, modified) in current {
+        match previous.get(path) {
+            None => changes.push(FileChange::Added(path.clone())),
+            Some(before) if before != modified => changes.push(FileChange::Modified(path.clone())),
+            Some(_) => {},
+        }
+    }
+    for path in previous.keys() {
+        if !current.contains_key(path) {
+            changes.push(FileChange::Removed(path.clone()));
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_reports_added_modified_and_removed_files() {
+        let earlier = SystemTime::UNIX_EPOCH;
+        let later = earlier + Duration::from_secs(1);
+        let previous = HashMap::from([(PathBuf::from("kept"), earlier), (PathBuf::from("edited"), earlier), (PathBuf::from("gone"), earlier)]);
+        let current = HashMap::from([(PathBuf::from("kept"), earlier), (PathBuf::from("edited"), later), (PathBuf::from("new"), later)]);
+
+        let mut changes = diff(&previous, &current);
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        assert_eq!(changes, [
+            FileChange::Modified(PathBuf::from("edited")),
+            FileChange::Removed(PathBuf::from("gone")),
+            FileChange::Added(PathBuf::from("new")),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn modifying_a_watched_file_calls_back() {
+        let root = std::env::temp_dir().join(format!("rust-server-watch-{}", std::process::id()));
+        let file = root.join("nested").join("page.html");
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, "before").unwrap();
+
+        let (changes, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let watcher = DirectoryWatcher::new(root.clone(), Duration::from_millis(10)).spawn(move |change| {
+            let _ = changes.send(change.clone());
+        });
+        // Let the first snapshot happen, then move the mtime so coarse filesystem clocks can't hide the edit
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        fs::File::options().write(true).open(&file).unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+
+        let change = tokio::time::timeout(Duration::from_secs(5), received.recv()).await;
+        watcher.abort();
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(change.unwrap(), Some(FileChange::Modified(file)));
+    }
+}
@@ -1,39 +1,187 @@
-use super::http::{Method, Request, Response, StatusCode};
-use super::server::Handler;
-use super::security::{RateLimiter, SecurityConfig, SecurityValidator};
+use super::error::ServerError;
+use super::http::multipart;
+use super::http::percent::{form_decode, percent_decode};
+use super::http::request::find_head_end;
+use super::http::{negotiate, CachePolicy, Method, Request, Response, StatusCode};
+use super::server::{BodyStream, Handler};
+use super::middleware::{Cors, Middleware, MiddlewareStack, RateLimit, Rejection, RequestValidation};
+use super::security::{AccessDenied, RateLimitSettings, RateLimiter, Rewrite, SecurityConfig, SecurityValidator, TrailingSlash};
+#[cfg(feature = "embedded")]
+use super::embedded;
+#[cfg(feature = "embedded")]
+use super::http::mime;
+use super::template;
+use super::validation::{FieldError, Validator};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fs;
+use std::io;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+
+// Keep the echo response bounded no matter how large the request body is
+const ECHO_BODY_LIMIT: usize = 4096;
+const REDACTED_ECHO_HEADERS: [&str; 4] = ["authorization", "proxy-authorization", "cookie", "x-api-key"];
+// Streamed straight from the connection rather than buffered; see `handle_stream`
+const UPLOAD_PATH: &str = "/api/upload";
+
+// Dev mode only: each SSE reply carries the current reload generation and the browser's
+// EventSource reconnects after `retry`, so no long-lived streaming response is needed
+const LIVE_RELOAD_PATH: &str = "/__livereload";
+const LIVE_RELOAD_SCRIPT: &str = r#"<script>(function(){var seen=null;var source=new EventSource("/__livereload");source.onmessage=function(e){if(seen!==null&&seen!==e.data){location.reload();}seen=e.data;};})();</script>"#;
+
+#[derive(Default)]
+struct RequestStats {
+    total: AtomicU64,
+    success: AtomicU64,
+    client_errors: AtomicU64,
+    server_errors: AtomicU64,
+}
+
+impl RequestStats {
+    fn record_status(&self, status: StatusCode) {
+        let counter = match status as u16 {
+            200..=299 => &self.success,
+            400..=499 => &self.client_errors,
+            500..=599 => &self.server_errors,
+            _ => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
 
 pub struct WebsiteHandler {
     public_path: PathBuf,
     rate_limiter: Arc<RateLimiter>,
+    // Kept to build the validation middleware
+    security_config: SecurityConfig,
     security_validator: SecurityValidator,
+    enable_templates: bool,
+    enable_api_routes: bool,
+    spa_fallback: bool,
+    clean_urls: bool,
+    content_sniffing: bool,
+    cache_policy: CachePolicy,
+    mime_overrides: HashMap<String, String>,
+    rewrites: Vec<Rewrite>,
+    dev_mode: bool,
+    follow_symlinks: bool,
+    upload_dir: Option<PathBuf>,
+    default_documents: Vec<String>,
+    trailing_slash: TrailingSlash,
+    directory_slash_redirect: bool,
+    reload_generation: Arc<AtomicU64>,
     // Simple in-memory storage for demo
-    request_count: Arc<std::sync::atomic::AtomicU64>,
+    stats: Arc<RequestStats>,
+    next_user_id: AtomicU64,
+    ready: Arc<AtomicBool>,
 }
 
 impl WebsiteHandler {
     pub fn new(public_path: PathBuf, security_config: SecurityConfig) -> Self {
         let rate_limiter = Arc::new(RateLimiter::new(security_config.clone()));
+        let config = security_config.clone();
+        let enable_templates = security_config.enable_templates;
+        let enable_api_routes = security_config.enable_api_routes;
+        let spa_fallback = security_config.spa_fallback;
+        let clean_urls = security_config.clean_urls;
+        let content_sniffing = security_config.content_sniffing;
+        let dev_mode = security_config.dev_mode;
+        // Edits should show up immediately while developing, so dev mode keeps the no-store default
+        let cache_policy = CachePolicy {
+            fixed: security_config.static_cache_control.clone(),
+            max_age: if dev_mode { Vec::new() } else { security_config.static_max_age.clone() },
+        };
+        let follow_symlinks = security_config.follow_symlinks;
+        let upload_dir = security_config.upload_dir.clone();
+        let trailing_slash = security_config.trailing_slash;
+        let directory_slash_redirect = security_config.directory_slash_redirect;
+        let default_documents = security_config.default_documents.clone();
+        let rewrites = security_config.rewrites.clone();
+        let mime_overrides = security_config.mime_overrides.iter()
+            .map(|(extension, mime)| (extension.trim_start_matches('.').to_ascii_lowercase(), mime.clone()))
+            .collect();
         let security_validator = SecurityValidator::new(security_config);
         
         Self { 
             public_path,
             rate_limiter,
+            security_config: config,
             security_validator,
-            request_count: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            enable_templates,
+            enable_api_routes,
+            spa_fallback,
+            clean_urls,
+            content_sniffing,
+            cache_policy,
+            mime_overrides,
+            rewrites,
+            dev_mode,
+            follow_symlinks,
+            upload_dir,
+            default_documents,
+            trailing_slash,
+            directory_slash_redirect,
+            reload_generation: Arc::new(AtomicU64::new(0)),
+            stats: Arc::new(RequestStats::default()),
+            // Ids 1-3 belong to the built-in demo users
+            next_user_id: AtomicU64::new(4),
+            ready: Arc::new(AtomicBool::new(true)),
         }
     }
 
+    // Start in the not-ready state; the returned flag is flipped once initialization completes
+    pub fn with_startup_gate(mut self) -> (Self, Arc<AtomicBool>) {
+        self.ready = Arc::new(AtomicBool::new(false));
+        let ready = Arc::clone(&self.ready);
+        (self, ready)
+    }
+
+    // Bumping the returned counter makes connected dev-mode pages reload
+    pub fn reload_trigger(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.reload_generation)
+    }
+
+    // Wraps the handler in its standard pipeline: request counting outermost so /api/info sees
+    // rejections too, then the dev live-reload endpoint ahead of rate limiting (every open dev page
+    // polls it once a second), then rate limiting and path/Host validation
+    pub fn with_default_middleware(self) -> MiddlewareStack<Self> {
+        let stats = StatsRecorder(Arc::clone(&self.stats));
+        let cors = self.security_config.cors.clone().map(Cors::new);
+        let live_reload = self.dev_mode.then(|| LiveReload(Arc::clone(&self.reload_generation)));
+        let rate_limit = RateLimit::new(Arc::clone(&self.rate_limiter));
+        let validation = RequestValidation::new(self.security_config.clone());
+
+        // CORS sits outside the limits so even a 429 or 400 carries headers the browser can read
+        let mut stack = MiddlewareStack::new(self).with(stats);
+        if let Some(cors) = cors {
+            stack = stack.with(cors);
+        }
+        if let Some(live_reload) = live_reload {
+            stack = stack.with(live_reload);
+        }
+        stack.with(rate_limit).with(validation)
+    }
+
+    fn inject_live_reload(&self, content: Vec<u8>) -> Vec<u8> {
+        let closing_body = content.windows(7).rposition(|window| window.eq_ignore_ascii_case(b"</body>"));
+        let insert_at = closing_body.unwrap_or(content.len());
+
+        let mut injected = Vec::with_capacity(content.len() + LIVE_RELOAD_SCRIPT.len());
+        injected.extend_from_slice(&content[..insert_at]);
+        injected.extend_from_slice(LIVE_RELOAD_SCRIPT.as_bytes());
+        injected.extend_from_slice(&content[insert_at..]);
+        injected
+    }
+
     // Handle API routes with simple string formatting
-    fn handle_api_route(&self, request: &Request, client_ip: SocketAddr) -> Option<Response> {
+    async fn handle_api_route(&self, request: &Request<'_>, client_ip: SocketAddr) -> Option<Response> {
         let path = request.path();
         
-        // Increment request counter
-        self.request_count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-
         match (request.method(), path) {
             // Simple ping endpoint
             (Method::GET, "/api/ping") => {
@@ -41,30 +189,76 @@ impl WebsiteHandler {
                 Some(Response::with_content_type(
                     StatusCode::Ok,
                     Some(response.to_string()),
-                    "application/json; charset=utf-8".to_string(),
+                    "application/json; charset=utf-8",
+                ))
+            },
+
+            // Readiness endpoint for health checks
+            (Method::GET, "/api/health") => {
+                let ready = self.is_ready();
+                let response = format!(r#"{{"status": "{}", "ready": {}}}"#, if ready { "ok" } else { "starting" }, ready);
+                Some(Response::with_content_type(
+                    if ready { StatusCode::Ok } else { StatusCode::ServiceUnavailable },
+                    Some(response),
+                    "application/json; charset=utf-8",
                 ))
             },
 
             // Server info endpoint
             (Method::GET, "/api/info") => {
-                let count = self.request_count.load(std::sync::atomic::Ordering::Relaxed);
+                let count = self.stats.total.load(Ordering::Relaxed);
+                let success = self.stats.success.load(Ordering::Relaxed);
+                let client_errors = self.stats.client_errors.load(Ordering::Relaxed);
+                let server_errors = self.stats.server_errors.load(Ordering::Relaxed);
                 let timestamp = std::time::SystemTime::now()
                     .duration_since(std::time::UNIX_EPOCH)
                     .unwrap()
                     .as_secs();
                 
-                let response = format!(
-                    r#"{{"server": "Rust HTTP Server", "version": "{}", "requests_served": {}, "timestamp": {}}}"#,
-                    env!("CARGO_PKG_VERSION"),
-                    count,
-                    timestamp
-                );
-                
-                Some(Response::with_content_type(
-                    StatusCode::Ok,
-                    Some(response),
-                    "application/json; charset=utf-8".to_string(),
-                ))
+                let version = env!("CARGO_PKG_VERSION");
+
+                let (body, content_type) = match negotiate(request.header("Accept"), &["application/json", "text/plain", "application/xml"]) {
+                    Some("text/plain") => (
+                        format!(
+                            "server: Rust HTTP Server\nversion: {}\nrequests_served: {}\nresponses_2xx: {}\nresponses_4xx: {}\nresponses_5xx: {}\ntimestamp: {}\n",
+                            version, count, success, client_errors, server_errors, timestamp
+                        ),
+                        "text/plain; charset=utf-8",
+                    ),
+                    Some("application/xml") => (
+                        format!(
+                            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><info><server>Rust HTTP Server</server><version>{}</version><requests_served>{}</requests_served><responses_2xx>{}</responses_2xx><responses_4xx>{}</responses_4xx><responses_5xx>{}</responses_5xx><timestamp>{}</timestamp></info>",
+                            version, count, success, client_errors, server_errors, timestamp
+                        ),
+                        "application/xml; charset=utf-8",
+                    ),
+                    Some(_) => (
+                        format!(
+                            r#"{{"server": "Rust HTTP Server", "version": "{}", "requests_served": {}, "responses_2xx": {}, "responses_4xx": {}, "responses_5xx": {}, "timestamp": {}}}"#,
+                            version, count, success, client_errors, server_errors, timestamp
+                        ),
+                        "application/json; charset=utf-8",
+                    ),
+                    None => return Some(Response::new(
+                        StatusCode::NotAcceptable,
+                        Some("Supported representations: application/json, text/plain, application/xml".to_string()),
+                    ).with_vary("Accept")),
+                };
+
+                Some(Response::with_content_type(StatusCode::Ok, Some(body), content_type).with_vary("Accept"))
+            },
+
+            // Build metadata captured by build.rs, for matching a running server to a deployment
+            (Method::GET, "/api/version") => {
+                let built_at = env!("BUILD_TIMESTAMP").parse::<i64>().ok()
+                    .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+                    .map(|built_at| built_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+                Some(Response::json(StatusCode::Ok, &serde_json::json!({
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "git_hash": env!("GIT_HASH"),
+                    "build_timestamp": built_at,
+                    "rustc_version": env!("RUSTC_VERSION"),
+                })))
             },
 
             // Simple user endpoint with hardcoded data
@@ -83,16 +277,53 @@ impl WebsiteHandler {
                 Some(Response::with_content_type(
                     StatusCode::Ok,
                     Some(response),
-                    "application/json; charset=utf-8".to_string(),
+                    "application/json; charset=utf-8",
                 ))
             },
 
+            // Create a user; the demo keeps no store, so the new record is only echoed back
+            (Method::POST, "/api/users") => {
+                let input: serde_json::Value = match serde_json::from_slice(request.body()) {
+                    Ok(input @ serde_json::Value::Object(_)) => input,
+                    _ => return Some(Response::json(StatusCode::BadRequest, &serde_json::json!({
+                        "success": false,
+                        "data": null,
+                        "message": "Request body must be a JSON object",
+                    }))),
+                };
+
+                let mut validator = Validator::new(&input);
+                let name = validator.required_string("name", 100);
+                let email = validator.required_email("email");
+                if let Err(errors) = validator.finish() {
+                    return Some(Response::json(StatusCode::UnprocessableEntity, &serde_json::json!({
+                        "success": false,
+                        "data": null,
+                        "message": "Validation failed",
+                        "errors": errors,
+                    })));
+                }
+
+                Some(Response::json(StatusCode::Created, &serde_json::json!({
+                    "success": true,
+                    "data": {
+                        "id": self.next_user_id.fetch_add(1, Ordering::Relaxed),
+                        "name": name,
+                        "email": email,
+                    },
+                    "message": "User created",
+                })))
+            },
+
             // Get user by ID
             (Method::GET, path) if path.starts_with("/api/users/") => {
-                let user_id_str = path.trim_start_matches("/api/users/");
+                let user_id_str = match request.path_segments()[..] {
+                    ["api", "users", id] => id,
+                    _ => "",
+                };
                 
                 match user_id_str.parse::<u32>() {
-                    Ok(user_id) if user_id >= 1 && user_id <= 3 => {
+                    Ok(user_id) if (1..=3).contains(&user_id) => {
                         let (name, email) = match user_id {
                             1 => ("Alice", "alice@example.com"),
                             2 => ("Bob", "bob@example.com"),
@@ -108,7 +339,7 @@ impl WebsiteHandler {
                         Some(Response::with_content_type(
                             StatusCode::Ok,
                             Some(response),
-                            "application/json; charset=utf-8".to_string(),
+                            "application/json; charset=utf-8",
                         ))
                     },
                     Ok(_) => {
@@ -116,7 +347,7 @@ impl WebsiteHandler {
                         Some(Response::with_content_type(
                             StatusCode::NotFound,
                             Some(error_response.to_string()),
-                            "application/json; charset=utf-8".to_string(),
+                            "application/json; charset=utf-8",
                         ))
                     },
                     Err(_) => {
@@ -124,12 +355,26 @@ impl WebsiteHandler {
                         Some(Response::with_content_type(
                             StatusCode::BadRequest,
                             Some(error_response.to_string()),
-                            "application/json; charset=utf-8".to_string(),
+                            "application/json; charset=utf-8",
                         ))
                     },
                 }
             },
 
+            // Decoded fields of an HTML form post, for checking what a form actually submits
+            (Method::POST, "/api/form") => match request.form() {
+                Some(form) => Some(Response::json(StatusCode::Ok, &serde_json::json!({
+                    "success": true,
+                    "data": form,
+                    "message": "Form received",
+                }))),
+                None => Some(Response::json(StatusCode::BadRequest, &serde_json::json!({
+                    "success": false,
+                    "data": null,
+                    "message": "Request body must be a valid application/x-www-form-urlencoded form",
+                }))),
+            },
+
             // Echo endpoint for testing
             (Method::POST, "/api/echo") => {
                 let timestamp = std::time::SystemTime::now()
@@ -137,44 +382,117 @@ impl WebsiteHandler {
                     .unwrap()
                     .as_secs();
 
-                let response = format!(
-                    r#"{{"success": true, "data": {{"method": "{}", "path": "{}", "client_ip": "{}", "timestamp": {}}}, "message": "Echo successful"}}"#,
-                    request.method_str(),
-                    request.path(),
-                    client_ip.ip(),
-                    timestamp
-                );
-                
+                let mut headers = serde_json::Map::new();
+                for (name, value) in request.headers().iter() {
+                    let name = name.to_ascii_lowercase();
+                    let value = if REDACTED_ECHO_HEADERS.contains(&name.as_str()) { "[REDACTED]" } else { value };
+                    match headers.get_mut(&name) {
+                        Some(serde_json::Value::String(existing)) => {
+                            existing.push_str(", ");
+                            existing.push_str(value);
+                        },
+                        _ => {
+                            headers.insert(name, serde_json::Value::String(value.to_string()));
+                        },
+                    }
+                }
+
+                let body = request.body();
+                let echoed_body = &body[..body.len().min(ECHO_BODY_LIMIT)];
+
+                // Path, headers and body are all client-controlled; serde escapes every one of them
+                Some(Response::json(StatusCode::Ok, &serde_json::json!({
+                    "success": true,
+                    "data": {
+                        "method": request.method_str(),
+                        "path": request.path(),
+                        "client_ip": client_ip.ip().to_canonical().to_string(),
+                        "timestamp": timestamp,
+                        "headers": headers,
+                        "body": String::from_utf8_lossy(echoed_body),
+                        "body_length": body.len(),
+                        "body_truncated": body.len() > ECHO_BODY_LIMIT,
+                        // Dev mode only: the head exactly as it arrived, for debugging parser behavior
+                        "raw_head": request.raw().filter(|_| self.dev_mode).map(redacted_raw_head),
+                    },
+                    "message": "Echo successful",
+                })))
+            },
+
+            // Rate limiter state for operators; admin-only via the default access rules
+            (Method::GET, "/api/stats") => {
+                let response = serde_json::json!({
+                    "success": true,
+                    "data": { "rate_limiter": self.rate_limiter.stats() },
+                    "message": "Rate limiter stats",
+                }).to_string();
+
                 Some(Response::with_content_type(
                     StatusCode::Ok,
                     Some(response),
-                    "application/json; charset=utf-8".to_string(),
+                    "application/json; charset=utf-8",
                 ))
             },
 
+            // Multipart uploads are buffered and parsed; other bodies stream to `handle_stream`
+            (Method::POST, UPLOAD_PATH) => Some(self.save_upload(request).await),
+
+            // Retune the rate limiter without a restart; admin-only via the default access rules
+            (Method::POST, "/admin/rate-limit") => {
+                let input: serde_json::Value = match serde_json::from_slice(request.body()) {
+                    Ok(input @ serde_json::Value::Object(_)) => input,
+                    _ => return Some(Response::json(StatusCode::BadRequest, &serde_json::json!({
+                        "success": false,
+                        "data": null,
+                        "message": "Request body must be a JSON object",
+                    }))),
+                };
+
+                let mut validator = Validator::new(&input);
+                let requests = validator.required_integer("requests", 1..=1_000_000);
+                let window_secs = validator.required_integer("window_secs", 1..=24 * 60 * 60);
+                let (Some(requests), Some(window_secs)) = (requests, window_secs) else {
+                    return Some(Response::json(StatusCode::UnprocessableEntity, &serde_json::json!({
+                        "success": false,
+                        "data": null,
+                        "message": "Validation failed",
+                        "errors": validator.finish().err().unwrap_or_default(),
+                    })));
+                };
+
+                let previous = self.rate_limiter.limit();
+                self.rate_limiter.set_limit(RateLimitSettings {
+                    requests: requests as usize,
+                    window: Duration::from_secs(window_secs),
+                });
+                println!(" [{}] Rate limit changed from {} per {}s to {} per {}s",
+                    request.request_id(), previous.requests, previous.window.as_secs(), requests, window_secs);
+
+                Some(Response::json(StatusCode::Ok, &serde_json::json!({
+                    "success": true,
+                    "data": { "requests": requests, "window_secs": window_secs },
+                    "message": "Rate limit updated",
+                })))
+            },
+
             // Handle query parameters example
             (Method::GET, "/api/search") => {
-                let query_result = match request.query_string() {
-                    Some(qs) => {
-                        if let Some(query) = qs.get("q") {
-                            format!(r#"{{"query": "{:?}", "results": ["result1", "result2", "result3"]}}"#, query)
-                        } else {
-                            r#"{"error": "Missing 'q' parameter"}"#.to_string()
-                        }
+                let data = match request.query_string() {
+                    Some(qs) => match qs.first("q") {
+                        Some(query) => {
+                            let query = form_decode(query).unwrap_or_else(|| query.to_string());
+                            serde_json::json!({ "query": query, "results": ["result1", "result2", "result3"] })
+                        },
+                        None => serde_json::json!({ "error": "Missing 'q' parameter" }),
                     },
-                    None => r#"{"error": "No query parameters provided"}"#.to_string()
+                    None => serde_json::json!({ "error": "No query parameters provided" }),
                 };
-                
-                let response = format!(
-                    r#"{{"success": true, "data": {}, "message": "Search completed"}}"#,
-                    query_result
-                );
-                
-                Some(Response::with_content_type(
-                    StatusCode::Ok,
-                    Some(response),
-                    "application/json; charset=utf-8".to_string(),
-                ))
+
+                Some(Response::json(StatusCode::Ok, &serde_json::json!({
+                    "success": true,
+                    "data": data,
+                    "message": "Search completed",
+                })))
             },
 
             // Time endpoint
@@ -192,181 +510,324 @@ impl WebsiteHandler {
                 Some(Response::with_content_type(
                     StatusCode::Ok,
                     Some(response),
-                    "application/json; charset=utf-8".to_string(),
+                    "application/json; charset=utf-8",
                 ))
             },
 
             // API route not found
-            (_, path) if path.starts_with("/api/") => {
-                let error_response = r#"{"success": false, "data": null, "message": "API endpoint not found"}"#;
-                Some(Response::with_content_type(
-                    StatusCode::NotFound,
-                    Some(error_response.to_string()),
-                    "application/json; charset=utf-8".to_string(),
-                ))
-            },
+            (_, path) if path.starts_with("/api/") => Some(self.handle_error(&ServerError::NotFound, Some(path))),
 
             // Not an API route
             _ => None,
         }
     }
 
-    // Your existing file serving methods (unchanged)
-    fn get_content_type(&self, file_path: &str) -> String {
-        match file_path.split('.').last() {
-            Some("html") => "text/html; charset=utf-8".to_string(),
-            Some("css") => "text/css; charset=utf-8".to_string(),
-            Some("js") => "application/javascript; charset=utf-8".to_string(),
-            Some("json") => "application/json; charset=utf-8".to_string(),
-            Some("xml") => "application/xml; charset=utf-8".to_string(),
-            Some("txt") => "text/plain; charset=utf-8".to_string(),
-            Some("png") => "image/png".to_string(),
-            Some("jpg") | Some("jpeg") => "image/jpeg".to_string(),
-            Some("gif") => "image/gif".to_string(),
-            Some("svg") => "image/svg+xml".to_string(),
-            Some("ico") => "image/x-icon".to_string(),
-            Some("webp") => "image/webp".to_string(),
-            Some("pdf") => "application/pdf".to_string(),
-            _ => "application/octet-stream".to_string(),
+    // Configured type for the file's extension; without one, the built-in table's type stands
+    fn mime_override(&self, file_path: &str) -> Option<String> {
+        let extension = file_path.split('.').next_back()?.to_ascii_lowercase();
+        self.mime_overrides.get(&extension).cloned()
+    }
+
+    // Server-side inference from magic bytes for files whose extension gives no type
+    fn sniff_content_type(&self, content: &[u8]) -> Option<&'static str> {
+        let text_start = content.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(content.len());
+        let text = &content[text_start..content.len().min(text_start + 14)];
+
+        if content.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some("image/png")
+        } else if content.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some("image/jpeg")
+        } else if content.starts_with(b"GIF87a") || content.starts_with(b"GIF89a") {
+            Some("image/gif")
+        } else if content.starts_with(b"%PDF-") {
+            Some("application/pdf")
+        } else if content.len() >= 12 && &content[..4] == b"RIFF" && &content[8..12] == b"WEBP" {
+            Some("image/webp")
+        } else if text.eq_ignore_ascii_case(b"<!doctype html") || text.get(..5).is_some_and(|t| t.eq_ignore_ascii_case(b"<html")) {
+            Some("text/html; charset=utf-8")
+        } else {
+            None
         }
     }
 
-    fn read_file(&self, file_path: &str) -> Option<(String, String)> {
-        if let Err(_) = self.security_validator.validate_path(file_path) {
-            return None;
+    // Blocked and missing files are both NotFound so probes can't tell them apart; files that exist
+    // but cannot be read map to Forbidden or an internal error (see PathError)
+    async fn read_file(&self, file_path: &str) -> Result<Response, ServerError> {
+        #[cfg(feature = "embedded")]
+        {
+            let decoded = self.decode_request_path(file_path).map_err(|e| e.into_server_error(file_path))?;
+            self.read_embedded_file(decoded.trim_matches('/'))
+        }
+        #[cfg(not(feature = "embedded"))]
+        {
+            let path = self.resolve_public_path(file_path).map_err(|e| e.into_server_error(file_path))?;
+            self.read_resolved_file(&path).await
         }
+    }
 
-        if !self.security_validator.validate_file_extension(file_path) {
-            eprintln!("Blocked file extension: {}", file_path);
-            return None;
+    // The single place a request path becomes a filesystem path: decode, join under the public
+    // root, canonicalize, and confirm the result is still inside it. The result may be a directory.
+    fn resolve_public_path(&self, request_path: &str) -> Result<PathBuf, PathError> {
+        let decoded = self.decode_request_path(request_path)?;
+        self.resolve_decoded_path(&decoded)
+    }
+
+    fn decode_request_path(&self, request_path: &str) -> Result<String, PathError> {
+        if self.security_validator.validate_path(request_path).is_err() {
+            return Err(PathError::Traversal);
         }
 
-        let requested_path = self.public_path.join(file_path.trim_start_matches('/'));
-        
-        match fs::canonicalize(&requested_path) {
-            Ok(canonical_path) => {
-                if !canonical_path.starts_with(&self.public_path) {
-                    eprintln!("Directory traversal attempt blocked: {}", file_path);
-                    return None;
-                }
+        // Decoding can reintroduce `..` or NUL (e.g. `%2e%2e`), so the decoded form is validated again
+        match percent_decode(request_path) {
+            Some(decoded) if self.security_validator.validate_path(&decoded).is_ok() => Ok(decoded),
+            _ => {
+                eprintln!("Blocked encoded path: {}", request_path);
+                Err(PathError::Traversal)
+            }
+        }
+    }
 
-                if canonical_path.is_file() {
-                    match fs::read_to_string(&canonical_path) {
-                        Ok(content) => {
-                            let content_type = self.get_content_type(file_path);
-                            println!(" Serving file: {}", canonical_path.display());
-                            Some((content, content_type))
-                        }
-                        Err(e) => {
-                            eprintln!("Failed to read file {}: {}", canonical_path.display(), e);
-                            None
-                        }
-                    }
-                } else {
-                    None
+    // `path` is already percent-decoded and validated; decoding again would let `%2541` through
+    fn resolve_decoded_path(&self, path: &str) -> Result<PathBuf, PathError> {
+        let relative_path = path.trim_start_matches('/');
+        if !self.follow_symlinks && self.traverses_symlink(relative_path) {
+            eprintln!("Symlink blocked: {}", path);
+            return Err(PathError::Traversal);
+        }
+
+        let canonical_path = fs::canonicalize(self.public_path.join(relative_path)).map_err(PathError::from)?;
+        if !canonical_path.starts_with(&self.public_path) {
+            eprintln!("Directory traversal attempt blocked: {}", path);
+            return Err(PathError::Traversal);
+        }
+        Ok(canonical_path)
+    }
+
+    // Whether a request path names a directory under the public root; paths that fail validation don't
+    fn is_directory(&self, request_path: &str) -> bool {
+        #[cfg(feature = "embedded")]
+        {
+            self.decode_request_path(request_path).is_ok_and(|decoded| embedded::is_dir(decoded.trim_matches('/')))
+        }
+        #[cfg(not(feature = "embedded"))]
+        {
+            self.resolve_public_path(request_path).is_ok_and(|path| path.is_dir())
+        }
+    }
+
+    // `path` comes from resolve_public_path, so it is canonical and inside the public root
+    async fn read_resolved_file(&self, path: &Path) -> Result<Response, ServerError> {
+        if path.is_dir() {
+            // Boxed because default documents are read through here again
+            return Box::pin(self.read_default_document(path)).await;
+        }
+        let file_path = path.to_string_lossy();
+        let file_path = file_path.as_ref();
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+
+        let sniff = self.check_file_type(file_name, file_path)?;
+        if !path.is_file() {
+            return Err(ServerError::NotFound);
+        }
+
+        let response = Response::from_file(StatusCode::Ok, path, &self.cache_policy).await
+            .map_err(|e| PathError::from(e).into_server_error(file_path))?;
+        self.typed_content(response, file_name, file_path, sniff)
+    }
+
+    // Embedded assets cannot escape the public root, but the same type checks and default
+    // documents apply as on disk
+    #[cfg(feature = "embedded")]
+    fn read_embedded_file(&self, relative_path: &str) -> Result<Response, ServerError> {
+        if embedded::is_dir(relative_path) {
+            for document in &self.default_documents {
+                let candidate = if relative_path.is_empty() { document.clone() } else { format!("{}/{}", relative_path, document) };
+                match self.read_embedded_file(&candidate) {
+                    Err(ServerError::NotFound) => continue,
+                    result => return result,
                 }
             }
-            Err(_) => None,
+            return Err(ServerError::NotFound);
+        }
+
+        let file_name = relative_path.rsplit('/').next().unwrap_or("");
+        let sniff = self.check_file_type(file_name, relative_path)?;
+        let content = embedded::file(relative_path).ok_or(ServerError::NotFound)?;
+        let response = Response::with_bytes(StatusCode::Ok, content.to_vec(), mime::content_type_for(file_name)).uncapped()
+            .with_cache_policy(&self.cache_policy);
+        self.typed_content(response, file_name, relative_path, sniff)
+    }
+
+    // Extensions must be on the allow-list unless an extensionless file will be sniffed; Ok(true)
+    // means the type comes from sniffing. Only the name is checked, so dots in parent directories
+    // cannot masquerade as extensions.
+    fn check_file_type(&self, file_name: &str, file_path: &str) -> Result<bool, ServerError> {
+        let sniff = self.content_sniffing && !file_name.contains('.');
+        if !sniff && !self.security_validator.validate_file_extension(file_name) {
+            eprintln!("Blocked file extension: {}", file_path);
+            return Err(ServerError::NotFound);
         }
+        Ok(sniff)
     }
 
-    fn create_safe_error_response(&self, status: StatusCode, message: &str) -> Response {
-        let safe_message = match status {
-            StatusCode::NotFound => "The requested resource was not found.".to_string(),
-            StatusCode::Forbidden => "Access to this resource is forbidden.".to_string(),
-            StatusCode::BadRequest => "The request was invalid.".to_string(),
-            _ => message.to_string(),
+    // `response` is typed by extension; sniffing and configured overrides take precedence
+    fn typed_content(&self, response: Response, file_name: &str, file_path: &str, sniff: bool) -> Result<Response, ServerError> {
+        let response = if sniff {
+            match self.sniff_content_type(response.body()) {
+                Some(content_type) => response.retyped(content_type, &self.cache_policy),
+                None => {
+                    eprintln!("Blocked file of unknown type: {}", file_path);
+                    return Err(ServerError::NotFound);
+                }
+            }
+        } else {
+            match self.mime_override(file_name) {
+                Some(content_type) => response.retyped(content_type, &self.cache_policy),
+                None => response,
+            }
         };
-        
-        Response::new(status, Some(safe_message))
+
+        if self.enable_templates && response.content_type().starts_with("text/html") {
+            Ok(response.map_body(|content| match String::from_utf8(content) {
+                Ok(text) => template::render(&text, &self.template_context()).into_bytes(),
+                Err(e) => e.into_bytes(),
+            }))
+        } else {
+            Ok(response)
+        }
     }
-}
 
-impl Handler for WebsiteHandler {
-    fn handle_request(&self, request: &Request, client_ip: SocketAddr) -> Response {
-        // Rate limiting check
-        if !self.rate_limiter.is_allowed(client_ip.ip()) {
-            return Response::rate_limited();
+    // Checks each component below the (already canonical) public root, so a symlinked directory
+    // halfway down the path is caught as well as a symlinked file
+    fn traverses_symlink(&self, relative_path: &str) -> bool {
+        let mut current = self.public_path.clone();
+        relative_path.split('/').filter(|segment| !segment.is_empty()).any(|segment| {
+            current.push(segment);
+            fs::symlink_metadata(&current).is_ok_and(|metadata| metadata.file_type().is_symlink())
+        })
+    }
+
+    // The rewritten path is only a lookup key; read_file still validates it like any request path
+    fn rewrite<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        if let Some(rule) = self.rewrites.iter().find(|rule| !rule.prefix && rule.from == path) {
+            return Cow::Owned(rule.to.clone());
         }
 
-        // Path security validation
-        if let Err(reason) = self.security_validator.validate_path(request.path()) {
-            return self.handle_security_violation(reason, client_ip);
+        let prefix_match = self.rewrites.iter()
+            .filter(|rule| rule.prefix)
+            .filter_map(|rule| {
+                let from = rule.from.trim_end_matches('/');
+                path.strip_prefix(from)
+                    .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+                    .map(|rest| (from.len(), rule, rest))
+            })
+            .max_by_key(|(matched_len, _, _)| *matched_len);
+
+        match prefix_match {
+            Some((_, rule, rest)) => Cow::Owned(format!("{}{}", rule.to.trim_end_matches('/'), rest)),
+            None => Cow::Borrowed(path),
         }
+    }
 
-        // logging
-        println!(" {} {} {} from {}", 
-            request.method_str(), 
-            request.path(),
-            if request.path().starts_with("/api/") { "" } else { "" },
-            client_ip.ip()
-        );
+    // The first configured default document that exists in the directory
+    async fn read_default_document(&self, directory: &Path) -> Result<Response, ServerError> {
+        let relative_directory = match directory.strip_prefix(&self.public_path).ok().and_then(Path::to_str) {
+            Some(relative_directory) => relative_directory,
+            None => return Err(ServerError::NotFound),
+        };
+        for document in &self.default_documents {
+            let resolved = match self.resolve_decoded_path(&format!("{}/{}", relative_directory, document)) {
+                Ok(path) => self.read_resolved_file(&path).await,
+                Err(e) => Err(e.into_server_error(document)),
+            };
+            match resolved {
+                Err(ServerError::NotFound) => continue,
+                result => return result,
+            }
+        }
+        Err(ServerError::NotFound)
+    }
+
+    // Falls back to `fallback` only when the primary file doesn't exist; real I/O failures surface
+    async fn read_file_or(&self, file_path: &str, fallback: Option<&str>) -> Result<Response, ServerError> {
+        // The .html twin goes through read_file too, so it gets the same path and extension checks
+        let file = match self.read_file(file_path).await {
+            Err(ServerError::NotFound) if self.clean_urls && !file_path.ends_with('/') && !has_extension(file_path) => {
+                self.read_file(&format!("{}.html", file_path)).await
+            },
+            file => file,
+        };
+        match (file, fallback) {
+            (Err(ServerError::NotFound), Some(fallback)) => self.read_file(fallback).await,
+            (result, _) => result,
+        }
+    }
+
+    fn static_file_response(&self, response: Response) -> Response {
+        let response = if self.dev_mode && response.content_type().starts_with("text/html") {
+            response.map_body(|content| self.inject_live_reload(content))
+        } else {
+            response
+        };
+        // Range requests are not implemented: Range and If-Range are ignored and the full entity is
+        // always sent, which this header tells clients up front (RFC 9110 §14.3)
+        response.with_header("Accept-Ranges", "none")
+    }
+
+    // Client-side routes like /dashboard/settings have no extension; missing assets like /app.js do
+    fn is_spa_route(&self, path: &str) -> bool {
+        self.spa_fallback && !has_extension(path)
+    }
+
+    fn template_context(&self) -> HashMap<&'static str, String> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        HashMap::from([
+            ("version", env!("CARGO_PKG_VERSION").to_string()),
+            ("time", timestamp.to_string()),
+            ("request_count", self.stats.total.load(Ordering::Relaxed).to_string()),
+        ])
+    }
+
+    async fn route_request(&self, request: &Request<'_>, client_ip: SocketAddr) -> Response {
+        if let Some(response) = self.access_denied(request) {
+            return response;
+        }
 
         // Try API routes first
-        if let Some(api_response) = self.handle_api_route(request, client_ip) {
-            return api_response;
+        if self.enable_api_routes {
+            if let Some(api_response) = self.handle_api_route(request, client_ip).await {
+                return api_response;
+            }
         }
 
         // Fall back to static file serving for non-API routes
         match request.method() {
-            Method::GET => {
-                match request.path() {
-                    "/" => {
-                        match self.read_file("index.html") {
-                            Some((content, content_type)) => {
-                                Response::with_content_type(StatusCode::Ok, Some(content), content_type)
-                            },
-                            None => {
-                                match self.read_file("hello.html") {
-                                    Some((content, content_type)) => {
-                                        Response::with_content_type(StatusCode::Ok, Some(content), content_type)
-                                    },
-                                    None => self.create_safe_error_response(StatusCode::NotFound, "Index page not found"),
-                                }
-                            }
-                        }
-                    }
-                    "/hello" => {
-                        match self.read_file("hello.html") {
-                            Some((content, content_type)) => {
-                                Response::with_content_type(StatusCode::Ok, Some(content), content_type)
-                            },
-                            None => self.create_safe_error_response(StatusCode::NotFound, "Page not found"),
-                        }
-                    }
-                    path => {
-                        match self.read_file(path) {
-                            Some((content, content_type)) => {
-                                Response::with_content_type(StatusCode::Ok, Some(content), content_type)
-                            },
-                            None => self.create_safe_error_response(StatusCode::NotFound, "File not found"),
-                        }
-                    }
+            // HEAD builds the full GET response; the server leaves the body off when sending it
+            Method::GET | Method::HEAD => {
+                // `//host` would become a protocol-relative Location, so such paths are never redirected
+                let bare_directory = self.directory_slash_redirect && !request.path().ends_with('/')
+                    && !request.path().starts_with("//") && self.is_directory(request.path());
+                if bare_directory {
+                    return moved_permanently(&with_query(&format!("{}/", request.path()), request.raw_query()));
                 }
-            },
-            Method::HEAD => {
-                match request.path() {
-                    "/" => {
-                        if self.read_file("index.html").is_some() || self.read_file("hello.html").is_some() {
-                            Response::html(StatusCode::Ok, None)
-                        } else {
-                            Response::new(StatusCode::NotFound, None)
-                        }
-                    },
-                    "/hello" => {
-                        if self.read_file("hello.html").is_some() {
-                            Response::html(StatusCode::Ok, None)
-                        } else {
-                            Response::new(StatusCode::NotFound, None)
-                        }
+
+                let path = self.rewrite(request.path());
+                let read_started = Instant::now();
+                let file = match path.as_ref() {
+                    "/" => self.read_file("/").await,
+                    path => self.read_file_or(path, if self.is_spa_route(path) { Some("index.html") } else { None }).await,
+                };
+                match file {
+                    Ok(response) if self.dev_mode => {
+                        let read_time = server_timing("file", read_started.elapsed());
+                        self.static_file_response(response).with_header("Server-Timing", &read_time)
                     },
-                    path => {
-                        if self.read_file(path).is_some() {
-                            Response::new(StatusCode::Ok, None)
-                        } else {
-                            Response::new(StatusCode::NotFound, None)
-                        }
-                    }
+                    Ok(response) => self.static_file_response(response),
+                    Err(e) => self.handle_error(&e, Some(request.path())),
                 }
             },
             Method::OPTIONS => {
@@ -378,4 +839,1050 @@ impl Handler for WebsiteHandler {
             },
         }
     }
-}
\ No newline at end of file
+
+    // Saves the `file` part of a multipart form into the upload directory under its own name.
+    // Existing files are never overwritten.
+    async fn save_upload(&self, request: &Request<'_>) -> Response {
+        let upload_dir = match &self.upload_dir {
+            Some(upload_dir) => upload_dir,
+            None => return self.handle_error(&ServerError::NotFound, Some(request.path())),
+        };
+        let boundary = request.header("Content-Type").and_then(multipart::boundary).unwrap_or_default();
+        let parts = match multipart::parse(request.body(), boundary) {
+            Ok(parts) => parts,
+            Err(e) => return self.handle_error(&ServerError::Parse(e), Some(request.path())),
+        };
+
+        let file = parts.iter().find(|part| part.name == "file");
+        let checked = match file {
+            None => Err("is required"),
+            Some(multipart::Part { filename: None, .. }) => Err("must be a file"),
+            Some(part @ multipart::Part { filename: Some(name), .. }) => {
+                self.security_validator.validate_upload(name, part.data.len()).map(|()| (*name, part))
+            },
+        };
+        let (name, part) = match checked {
+            Ok(checked) => checked,
+            Err(message) => return Response::json(StatusCode::UnprocessableEntity, &serde_json::json!({
+                "success": false,
+                "data": null,
+                "message": "Validation failed",
+                "errors": [FieldError { field: "file", message }],
+            })),
+        };
+
+        match write_new_file(&upload_dir.join(name), part.data).await {
+            Ok(()) => {
+                println!(" [{}] Saved upload {} ({} bytes)", request.request_id(), name, part.data.len());
+                Response::json(StatusCode::Created, &serde_json::json!({
+                    "success": true,
+                    "data": { "name": name, "size": part.data.len(), "content_type": part.content_type },
+                    "message": "File uploaded",
+                }))
+            },
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Response::json(StatusCode::Conflict, &serde_json::json!({
+                "success": false,
+                "data": null,
+                "message": "A file with that name already exists",
+            })),
+            Err(e) => {
+                eprintln!("[{}] Failed to save upload {}: {}", request.request_id(), name, e);
+                self.handle_error(&ServerError::Io(e), Some(request.path()))
+            },
+        }
+    }
+
+    // The 401/403 for a request the access rules turn away, or None when it may proceed
+    fn access_denied(&self, request: &Request) -> Option<Response> {
+        match self.security_validator.authorize(*request.method(), request.path(), request.header("Authorization")) {
+            Ok(()) => None,
+            Err(AccessDenied::Unauthenticated) => {
+                Some(Response::new(StatusCode::Unauthorized, Some("Authentication required".to_string()))
+                    .with_header("WWW-Authenticate", "Bearer"))
+            },
+            Err(AccessDenied::Forbidden) => {
+                println!(" [{}] Insufficient role for {} {}", request.request_id(), request.method_str(), request.path());
+                Some(self.handle_error(&ServerError::Forbidden, Some(request.path())))
+            },
+        }
+    }
+
+    async fn dispatch(&self, request: &Request<'_>, client_ip: SocketAddr) -> Response {
+        let path = request.path();
+        // Trimming a directory's slash would just bounce off the directory redirect
+        if path == "/" || !path.ends_with('/') || (self.directory_slash_redirect && self.is_directory(path)) {
+            return self.route_request(request, client_ip).await;
+        }
+
+        match self.trailing_slash {
+            TrailingSlash::Strict => self.route_request(request, client_ip).await,
+            TrailingSlash::Redirect => {
+                let canonical = match path.trim_end_matches('/') {
+                    "" => "/",
+                    trimmed => trimmed,
+                };
+                // `//host/` would become the protocol-relative `//host`, an open redirect
+                if canonical.starts_with("//") {
+                    return self.route_request(request, client_ip).await;
+                }
+                moved_permanently(&with_query(canonical, request.raw_query()))
+            },
+            TrailingSlash::Ignore => {
+                let mut request = request.clone();
+                request.trim_trailing_slash();
+                self.route_request(&request, client_ip).await
+            },
+        }
+    }
+}
+
+impl Handler for WebsiteHandler {
+    fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    // Dev mode reports handling time to the browser's network panel; middleware is not included
+    async fn handle_request(&self, request: &Request<'_>, client_ip: SocketAddr) -> Response {
+        if !self.dev_mode {
+            return self.dispatch(request, client_ip).await;
+        }
+        let started = Instant::now();
+        let response = self.dispatch(request, client_ip).await;
+        response.with_header("Server-Timing", &server_timing("total", started.elapsed()))
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.enable_api_routes && *request.method() == Method::POST && request.path() == UPLOAD_PATH
+            && request.header("Content-Type").and_then(multipart::boundary).is_none()
+    }
+
+    // Upload sink: counts the body as it arrives without ever holding more than one piece of it
+    async fn handle_stream(&self, request: &Request<'_>, body: &mut BodyStream<'_>, _client_ip: SocketAddr) -> Response {
+        if let Some(response) = self.access_denied(request) {
+            return response;
+        }
+
+        let mut received = 0;
+        loop {
+            match body.next_chunk().await {
+                Ok(Some(chunk)) => received += chunk.len(),
+                Ok(None) => break,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    return self.handle_error(&ServerError::Timeout, Some(request.path()));
+                },
+                Err(e) => {
+                    println!(" [{}] Upload aborted after {} bytes: {}", request.request_id(), received, e);
+                    return self.handle_error(&ServerError::Io(e), Some(request.path()));
+                },
+            }
+        }
+
+        Response::json(StatusCode::Ok, &serde_json::json!({
+            "success": true,
+            "data": { "bytes": received },
+            "message": "Upload received",
+        }))
+    }
+}
+
+// The header block of `raw` (up to the blank line) as text, with credential values blanked out
+fn redacted_raw_head(raw: &[u8]) -> String {
+    let head = find_head_end(raw).map_or(raw, |(end, _)| &raw[..end]);
+    String::from_utf8_lossy(head)
+        .split_inclusive('\n')
+        .map(|line| match line.split_once(':') {
+            Some((name, value)) if REDACTED_ECHO_HEADERS.contains(&name.trim().to_ascii_lowercase().as_str()) => {
+                let ending = &value[value.trim_end_matches(['\r', '\n']).len()..];
+                format!("{}: [REDACTED]{}", name, ending)
+            },
+            _ => line.to_string(),
+        })
+        .collect()
+}
+
+fn moved_permanently(location: &str) -> Response {
+    Response::new(StatusCode::MovedPermanently, Some("Moved Permanently".to_string()))
+        .with_header("Location", location)
+}
+
+fn with_query(path: &str, query: Option<&str>) -> String {
+    match query {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    }
+}
+
+// Never replaces an existing file, and removes the new one again if it couldn't be written in full
+async fn write_new_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new().write(true).create_new(true).open(path).await?;
+    // tokio's File writes in the background, so errors may only show up on flush
+    let written = match file.write_all(data).await {
+        Ok(()) => file.flush().await,
+        Err(e) => Err(e),
+    };
+    if written.is_err() {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+    written
+}
+
+fn has_extension(path: &str) -> bool {
+    path.rsplit('/').next().unwrap_or("").contains('.')
+}
+
+// One Server-Timing metric, in milliseconds as the header expects
+fn server_timing(metric: &str, elapsed: Duration) -> String {
+    format!("{};dur={:.3}", metric, elapsed.as_secs_f64() * 1000.0)
+}
+
+struct StatsRecorder(Arc<RequestStats>);
+
+impl Middleware for StatsRecorder {
+    fn before(&self, _request: &Request, _client_ip: SocketAddr) -> Result<Option<Response>, Rejection> {
+        self.0.total.fetch_add(1, Ordering::Relaxed);
+        Ok(None)
+    }
+
+    fn after(&self, _request: &Request, response: Response) -> Response {
+        self.0.record_status(response.status_code());
+        response
+    }
+}
+
+// Dev mode only: answers the EventSource poll with the current reload generation
+struct LiveReload(Arc<AtomicU64>);
+
+impl Middleware for LiveReload {
+    fn before(&self, request: &Request, _client_ip: SocketAddr) -> Result<Option<Response>, Rejection> {
+        if request.path() != LIVE_RELOAD_PATH {
+            return Ok(None);
+        }
+        let event = format!("retry: 1000\ndata: {}\n\n", self.0.load(Ordering::Acquire));
+        Ok(Some(Response::with_content_type(StatusCode::Ok, Some(event), "text/event-stream")
+            .with_cache_control("no-cache")))
+    }
+}
+
+// Why a request path does not lead to a readable file under the public root
+#[derive(Debug)]
+enum PathError {
+    // `..`, NUL, a blocked symlink, or a canonical path outside the root
+    Traversal,
+    NotFound,
+    // Exists but the server may not read it
+    Forbidden,
+    // Anything else (symlink loops, I/O failures) is a server-side problem
+    Io(io::Error),
+}
+
+impl From<io::Error> for PathError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => Self::NotFound,
+            io::ErrorKind::PermissionDenied => Self::Forbidden,
+            _ => Self::Io(error),
+        }
+    }
+}
+
+impl PathError {
+    // Traversal is reported as NotFound so probes learn nothing about the layout
+    fn into_server_error(self, file_path: &str) -> ServerError {
+        match self {
+            Self::Traversal | Self::NotFound => ServerError::NotFound,
+            Self::Forbidden => {
+                eprintln!("Permission denied reading {}", file_path);
+                ServerError::Forbidden
+            },
+            Self::Io(error) => {
+                eprintln!("Failed to read file {}: {}", file_path, error);
+                ServerError::Io(error)
+            },
+        }
+    }
+}
+
+// Tests that serve files from a Fixture are compiled out of the embedded build, which never reads
+// the public directory
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::SendOptions;
+    use crate::security::Role;
+    use crate::server::{testing, Server};
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    const CLIENT: &str = "127.0.0.1:40000";
+
+    // A throwaway public directory, removed when the test ends
+    struct Fixture {
+        root: PathBuf,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let name = format!("rust-server-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+            let root = std::env::temp_dir().join(name);
+            fs::create_dir_all(&root).unwrap();
+            Self { root: fs::canonicalize(root).unwrap() }
+        }
+
+        fn file(self, path: &str, contents: impl AsRef<[u8]>) -> Self {
+            let path = self.root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+            self
+        }
+
+        fn handler(&self, config: SecurityConfig) -> WebsiteHandler {
+            WebsiteHandler::new(self.root.clone(), config)
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    async fn handle(handler: &impl Handler, raw: &[u8]) -> Response {
+        handler.handle_request(&Request::try_from(raw).unwrap(), CLIENT.parse().unwrap()).await
+    }
+
+    async fn send(handler: &impl Handler, method: Method, target: &str, headers: &[(&str, &str)], body: &[u8]) -> Response {
+        let mut all_headers = vec![("Host", "localhost:8080")];
+        all_headers.extend_from_slice(headers);
+        let request = Request::for_test(method, target, &all_headers, body, CLIENT.parse().unwrap());
+        handler.handle_request(&request, CLIENT.parse().unwrap()).await
+    }
+
+    async fn get(handler: &impl Handler, target: &str) -> Response {
+        send(handler, Method::GET, target, &[], b"").await
+    }
+
+    // The response as a client would receive it
+    async fn wire(response: Response) -> String {
+        let mut bytes = Vec::new();
+        response.send(&mut bytes, SendOptions::closing()).await.unwrap();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    async fn body(response: Response) -> String {
+        testing::body(&wire(response).await).to_string()
+    }
+
+    async fn content_type(response: Response) -> String {
+        testing::header(&wire(response).await, "Content-Type").unwrap_or_default().to_string()
+    }
+
+    async fn json(response: Response) -> serde_json::Value {
+        serde_json::from_str(&body(response).await).expect("body is not JSON")
+    }
+
+    #[tokio::test]
+    async fn api_info_negotiates_its_representation() {
+        let fixture = Fixture::new();
+        let handler = &fixture.handler(SecurityConfig::default());
+        let info = |accept| async move { wire(send(handler, Method::GET, "/api/info", accept, b"").await).await };
+
+        let response = info(&[]).await;
+        assert_eq!(testing::header(&response, "Content-Type"), Some("application/json; charset=utf-8"));
+        assert_eq!(serde_json::from_str::<serde_json::Value>(testing::body(&response)).unwrap()["server"], "Rust HTTP Server");
+        let response = info(&[("Accept", "text/plain")]).await;
+        assert_eq!(testing::header(&response, "Content-Type"), Some("text/plain; charset=utf-8"));
+        assert!(testing::body(&response).starts_with("server: Rust HTTP Server\n"));
+        let response = info(&[("Accept", "application/xml")]).await;
+        assert_eq!(testing::header(&response, "Content-Type"), Some("application/xml; charset=utf-8"));
+        assert!(testing::body(&response).starts_with("<?xml"));
+        assert_eq!(testing::status(&info(&[("Accept", "image/png")]).await), 406);
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn templates_render_only_when_enabled() {
+        let fixture = Fixture::new().file("page.html", "<p>{{version}} {{nope}}</p>");
+        let rendered = format!("<p>{} {{{{nope}}}}</p>", env!("CARGO_PKG_VERSION"));
+
+        let handler = fixture.handler(SecurityConfig { enable_templates: true, ..SecurityConfig::default() });
+        assert_eq!(body(get(&handler, "/page.html").await).await, rendered);
+        let handler = fixture.handler(SecurityConfig::default());
+        assert_eq!(body(get(&handler, "/page.html").await).await, "<p>{{version}} {{nope}}</p>");
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn spa_routes_get_index_but_missing_assets_404() {
+        let fixture = Fixture::new().file("index.html", "<div id=app></div>");
+        let handler = fixture.handler(SecurityConfig { spa_fallback: true, ..SecurityConfig::default() });
+
+        let response = get(&handler, "/some/app/route").await;
+        assert_eq!(response.status_code(), StatusCode::Ok);
+        assert_eq!(body(response).await, "<div id=app></div>");
+        assert_eq!(get(&handler, "/missing.js").await.status_code(), StatusCode::NotFound);
+        assert_eq!(get(&handler, "/api/missing").await.status_code(), StatusCode::NotFound);
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn extensionless_files_are_typed_by_magic_bytes() {
+        let fixture = Fixture::new()
+            .file("photo", b"\xFF\xD8\xFF\xE0rest")
+            .file("doc", b"%PDF-1.7")
+            .file("page", "  <!DOCTYPE html><p>hi</p>")
+            .file("blob", b"\x00\x01\x02");
+        let handler = fixture.handler(SecurityConfig { content_sniffing: true, ..SecurityConfig::default() });
+
+        assert_eq!(content_type(get(&handler, "/photo").await).await, "image/jpeg");
+        assert_eq!(content_type(get(&handler, "/doc").await).await, "application/pdf");
+        assert_eq!(content_type(get(&handler, "/page").await).await, "text/html; charset=utf-8");
+        // Unrecognised content is refused rather than served as octet-stream
+        assert_eq!(get(&handler, "/blob").await.status_code(), StatusCode::NotFound);
+
+        let handler = fixture.handler(SecurityConfig::default());
+        assert_eq!(get(&handler, "/photo").await.status_code(), StatusCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn api_info_counts_static_and_rejected_requests() {
+        let fixture = Fixture::new().file("index.html", "home");
+        let stack = fixture.handler(SecurityConfig::default()).with_default_middleware();
+
+        assert_eq!(get(&stack, "/index.html").await.status_code(), StatusCode::Ok);
+        assert_eq!(get(&stack, "/nope.html").await.status_code(), StatusCode::NotFound);
+        assert_eq!(get(&stack, "/.git/config").await.status_code(), StatusCode::BadRequest);
+
+        // The info request itself is counted on the way in, its status only once it is answered
+        let info = json(get(&stack, "/api/info").await).await;
+        assert_eq!(info["requests_served"], 4);
+        assert_eq!(info["responses_2xx"], 1);
+        assert_eq!(info["responses_4xx"], 2);
+        assert_eq!(info["responses_5xx"], 0);
+    }
+
+    #[tokio::test]
+    async fn echo_reflects_headers_and_body_with_credentials_redacted() {
+        let fixture = Fixture::new();
+        let handler = fixture.handler(SecurityConfig::default());
+        let headers = [("Content-Type", "application/json"), ("X-Trace", "abc"), ("Authorization", "Bearer secret")];
+        let response = send(&handler, Method::POST, "/api/echo", &headers, br#"{"name":"</script>"}"#).await;
+
+        assert_eq!(response.status_code(), StatusCode::Ok);
+        let echoed = json(response).await;
+        let data = &echoed["data"];
+        assert_eq!(data["body"], r#"{"name":"</script>"}"#);
+        assert_eq!(data["headers"]["x-trace"], "abc");
+        assert_eq!(data["headers"]["authorization"], "[REDACTED]");
+        assert_eq!(data["body_truncated"], false);
+
+        let large = vec![b'a'; ECHO_BODY_LIMIT + 1];
+        let echoed = json(send(&handler, Method::POST, "/api/echo", &[], &large).await).await;
+        let data = &echoed["data"];
+        assert_eq!(data["body"].as_str().unwrap().len(), ECHO_BODY_LIMIT);
+        assert_eq!(data["body_length"], ECHO_BODY_LIMIT + 1);
+        assert_eq!(data["body_truncated"], true);
+
+        // A dual-stack listener sees IPv4 clients as ::ffff:a.b.c.d
+        let mapped: SocketAddr = "[::ffff:203.0.113.9]:40000".parse().unwrap();
+        let request = Request::for_test(Method::POST, "/api/echo", &[("Host", "localhost:8080")], b"", mapped);
+        let echoed = json(handler.handle_request(&request, mapped).await).await;
+        assert_eq!(echoed["data"]["client_ip"], "203.0.113.9");
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn dev_mode_injects_live_reload_into_html_only() {
+        let fixture = Fixture::new().file("page.html", "<body><p>hi</p></body>").file("site.css", "body {}");
+        let handler = fixture.handler(SecurityConfig { dev_mode: true, ..SecurityConfig::default() });
+
+        let expected = format!("<body><p>hi</p>{}</body>", LIVE_RELOAD_SCRIPT);
+        assert_eq!(body(get(&handler, "/page.html").await).await, expected);
+        assert_eq!(body(get(&handler, "/site.css").await).await, "body {}");
+
+        let handler = fixture.handler(SecurityConfig::default());
+        assert_eq!(body(get(&handler, "/page.html").await).await, "<body><p>hi</p></body>");
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn mime_overrides_win_over_the_octet_stream_fallback() {
+        let fixture = Fixture::new().file("data.dat", b"\x00\x01").file("LOUD.DAT", b"\x00\x01");
+        let mut config = SecurityConfig::default();
+        config.allowed_file_extensions.push("dat");
+        assert_eq!(content_type(get(&fixture.handler(config.clone()), "/data.dat").await).await, "application/octet-stream");
+
+        let mime_overrides = HashMap::from([(".Dat".to_string(), "application/x-custom-data".to_string())]);
+        let handler = fixture.handler(SecurityConfig { mime_overrides, ..config });
+        assert_eq!(content_type(get(&handler, "/data.dat").await).await, "application/x-custom-data");
+        assert_eq!(content_type(get(&handler, "/LOUD.DAT").await).await, "application/x-custom-data");
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn percent_encoded_paths_are_decoded_but_cannot_escape() {
+        let fixture = Fixture::new().file("public/my file.txt", "spaced").file("secret.txt", "hidden");
+        let handler = WebsiteHandler::new(fixture.root.join("public"), SecurityConfig::default());
+
+        let response = get(&handler, "/my%20file.txt").await;
+        assert_eq!(response.status_code(), StatusCode::Ok);
+        assert_eq!(body(response).await, "spaced");
+
+        for target in ["/%2e%2e/secret.txt", "/%2E%2E%2Fsecret.txt", "/my%00file.txt"] {
+            let response = get(&handler, target).await;
+            assert_eq!(response.status_code(), StatusCode::NotFound, "{}", target);
+            assert_ne!(body(response).await, "hidden", "{}", target);
+        }
+    }
+
+    #[tokio::test]
+    async fn mutating_user_routes_need_an_admin_token() {
+        let fixture = Fixture::new();
+        let api_tokens = HashMap::from([("user-token".to_string(), Role::User), ("admin-token".to_string(), Role::Admin)]);
+        let handler = fixture.handler(SecurityConfig { api_tokens, ..SecurityConfig::default() });
+
+        assert_eq!(get(&handler, "/api/users").await.status_code(), StatusCode::Ok);
+
+        let response = send(&handler, Method::DELETE, "/api/users/1", &[], b"").await;
+        assert_eq!(response.status_code(), StatusCode::Unauthorized);
+        assert_eq!(response.header("WWW-Authenticate"), Some("Bearer"));
+        let unknown = send(&handler, Method::DELETE, "/api/users/1", &[("Authorization", "Bearer guessed")], b"").await;
+        assert_eq!(unknown.status_code(), StatusCode::Unauthorized);
+
+        let user = send(&handler, Method::DELETE, "/api/users/1", &[("Authorization", "Bearer user-token")], b"").await;
+        assert_eq!(user.status_code(), StatusCode::Forbidden);
+        // Past the access check there is no delete route; creating a user shows an admin gets through
+        let admin = send(&handler, Method::DELETE, "/api/users/1", &[("Authorization", "Bearer admin-token")], b"").await;
+        assert_eq!(admin.status_code(), StatusCode::NotFound);
+        let body = br#"{"name": "Dana", "email": "dana@example.com"}"#;
+        let created = send(&handler, Method::POST, "/api/users", &[("Authorization", "Bearer admin-token")], body).await;
+        assert_eq!(created.status_code(), StatusCode::Created);
+    }
+
+    #[cfg(unix)]
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn unreadable_file_is_a_500_and_api_404s_are_json() {
+        let fixture = Fixture::new();
+        // A symlink loop fails to resolve with neither NotFound nor PermissionDenied
+        std::os::unix::fs::symlink("loop.html", fixture.root.join("loop.html")).unwrap();
+        let handler = fixture.handler(SecurityConfig { follow_symlinks: true, ..SecurityConfig::default() });
+
+        let response = get(&handler, "/loop.html").await;
+        assert_eq!(response.status_code(), StatusCode::InternalServerError);
+        assert_eq!(response.body(), b"Internal server error");
+
+        let response = get(&handler, "/api/nope").await;
+        assert_eq!(response.status_code(), StatusCode::NotFound);
+        assert!(response.content_type().starts_with("application/json"));
+        assert_eq!(json(response).await["success"], false);
+        assert_eq!(get(&handler, "/nope.html").await.body(), b"The requested resource was not found.");
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn sniffed_files_are_cached_by_their_sniffed_type() {
+        let fixture = Fixture::new().file("site.css", "body {}").file("logo", b"\x89PNG\r\n\x1a\n");
+        let handler = fixture.handler(SecurityConfig { content_sniffing: true, ..SecurityConfig::default() });
+
+        for (path, content_type) in [("/site.css", "text/css; charset=utf-8"), ("/logo", "image/png")] {
+            let response = get(&handler, path).await;
+            assert_eq!(response.content_type(), content_type);
+            assert!(response.header("Last-Modified").is_some_and(|date| date.ends_with(" GMT")), "{}", path);
+            let (encoded, head_len) = response.encode(&SendOptions::closing()).unwrap();
+            let head = String::from_utf8_lossy(&encoded[..head_len]).into_owned();
+            assert!(head.contains("Cache-Control: public, max-age=2592000\r\n"), "{}", head);
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limited_api_requests_get_json_and_pages_get_text() {
+        let fixture = Fixture::new().file("page.html", "page");
+        let stack = fixture.handler(SecurityConfig { rate_limit_requests: 1, ..SecurityConfig::default() }).with_default_middleware();
+        assert_eq!(get(&stack, "/api/ping").await.status_code(), StatusCode::Ok);
+
+        let api = get(&stack, "/api/ping").await;
+        assert_eq!(api.status_code(), StatusCode::TooManyRequests);
+        assert!(api.content_type().starts_with("application/json"));
+        assert_eq!(json(api).await["success"], false);
+
+        let page = get(&stack, "/page").await;
+        assert_eq!(page.status_code(), StatusCode::TooManyRequests);
+        assert!(page.content_type().starts_with("text/plain"));
+        assert_eq!(page.body(), b"Rate limit exceeded. Please try again later.");
+    }
+
+    #[tokio::test]
+    async fn synthetic_requests_are_limited_by_their_own_client_address() {
+        let fixture = Fixture::new();
+        let stack = fixture.handler(SecurityConfig { rate_limit_requests: 1, ..SecurityConfig::default() }).with_default_middleware();
+
+        let clients = [("192.0.2.1:1000", StatusCode::Ok), ("192.0.2.1:1001", StatusCode::TooManyRequests), ("192.0.2.2:1000", StatusCode::Ok)];
+        for (client, status) in clients {
+            let addr: SocketAddr = client.parse().unwrap();
+            let request = Request::for_test(Method::GET, "/api/ping", &[("Host", "localhost:8080")], b"", addr);
+            assert_eq!(request.remote_addr(), Some(addr));
+            assert_eq!(stack.handle_request(&request, addr).await.status_code(), status, "{}", client);
+        }
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn rewrites_serve_their_target_but_stay_under_the_root() {
+        let fixture = Fixture::new()
+            .file("public/new-page.html", "new")
+            .file("public/documentation/intro.html", "intro")
+            .file("secret.html", "hidden");
+        let rewrite = |from: &str, to: &str, prefix| Rewrite { from: from.to_string(), to: to.to_string(), prefix };
+        let rewrites = vec![
+            rewrite("/old-page", "/new-page.html", false),
+            rewrite("/docs", "/documentation", true),
+            rewrite("/escape", "/../secret.html", false),
+            rewrite("/up", "/%2e%2e", true),
+        ];
+        let handler = WebsiteHandler::new(fixture.root.join("public"), SecurityConfig { rewrites, ..SecurityConfig::default() });
+
+        assert_eq!(get(&handler, "/old-page").await.body(), b"new");
+        assert_eq!(get(&handler, "/docs/intro.html").await.body(), b"intro");
+        for target in ["/escape", "/up/secret.html"] {
+            let response = get(&handler, target).await;
+            assert_eq!(response.status_code(), StatusCode::NotFound, "{}", target);
+            assert_ne!(response.body(), b"hidden", "{}", target);
+        }
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn static_assets_get_max_age_while_the_api_stays_no_store() {
+        let fixture = Fixture::new().file("site.css", "body {}").file("index.html", "home");
+        let handler = fixture.handler(SecurityConfig::default());
+        let cache_control = |response: Response| {
+            let (encoded, head_len) = response.encode(&SendOptions::closing()).unwrap();
+            let head = String::from_utf8_lossy(&encoded[..head_len]).into_owned();
+            head.lines().find_map(|line| line.strip_prefix("Cache-Control: ")).map(str::to_string)
+        };
+
+        assert_eq!(cache_control(get(&handler, "/site.css").await).as_deref(), Some("public, max-age=2592000"));
+        assert_eq!(cache_control(get(&handler, "/index.html").await).as_deref(), Some("public, max-age=300"));
+        let api = get(&handler, "/api/info").await;
+        assert_eq!(api.status_code(), StatusCode::Ok);
+        assert!(cache_control(api).is_some_and(|value| value.contains("no-store")));
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn paths_over_max_path_length_are_414() {
+        let fixture = Fixture::new().file("at-the-limit.js", "ok").file("past-the-limit.js", "ok");
+        let stack = fixture.handler(SecurityConfig { max_path_length: 16, ..SecurityConfig::default() }).with_default_middleware();
+
+        assert_eq!(get(&stack, "/at-the-limit.js").await.status_code(), StatusCode::Ok);
+        let response = get(&stack, "/past-the-limit.js").await;
+        assert_eq!(response.status_code(), StatusCode::UriTooLong);
+        assert_eq!(response.status_code() as u16, 414);
+    }
+
+    #[test]
+    fn io_error_kinds_map_to_404_403_and_500() {
+        let cases = [
+            (io::ErrorKind::NotFound, StatusCode::NotFound),
+            (io::ErrorKind::NotADirectory, StatusCode::NotFound),
+            (io::ErrorKind::PermissionDenied, StatusCode::Forbidden),
+            (io::ErrorKind::Other, StatusCode::InternalServerError),
+        ];
+        for (kind, status) in cases {
+            assert_eq!(PathError::from(io::Error::from(kind)).into_server_error("/file").status_code(), status, "{:?}", kind);
+        }
+    }
+
+    #[cfg(unix)]
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn restricted_files_are_403_and_missing_ones_404() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let fixture = Fixture::new().file("locked/page.html", "private").file("open.html", "public");
+        let locked = fixture.root.join("locked");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        let handler = fixture.handler(SecurityConfig::default());
+
+        // Privileged users read through permission bits, leaving nothing to deny
+        if fs::read(locked.join("page.html")).is_err() {
+            assert_eq!(get(&handler, "/locked/page.html").await.status_code(), StatusCode::Forbidden);
+        }
+        assert_eq!(get(&handler, "/open.html").await.status_code(), StatusCode::Ok);
+        assert_eq!(get(&handler, "/missing.html").await.status_code(), StatusCode::NotFound);
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn symlinks_inside_the_root_need_opting_in_and_outside_ones_never_work() {
+        use std::os::unix::fs::symlink;
+
+        let fixture = Fixture::new().file("public/real.html", "real").file("secret.html", "hidden");
+        let public = fixture.root.join("public");
+        symlink(public.join("real.html"), public.join("inside.html")).unwrap();
+        symlink(fixture.root.join("secret.html"), public.join("outside.html")).unwrap();
+        symlink(&fixture.root, public.join("parent")).unwrap();
+
+        for follow_symlinks in [false, true] {
+            let handler = WebsiteHandler::new(public.clone(), SecurityConfig { follow_symlinks, ..SecurityConfig::default() });
+            let inside = get(&handler, "/inside.html").await;
+            assert_eq!(inside.status_code() == StatusCode::Ok, follow_symlinks);
+            for target in ["/outside.html", "/parent/secret.html"] {
+                let response = get(&handler, target).await;
+                assert_eq!(response.status_code(), StatusCode::NotFound, "{} {}", target, follow_symlinks);
+                assert_ne!(response.body(), b"hidden");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn denied_requests_show_up_in_api_stats() {
+        let fixture = Fixture::new();
+        let api_tokens = HashMap::from([("admin-token".to_string(), Role::Admin)]);
+        let config = SecurityConfig { rate_limit_requests: 2, api_tokens, ..SecurityConfig::default() };
+        let stack = fixture.handler(config).with_default_middleware();
+        let stats = || async {
+            let other_client = "192.0.2.9:4000".parse().unwrap();
+            let request = Request::for_test(Method::GET, "/api/stats", &[("Host", "localhost:8080"), ("Authorization", "Bearer admin-token")], b"", other_client);
+            json(stack.handle_request(&request, other_client).await).await["data"]["rate_limiter"].clone()
+        };
+
+        assert_eq!(stats().await["denied_total"], 0);
+        for _ in 0..4 {
+            get(&stack, "/api/ping").await;
+        }
+        let limiter = stats().await;
+        assert_eq!(limiter["denied_total"], 2);
+        assert!(limiter["throttled"].as_array().unwrap().contains(&"127.0.0.1".into()));
+    }
+
+    #[tokio::test]
+    async fn search_queries_with_quotes_are_escaped() {
+        let fixture = Fixture::new();
+        let handler = fixture.handler(SecurityConfig::default());
+
+        let response = get(&handler, "/api/search?q=say%20%22hi%22%7D").await;
+        assert_eq!(response.content_type(), "application/json; charset=utf-8");
+        assert!(std::str::from_utf8(response.body()).unwrap().contains(r#""query":"say \"hi\"}""#));
+        assert_eq!(json(response).await["data"]["query"], "say \"hi\"}");
+        assert_eq!(json(get(&handler, "/api/search?q=hello+world").await).await["data"]["query"], "hello world");
+    }
+
+    #[tokio::test]
+    async fn quotes_backslashes_and_control_characters_stay_valid_json() {
+        let fixture = Fixture::new();
+        let handler = fixture.handler(SecurityConfig::default());
+        let hostile = "a\"b\\c\u{0}\u{1f}\n";
+
+        let response = get(&handler, "/api/search?q=a%22b%5Cc%00%1F%0A").await;
+        assert_eq!(json(response).await["data"]["query"], hostile);
+
+        let response = send(&handler, Method::POST, "/api/echo", &[("X-Note", "a\"b\\c")], hostile.as_bytes()).await;
+        let data = &json(response).await["data"];
+        assert_eq!(data["body"], hostile);
+        assert_eq!(data["headers"]["x-note"], "a\"b\\c");
+    }
+
+    #[tokio::test]
+    async fn empty_posts_to_echo_are_answered_at_once() {
+        let fixture = Fixture::new();
+        let addr = testing::spawn(Server::new(String::new()), fixture.handler(SecurityConfig::default())).await;
+        for length in ["Content-Length: 0\r\n", ""] {
+            let request = format!("POST /api/echo HTTP/1.1\r\nHost: localhost:8080\r\n{}Connection: close\r\n\r\n", length);
+            let exchange = testing::exchange(addr, request.as_bytes());
+            let response = tokio::time::timeout(Duration::from_secs(1), exchange).await.expect("empty POST hung");
+            assert_eq!(testing::status(&response), 200, "{:?}", length);
+            let data: serde_json::Value = serde_json::from_str(testing::body(&response)).unwrap();
+            assert_eq!(data["data"]["body"], "");
+            assert_eq!(data["data"]["body_length"], 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn each_trailing_slash_policy_routes_both_forms() {
+        let fixture = Fixture::new();
+        let policies = [
+            // Strictly, `/api/users/` is the by-id route with an empty id
+            (TrailingSlash::Strict, StatusCode::BadRequest),
+            (TrailingSlash::Redirect, StatusCode::MovedPermanently),
+            (TrailingSlash::Ignore, StatusCode::Ok),
+        ];
+        for (trailing_slash, slashed) in policies {
+            let handler = fixture.handler(SecurityConfig { trailing_slash, ..SecurityConfig::default() });
+            assert_eq!(get(&handler, "/api/users").await.status_code(), StatusCode::Ok, "{:?}", trailing_slash);
+            let response = get(&handler, "/api/users/?page=2").await;
+            assert_eq!(response.status_code(), slashed, "{:?}", trailing_slash);
+            if trailing_slash == TrailingSlash::Redirect {
+                assert_eq!(response.header("Location"), Some("/api/users?page=2"));
+            }
+        }
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn range_requests_get_the_whole_entity() {
+        use crate::server::{testing, Server};
+
+        let fixture = Fixture::new().file("data.txt", "0123456789");
+        let addr = testing::spawn(Server::new(String::new()), fixture.handler(SecurityConfig::default())).await;
+        let request = |method, extra| format!("{} /data.txt HTTP/1.1\r\nHost: localhost:8080\r\nRange: bytes=2-4\r\n{}Connection: close\r\n\r\n", method, extra);
+
+        let head = testing::exchange(addr, request("HEAD", "").as_bytes()).await;
+        assert_eq!(testing::status(&head), 200);
+        assert_eq!(testing::header(&head, "Accept-Ranges"), Some("none"));
+        assert_eq!(testing::header(&head, "Content-Length"), Some("10"));
+        assert_eq!(testing::body(&head), "");
+
+        let get = testing::exchange(addr, request("GET", "If-Range: \"stale-etag\"\r\n").as_bytes()).await;
+        assert_eq!(testing::status(&get), 200);
+        assert_eq!(testing::body(&get), "0123456789");
+    }
+
+    #[tokio::test]
+    async fn creating_a_user_without_a_name_is_422_with_field_errors() {
+        let fixture = Fixture::new();
+        let api_tokens = HashMap::from([("admin-token".to_string(), Role::Admin)]);
+        let handler = fixture.handler(SecurityConfig { api_tokens, ..SecurityConfig::default() });
+
+        let auth = [("Authorization", "Bearer admin-token")];
+        let response = send(&handler, Method::POST, "/api/users", &auth, br#"{"email": "dana@example.com"}"#).await;
+        assert_eq!(response.status_code(), StatusCode::UnprocessableEntity);
+        let body = json(response).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["errors"], serde_json::json!([{ "field": "name", "message": "is required" }]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_public_path_returns_a_typed_error_for_each_failure() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let fixture = Fixture::new()
+            .file("public/a/b/c.html", "nested")
+            .file("public/locked/page.html", "private")
+            .file("secret.html", "hidden");
+        let public = fixture.root.join("public");
+        symlink(&fixture.root, public.join("escape")).unwrap();
+        symlink("loop", public.join("loop")).unwrap();
+        let handler = WebsiteHandler::new(public.clone(), SecurityConfig { follow_symlinks: true, ..SecurityConfig::default() });
+
+        let nested = [("/a/b/c.html", "a/b/c.html"), ("/a//b/c.html", "a/b/c.html"), ("/a/b/", "a/b")];
+        for (path, resolved) in nested {
+            assert_eq!(handler.resolve_public_path(path).unwrap(), public.join(resolved), "{}", path);
+        }
+
+        let traversals = [
+            "/../secret.html",
+            "/a/../../secret.html",
+            "/a/b/../../../secret.html",
+            "/%2e%2e/secret.html",
+            "/a/%2E%2E/%2e%2e/secret.html",
+            "/..%2fsecret.html",
+            "/a/b/c.html%00.png",
+            "/etc/passwd",
+            // Inside the root by name, outside once the symlink is resolved
+            "/escape/secret.html",
+        ];
+        for path in traversals {
+            assert!(matches!(handler.resolve_public_path(path), Err(PathError::Traversal)), "{}", path);
+        }
+
+        // Absolute paths are taken relative to the root, never from the filesystem root
+        assert_eq!(handler.resolve_public_path("//a/b/c.html").unwrap(), public.join("a/b/c.html"));
+        for path in ["/usr/share/missing.html", "//usr/share/missing.html", "/a/b/missing.html"] {
+            assert!(matches!(handler.resolve_public_path(path), Err(PathError::NotFound)), "{}", path);
+        }
+
+        assert!(matches!(handler.resolve_public_path("/loop"), Err(PathError::Io(_))));
+
+        let locked = public.join("locked");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // Privileged users read through permission bits, leaving nothing to deny
+        if fs::read_dir(&locked).is_err() {
+            assert!(matches!(handler.resolve_public_path("/locked/page.html"), Err(PathError::Forbidden)));
+        }
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn the_root_with_a_query_serves_the_index() {
+        let fixture = Fixture::new().file("index.html", "home");
+        let handler = fixture.handler(SecurityConfig::default());
+
+        let request = Request::for_test(Method::GET, "/?x=1&page=2", &[("Host", "localhost:8080")], b"", CLIENT.parse().unwrap());
+        assert_eq!(request.path(), "/");
+        assert_eq!(request.query_string().and_then(|query| query.first("x")), Some("1"));
+
+        let response = handler.handle_request(&request, CLIENT.parse().unwrap()).await;
+        assert_eq!(response.status_code(), StatusCode::Ok);
+        assert_eq!(response.body(), b"home");
+        assert_eq!(get(&handler, "/?").await.body(), b"home");
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn disabled_api_routes_fall_through_to_static_files() {
+        let fixture = Fixture::new().file("api/info.json", "{\"static\": true}");
+        let enabled = fixture.handler(SecurityConfig::default());
+        assert_eq!(json(get(&enabled, "/api/ping").await).await["message"], "pong");
+
+        let handler = fixture.handler(SecurityConfig { enable_api_routes: false, ..SecurityConfig::default() });
+        let response = get(&handler, "/api/ping").await;
+        assert_eq!(response.status_code(), StatusCode::NotFound);
+        assert!(!String::from_utf8_lossy(response.body()).contains("pong"));
+        assert_eq!(get(&handler, "/api/info.json").await.body(), b"{\"static\": true}");
+    }
+
+    #[tokio::test]
+    async fn rate_limit_changes_apply_to_the_next_request() {
+        let fixture = Fixture::new();
+        let api_tokens = HashMap::from([("admin-token".to_string(), Role::Admin)]);
+        let config = SecurityConfig { rate_limit_requests: 2, api_tokens, ..SecurityConfig::default() };
+        let stack = fixture.handler(config).with_default_middleware();
+        // Each operator call comes from its own address so none of them is throttled by the limit under test
+        let operators = std::cell::Cell::new(0);
+        let set_limit = |body: &'static str, token: &'static str| {
+            let stack = &stack;
+            operators.set(operators.get() + 1);
+            let operator: SocketAddr = format!("192.0.2.{}:4000", operators.get()).parse().unwrap();
+            async move {
+                let headers = [("Host", "localhost:8080"), ("Authorization", token), ("Content-Type", "application/json")];
+                let request = Request::for_test(Method::POST, "/admin/rate-limit", &headers, body.as_bytes(), operator);
+                stack.handle_request(&request, operator).await
+            }
+        };
+
+        for _ in 0..2 {
+            assert_eq!(get(&stack, "/api/ping").await.status_code(), StatusCode::Ok);
+        }
+        assert_eq!(get(&stack, "/api/ping").await.status_code(), StatusCode::TooManyRequests);
+
+        let anonymous = set_limit(r#"{"requests": 5, "window_secs": 60}"#, "").await;
+        assert_eq!(anonymous.status_code(), StatusCode::Unauthorized);
+        let invalid = set_limit(r#"{"requests": 0, "window_secs": 60}"#, "Bearer admin-token").await;
+        assert_eq!(invalid.status_code(), StatusCode::UnprocessableEntity);
+        assert_eq!(get(&stack, "/api/ping").await.status_code(), StatusCode::TooManyRequests);
+
+        let raised = set_limit(r#"{"requests": 3, "window_secs": 60}"#, "Bearer admin-token").await;
+        assert_eq!(raised.status_code(), StatusCode::Ok);
+        assert_eq!(get(&stack, "/api/ping").await.status_code(), StatusCode::Ok);
+        assert_eq!(get(&stack, "/api/ping").await.status_code(), StatusCode::TooManyRequests);
+
+        let lowered = set_limit(r#"{"requests": 1, "window_secs": 60}"#, "Bearer admin-token").await;
+        assert_eq!(lowered.status_code(), StatusCode::Ok);
+        assert_eq!(get(&stack, "/api/ping").await.status_code(), StatusCode::TooManyRequests);
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn dev_mode_reports_handling_time_in_server_timing() {
+        let fixture = Fixture::new().file("page.html", "page");
+        let duration = |timing: &str, metric: &str| -> f64 {
+            let value = timing.strip_prefix(metric).and_then(|rest| rest.strip_prefix(";dur=")).expect("no duration");
+            value.parse().expect("duration is not a number")
+        };
+
+        let dev = fixture.handler(SecurityConfig { dev_mode: true, ..SecurityConfig::default() });
+        let api = get(&dev, "/api/ping").await;
+        assert!(duration(api.header("Server-Timing").unwrap(), "total") >= 0.0);
+        // Static files also time the file read; it is reported first
+        let page = get(&dev, "/page.html").await;
+        assert!(duration(page.header("Server-Timing").unwrap(), "file") >= 0.0);
+
+        let production = fixture.handler(SecurityConfig::default());
+        assert_eq!(get(&production, "/api/ping").await.header("Server-Timing"), None);
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn uploaded_files_are_saved_and_served_back() {
+        let fixture = Fixture::new();
+        let upload_dir = fixture.root.join("uploads");
+        fs::create_dir_all(&upload_dir).unwrap();
+        let api_tokens = HashMap::from([("user-token".to_string(), Role::User)]);
+        let handler = fixture.handler(SecurityConfig { api_tokens, upload_dir: Some(upload_dir.clone()), ..SecurityConfig::default() });
+        let upload = |filename: &str, contents: &str| {
+            let body = format!(
+                "--b0und\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: text/plain\r\n\r\n{}\r\n--b0und--\r\n",
+                filename, contents,
+            );
+            let handler = &handler;
+            async move {
+                let headers = [("Authorization", "Bearer user-token"), ("Content-Type", "multipart/form-data; boundary=b0und")];
+                send(handler, Method::POST, "/api/upload", &headers, body.as_bytes()).await
+            }
+        };
+
+        let saved = upload("notes.txt", "uploaded text").await;
+        assert_eq!(saved.status_code(), StatusCode::Created);
+        assert_eq!(json(saved).await["data"]["size"], 13);
+        assert_eq!(fs::read_to_string(upload_dir.join("notes.txt")).unwrap(), "uploaded text");
+        assert_eq!(get(&handler, "/uploads/notes.txt").await.body(), b"uploaded text");
+
+        assert_eq!(upload("notes.txt", "replacement").await.status_code(), StatusCode::Conflict);
+        assert_eq!(upload("setup.exe", "MZ").await.status_code(), StatusCode::UnprocessableEntity);
+        assert_eq!(upload("../escape.txt", "out").await.status_code(), StatusCode::UnprocessableEntity);
+        assert!(!fixture.root.join("escape.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn form_posts_are_echoed_as_decoded_fields() {
+        let fixture = Fixture::new();
+        let handler = fixture.handler(SecurityConfig::default());
+
+        let form = [("Content-Type", "application/x-www-form-urlencoded; charset=UTF-8")];
+        let response = send(&handler, Method::POST, "/api/form", &form, b"a=1&b=hello+world").await;
+        assert_eq!(response.status_code(), StatusCode::Ok);
+        assert_eq!(json(response).await["data"], serde_json::json!({ "a": ["1"], "b": ["hello world"] }));
+
+        let not_a_form = send(&handler, Method::POST, "/api/form", &[("Content-Type", "application/json")], b"{}").await;
+        assert_eq!(not_a_form.status_code(), StatusCode::BadRequest);
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn clean_urls_serve_the_html_twin_of_an_extensionless_path() {
+        let fixture = Fixture::new().file("about.html", "about us").file("docs/intro.html", "intro");
+        let handler = fixture.handler(SecurityConfig { clean_urls: true, ..SecurityConfig::default() });
+
+        let about = get(&handler, "/about").await;
+        assert_eq!(about.status_code(), StatusCode::Ok);
+        assert_eq!(about.body(), b"about us");
+        assert_eq!(get(&handler, "/docs/intro").await.body(), b"intro");
+        assert_eq!(get(&handler, "/contact").await.status_code(), StatusCode::NotFound);
+        // Only extensionless paths are retried, and the twin is still checked like any other path
+        assert_eq!(get(&handler, "/about.txt").await.status_code(), StatusCode::NotFound);
+        assert_eq!(get(&handler, "/../about").await.status_code(), StatusCode::NotFound);
+
+        let off = fixture.handler(SecurityConfig::default());
+        assert_eq!(get(&off, "/about").await.status_code(), StatusCode::NotFound);
+    }
+
+    #[cfg(not(feature = "embedded"))]
+    #[tokio::test]
+    async fn directories_without_a_slash_are_redirected_to_it() {
+        let fixture = Fixture::new().file("docs/index.html", "docs home").file("notes.html", "notes");
+        let handler = fixture.handler(SecurityConfig {
+            directory_slash_redirect: true,
+            trailing_slash: TrailingSlash::Redirect,
+            ..SecurityConfig::default()
+        });
+
+        let moved = get(&handler, "/docs").await;
+        assert_eq!(moved.status_code(), StatusCode::MovedPermanently);
+        assert_eq!(moved.header("Location"), Some("/docs/"));
+        assert_eq!(get(&handler, "/docs?page=2").await.header("Location"), Some("/docs/?page=2"));
+        // The slashed form is served rather than bounced back by the trailing-slash redirect
+        assert_eq!(get(&handler, "/docs/").await.body(), b"docs home");
+        // Files and missing paths are left alone
+        assert_eq!(get(&handler, "/notes.html").await.status_code(), StatusCode::Ok);
+        assert_eq!(get(&handler, "/missing").await.status_code(), StatusCode::NotFound);
+
+        let off = fixture.handler(SecurityConfig::default());
+        assert_ne!(get(&off, "/docs").await.status_code(), StatusCode::MovedPermanently);
+    }
+}
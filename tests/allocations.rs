@@ -0,0 +1,85 @@
+// Keeps the per-request allocation counts that benches/hot_path.rs reports from creeping back up.
+// Everything in this binary is counted, so it holds a single test.
+
+use rust_server::http::response::RenderedSecurityHeaders;
+use rust_server::http::{Request, SecurityHeaders, SendOptions};
+use rust_server::security::SecurityConfig;
+use rust_server::server::Handler;
+use rust_server::website_handler::WebsiteHandler;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// Measured at 5 and 26 when the ceilings were set; serializing the responses alone took 27 and 29
+// before the hot-path work and 5 and 6 after it
+const PING_CEILING: f64 = 8.0;
+const STATIC_CEILING: f64 = 32.0;
+
+#[test]
+fn ping_and_static_requests_stay_under_their_allocation_ceilings() {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let public_path = std::fs::canonicalize(concat!(env!("CARGO_MANIFEST_DIR"), "/public")).unwrap();
+    // The limiter still runs for every request; the short window keeps one client under it
+    let config = SecurityConfig {
+        rate_limit_requests: usize::MAX,
+        rate_limit_window: Duration::from_millis(1),
+        ..SecurityConfig::default()
+    };
+    let handler = WebsiteHandler::new(public_path, config).with_default_middleware();
+    let security_headers = Arc::new(RenderedSecurityHeaders::new(&SecurityHeaders::default()));
+    let client: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+    let mut sink = Vec::with_capacity(4096);
+
+    let mut per_request = |raw: &[u8]| {
+        let serve = |sink: &mut Vec<u8>| {
+            let request = Request::try_from(raw).unwrap();
+            let options = SendOptions::new(request.version(), request.keep_alive(), false);
+            sink.clear();
+            runtime.block_on(async {
+                let response = handler.handle_request(&request, client).await
+                    .with_request_id("00000000-000000000000")
+                    .with_default_security_headers(&security_headers);
+                response.send(sink, options).await
+            }).unwrap();
+        };
+        serve(&mut sink);
+        assert!(sink.starts_with(b"HTTP/1.1 200 OK\r\n"), "{}", String::from_utf8_lossy(&sink));
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        for _ in 0..1000 {
+            serve(&mut sink);
+        }
+        (ALLOCATIONS.load(Ordering::Relaxed) - before) as f64 / 1000.0
+    };
+
+    let ping = per_request(b"GET /api/ping HTTP/1.1\r\nHost: localhost:8080\r\nUser-Agent: bench\r\nAccept: */*\r\n\r\n");
+    let css = per_request(b"GET /style.css HTTP/1.1\r\nHost: localhost:8080\r\nUser-Agent: bench\r\nAccept: text/css\r\n\r\n");
+    println!("allocations per request: /api/ping {:.1} (ceiling {}), /style.css {:.1} (ceiling {})", ping, PING_CEILING, css, STATIC_CEILING);
+    assert!(ping <= PING_CEILING, "/api/ping made {:.1} allocations per request", ping);
+    assert!(css <= STATIC_CEILING, "/style.css made {:.1} allocations per request", css);
+}